@@ -1,11 +1,25 @@
-use std::path::PathBuf;
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
 
 use anyhow::Context;
 use clap::{Parser, Subcommand};
 use tokio::runtime::Runtime;
 
+use av_core::ipc::{self, Request, Response, UpdateStatus};
+use av_core::rules::{self, Expectation, Severity};
+use av_core::signatures::{AbAssignment, RuleSet, ShadowHits};
 use av_core::{Scanner, ScannerConfig};
+use av_quarantine::kem::QuarantineSecretKey;
+use av_quarantine::keys::{KeyStore, KEY_DIR};
 use av_quarantine::{QuarantineConfig, QuarantineManager};
+use av_signatures::build;
+use av_signatures::crypto::{SignatureAlgorithm, SigningKey};
+use av_signatures::store::{Generation, Installed};
+use av_signatures::{SourcesFailed, UpdateConfig, Updater};
+
+const SIGNATURES_CONFIG: &str = "/etc/charmedwoa-av/signatures.toml";
+const DAEMON_CONFIG: &str = "/etc/charmedwoa-av/daemon.toml";
 
 #[derive(Parser, Debug)]
 #[command(author, version, about = "CharmedWOA ARM64 Antivirus CLI", propagate_version = true)]
@@ -22,8 +36,26 @@ struct Cli {
 enum Commands {
     Scan { path: PathBuf },
     Realtime { state: Toggle },
-    Quarantine { command: QuarantineCmd },
-    Signatures { command: SignatureCmd },
+    Quarantine {
+        #[command(subcommand)]
+        command: QuarantineCmd,
+    },
+    Signatures {
+        /// Update sources and cache settings.
+        #[arg(long, default_value = SIGNATURES_CONFIG)]
+        config: PathBuf,
+        /// Signature cache directory, overriding the configured one.
+        #[arg(long)]
+        cache_dir: Option<PathBuf>,
+        #[command(subcommand)]
+        command: SignatureCmd,
+    },
+    /// Check, test and precompile YARA rule files.
+    Rules {
+        #[command(subcommand)]
+        command: RulesCmd,
+    },
+    /// Counters reported by the running daemon.
     Metrics,
 }
 
@@ -36,47 +68,201 @@ enum Toggle {
 #[derive(Subcommand, Debug)]
 enum QuarantineCmd {
     List,
-    Restore { id: String, destination: PathBuf },
+    Restore {
+        id: String,
+        destination: PathBuf,
+        /// Secret key file to restore with, instead of the one the item's
+        /// key id names in the key store.
+        #[arg(long)]
+        key: Option<PathBuf>,
+    },
+    /// Versioned quarantine key pairs.
+    Keys {
+        #[arg(long, default_value = KEY_DIR)]
+        dir: PathBuf,
+        #[command(subcommand)]
+        command: QuarantineKeyCmd,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum QuarantineKeyCmd {
+    /// Generate the first key pair unless one exists; run before the daemon starts.
+    Init,
+    /// Generate a new key pair, re-wrap every item to it and delete the older ones.
+    Rotate,
+    /// Retained key versions and the one new items are wrapped to.
+    List,
 }
 
 #[derive(Subcommand, Debug)]
 enum SignatureCmd {
+    /// Fetch and install new bundles from every configured source now.
     Update,
+    /// Re-activate an earlier cache generation, e.g. after false positives.
+    Rollback {
+        /// Generation id; defaults to the one before the active generation.
+        #[arg(long)]
+        to: Option<u64>,
+    },
+    /// List cached generations.
+    Generations,
+    /// Active generation, update freshness and rule counts per lifecycle state.
+    Status,
+    /// Verify and install a signed bundle from a file, without network access.
+    Import {
+        file: PathBuf,
+        /// Update source the bundle was published by.
+        #[arg(long, default_value = "default")]
+        source: String,
+    },
+    /// Download a source's signed bundle to a file for `signatures import`.
+    Export {
+        output: PathBuf,
+        #[arg(long, default_value = "default")]
+        source: String,
+    },
+    /// Generate a signing key and print its public half for `[[sources.keys]]`.
+    Keygen {
+        output: PathBuf,
+        #[arg(long, default_value = "ed25519")]
+        algorithm: SignatureAlgorithm,
+    },
+    /// Package YARA rule files into an unsigned bundle for `signatures sign`.
+    Build {
+        /// Rule files, or directories of `.yar`/`.yara` files.
+        #[arg(required = true)]
+        rules: Vec<PathBuf>,
+        #[arg(long)]
+        bundle_version: semver::Version,
+        /// Days until the bundle expires; republish before then.
+        #[arg(long, default_value_t = 90)]
+        valid_days: i64,
+        #[arg(long, short)]
+        output: PathBuf,
+    },
+    /// Sign a bundle as-is with one or more keys from `signatures keygen`.
+    Sign {
+        bundle: PathBuf,
+        #[arg(long = "key", required = true)]
+        keys: Vec<PathBuf>,
+        #[arg(long, short)]
+        output: PathBuf,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum RulesCmd {
+    /// Check syntax, required meta fields and regexes likely to slow scanning.
+    Lint {
+        /// Rule files, or directories of `.yar`/`.yara` files.
+        #[arg(required = true)]
+        rules: Vec<PathBuf>,
+    },
+    /// Report which corpus files each rule matches, checked against the
+    /// corpus's `expected.json` annotations.
+    Test { rules: PathBuf, corpus: PathBuf },
+    /// Compile rules into a precompiled rule cache.
+    Compile {
+        #[arg(required = true)]
+        rules: Vec<PathBuf>,
+        #[arg(long, short)]
+        output: PathBuf,
+    },
 }
 
 fn main() -> anyhow::Result<()> {
-    let cli = Cli::parse();
+    run(Cli::parse(), &mut std::io::stdout().lock())
+}
+
+/// Run one command, writing its report to `out`.
+fn run(cli: Cli, out: &mut dyn Write) -> anyhow::Result<()> {
     let rt = Runtime::new()?;
     match cli.command {
-        Commands::Scan { path } => run_scan(&rt, path, cli.json),
-        Commands::Realtime { state } => set_realtime(state),
-        Commands::Quarantine { command } => run_quarantine(command, cli.json),
-        Commands::Signatures { command } => run_signatures(&rt, command),
-        Commands::Metrics => show_metrics(cli.json),
+        Commands::Scan { path } => run_scan(&rt, path, cli.json, out),
+        Commands::Realtime { state } => set_realtime(state, out),
+        Commands::Quarantine { command } => run_quarantine(command, cli.json, out),
+        Commands::Signatures {
+            config,
+            cache_dir,
+            command,
+        } => run_signatures(&rt, &config, cache_dir, command, cli.json, out),
+        Commands::Rules { command } => run_rules(command, cli.json, out),
+        Commands::Metrics => show_metrics(cli.json, out),
     }
 }
 
-fn run_scan(rt: &Runtime, path: PathBuf, json: bool) -> anyhow::Result<()> {
+/// The parts of the daemon configuration `signatures status` reports on.
+#[derive(serde::Deserialize, Default)]
+#[serde(default)]
+struct DaemonView {
+    scanner: ScannerConfig,
+    scheduler: SchedulerView,
+    ipc: IpcView,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(default)]
+struct IpcView {
+    socket_path: PathBuf,
+}
+
+impl Default for IpcView {
+    fn default() -> Self {
+        Self {
+            socket_path: PathBuf::from(ipc::DEFAULT_SOCKET),
+        }
+    }
+}
+
+#[derive(serde::Deserialize)]
+#[serde(default)]
+struct SchedulerView {
+    shadow_hits_path: PathBuf,
+}
+
+impl Default for SchedulerView {
+    fn default() -> Self {
+        Self {
+            shadow_hits_path: PathBuf::from("/var/lib/av/state/shadow-hits.json"),
+        }
+    }
+}
+
+/// Read the daemon configuration the same way the daemon does,
+/// honouring `AV_DAEMON_CONFIG`.
+fn load_daemon_view() -> anyhow::Result<DaemonView> {
+    let path = std::env::var_os("AV_DAEMON_CONFIG")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(DAEMON_CONFIG));
+    let settings = config::Config::builder()
+        .add_source(config::File::from(path.as_path()).required(false))
+        .build()?;
+    settings
+        .try_deserialize()
+        .with_context(|| format!("reading {}", path.display()))
+}
+
+fn run_scan(rt: &Runtime, path: PathBuf, json: bool, out: &mut dyn Write) -> anyhow::Result<()> {
     let cfg = ScannerConfig::default();
     let scanner = Scanner::new(cfg)?;
     let outcome = rt.block_on(scanner.scan_path(path))?;
     if json {
-        println!("{}", serde_json::to_string_pretty(&outcome)?);
+        writeln!(out, "{}", serde_json::to_string_pretty(&outcome)?)?;
     } else {
-        println!("Result: {:?}", outcome.recommended_action);
-        println!("Signatures: {}", outcome.signatures.len());
-        println!("Score: {:.3}", outcome.heuristic_score.0);
+        writeln!(out, "Result: {:?}", outcome.recommended_action)?;
+        writeln!(out, "Signatures: {}", outcome.signatures.len())?;
+        writeln!(out, "Score: {:.3}", outcome.heuristic_score.0)?;
     }
     Ok(())
 }
 
-fn set_realtime(state: Toggle) -> anyhow::Result<()> {
-    println!("Realtime mode set to {:?} (placeholder)", state);
+fn set_realtime(state: Toggle, out: &mut dyn Write) -> anyhow::Result<()> {
+    writeln!(out, "Realtime mode set to {:?} (placeholder)", state)?;
     Ok(())
 }
 
-fn run_quarantine(cmd: QuarantineCmd, json: bool) -> anyhow::Result<()> {
-    let manager = QuarantineManager::new(QuarantineConfig::default())?;
+fn run_quarantine(cmd: QuarantineCmd, json: bool, out: &mut dyn Write) -> anyhow::Result<()> {
     match cmd {
         QuarantineCmd::List => {
             let entries = std::fs::read_dir("/var/lib/av/quarantine")?
@@ -84,38 +270,683 @@ fn run_quarantine(cmd: QuarantineCmd, json: bool) -> anyhow::Result<()> {
                 .filter(|entry| entry.path().extension().map(|ext| ext == "json").unwrap_or(false))
                 .collect::<Vec<_>>();
             if json {
-                println!("{}", serde_json::to_string_pretty(&entries.len())?);
+                writeln!(out, "{}", serde_json::to_string_pretty(&entries.len())?)?;
             } else {
-                println!("{} items in quarantine", entries.len());
+                writeln!(out, "{} items in quarantine", entries.len())?;
             }
         }
-        QuarantineCmd::Restore { id, destination } => {
+        QuarantineCmd::Restore { id, destination, key } => {
             let metadata_path = format!("/var/lib/av/quarantine/{}.json", id);
             let record: av_quarantine::QuarantineRecord = serde_json::from_slice(&std::fs::read(metadata_path)?)?;
-            manager.restore(&record, &destination)?;
-            println!("Restored {}", id);
+            let Some(wrapped) = &record.key else {
+                anyhow::bail!(
+                    "item {id} was stored under the all-zero quarantine key; run `quarantine keys rotate` to migrate it"
+                );
+            };
+            let secret_key = match key {
+                Some(path) => QuarantineSecretKey::load(&path)?,
+                None => KeyStore::new(KEY_DIR).secret_key(&wrapped.key_id)?,
+            };
+            let manager = QuarantineManager::new(QuarantineConfig::default())?;
+            manager.restore(&record, &secret_key, &destination)?;
+            writeln!(out, "Restored {}", id)?;
         }
+        QuarantineCmd::Keys { dir, command } => run_quarantine_keys(&KeyStore::new(dir), command, json, out)?,
     }
     Ok(())
 }
 
-fn run_signatures(rt: &Runtime, command: SignatureCmd) -> anyhow::Result<()> {
+fn run_quarantine_keys(store: &KeyStore, cmd: QuarantineKeyCmd, json: bool, out: &mut dyn Write) -> anyhow::Result<()> {
+    match cmd {
+        QuarantineKeyCmd::Init => {
+            let generated = store.init()?;
+            if json {
+                writeln!(out, "{}", serde_json::json!({ "generated": generated, "current": store.recipient()?.id }))?;
+            } else if let Some(id) = generated {
+                writeln!(out, "Generated quarantine key {id}")?;
+            } else {
+                writeln!(out, "Quarantine key {} already present", store.recipient()?.id)?;
+            }
+        }
+        QuarantineKeyCmd::Rotate => {
+            let manager = QuarantineManager::new(QuarantineConfig::default())?;
+            let rotation = manager.rotate(store)?;
+            if json {
+                writeln!(out, "{}", serde_json::to_string_pretty(&rotation)?)?;
+            } else {
+                writeln!(out, "Rotated to quarantine key {}", rotation.key_id)?;
+                writeln!(out, "Re-wrapped {} items; retired versions {:?}", rotation.rewrapped, rotation.retired)?;
+                if !rotation.migrated.is_empty() {
+                    writeln!(
+                        out,
+                        "Migrated {} items off the all-zero key: {}",
+                        rotation.migrated.len(),
+                        rotation.migrated.join(", ")
+                    )?;
+                }
+            }
+        }
+        QuarantineKeyCmd::List => {
+            let versions = store.versions()?;
+            let current = store.recipient()?.id;
+            if json {
+                writeln!(out, "{}", serde_json::json!({ "versions": versions, "current": current }))?;
+            } else {
+                writeln!(out, "Key versions: {:?}", versions)?;
+                writeln!(out, "New items are wrapped to {current}")?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn run_signatures(
+    rt: &Runtime,
+    config: &Path,
+    cache_dir: Option<PathBuf>,
+    command: SignatureCmd,
+    json: bool,
+    out: &mut dyn Write,
+) -> anyhow::Result<()> {
+    let settings = config::Config::builder()
+        .add_source(config::File::from(config).required(false))
+        .build()?;
+    let mut cfg: UpdateConfig = settings
+        .try_deserialize()
+        .with_context(|| format!("reading {}", config.display()))?;
+    if let Some(cache_dir) = cache_dir {
+        cfg.cache_dir = cache_dir;
+    }
+    let store = cfg.store();
     match command {
         SignatureCmd::Update => {
-            rt.block_on(async {
-                println!("Updating signatures (placeholder)");
-                Ok::<(), anyhow::Error>(())
-            })?;
+            let result = rt.block_on(Updater::new(cfg)?.update());
+            // Sources that verified are installed even when others failed.
+            let partial = result.as_ref().err().and_then(|err| err.downcast_ref::<SourcesFailed>());
+            if let Some(failed) = partial.filter(|failed| failed.installed != Installed::Unchanged) {
+                report_installed(&failed.installed, json, out)?;
+            }
+            let installed = result.context("signature update failed")?;
+            report_installed(&installed, json, out)?;
+        }
+        SignatureCmd::Rollback { to } => {
+            let generation = store.rollback(to).context("signature rollback failed")?;
+            if json {
+                writeln!(out, "{}", serde_json::to_string_pretty(&generation)?)?;
+            } else {
+                writeln!(out, "Rolled back to generation {}", describe(&generation))?;
+            }
+        }
+        SignatureCmd::Generations => {
+            let generations = store.generations()?;
+            let current = store.current()?.map(|g| g.id);
+            if json {
+                writeln!(
+                    out,
+                    "{}",
+                    serde_json::json!({ "current": current, "generations": generations })
+                )?;
+            } else {
+                for generation in &generations {
+                    let marker = if Some(generation.id) == current { "*" } else { " " };
+                    writeln!(out, "{marker} {}", describe(generation))?;
+                }
+            }
+        }
+        SignatureCmd::Status => {
+            let current = store.current()?;
+            let health = Updater::new(cfg.clone())?.health()?;
+            let merged = store.rules()?.unwrap_or_default();
+            let daemon = load_daemon_view()?;
+            let ab = AbAssignment::for_this_host(daemon.scanner.ab_rollout.clone()).unwrap_or_else(|_| {
+                // Without a machine id only the baseline bucket is evaluated.
+                AbAssignment::new("", BTreeMap::new())
+            });
+            let rules = RuleSet::load(merged.rules.into_values(), chrono::Utc::now(), &ab);
+            let shadow_hits = match daemon_shadow_hits(&daemon.ipc.socket_path) {
+                Some(hits) => hits,
+                None => ShadowHits::load(&daemon.scheduler.shadow_hits_path)?.rules,
+            };
+            let polling = daemon_update_status(&daemon.ipc.socket_path);
+            if json {
+                writeln!(
+                    out,
+                    "{}",
+                    serde_json::json!({
+                        "generation": current,
+                        "health": health,
+                        "daemon": polling,
+                        "loaded": rules.rules.len(),
+                        "shadow": rules.shadow,
+                        "rules": rules.summary,
+                        "shadow_hits": shadow_hits,
+                    })
+                )?;
+            } else {
+                match &current {
+                    Some(generation) => writeln!(out, "Generation: {}", describe(generation))?,
+                    None => writeln!(out, "Generation: none installed")?,
+                }
+                writeln!(out, "Updates: {health}")?;
+                match &polling {
+                    Some(status) if !status.enabled => writeln!(out, "Daemon polling: disabled")?,
+                    Some(status) => {
+                        let when = |at: Option<chrono::DateTime<chrono::Utc>>| {
+                            at.map_or("never".to_string(), |at| at.format("%Y-%m-%d %H:%M UTC").to_string())
+                        };
+                        writeln!(
+                            out,
+                            "Daemon polling: last success {}, next attempt {}",
+                            when(status.last_success),
+                            when(status.next_attempt)
+                        )?;
+                        if let Some(reason) = &status.last_failure {
+                            writeln!(
+                                out,
+                                "Last failure ({} in a row): {reason}",
+                                status.consecutive_failures
+                            )?;
+                        }
+                        if status.generation != current.as_ref().map(|g| g.id) {
+                            writeln!(out, "Daemon has not loaded the active generation yet")?;
+                        }
+                    }
+                    None => writeln!(out, "Daemon polling: daemon not reachable")?,
+                }
+                let states: Vec<String> = rules
+                    .summary
+                    .states
+                    .iter()
+                    .map(|(state, count)| format!("{state} {count}"))
+                    .collect();
+                writeln!(
+                    out,
+                    "Rules: {} loaded ({}), {} expired, {} in shadow mode, {} outside this host's A/B sample",
+                    rules.rules.len(),
+                    states.join(", "),
+                    rules.summary.expired,
+                    rules.shadow.len(),
+                    rules.summary.unsampled
+                )?;
+                for (rule, hits) in &shadow_hits {
+                    writeln!(out, "Shadow hits: {rule} {hits}")?;
+                }
+                if !rules.summary.expiring.is_empty() {
+                    writeln!(out, "Expiring soon: {}", rules.summary.expiring.join(", "))?;
+                }
+            }
+        }
+        SignatureCmd::Import { file, source } => {
+            let body = std::fs::read(&file).with_context(|| format!("reading {}", file.display()))?;
+            let installed = Updater::new(cfg)?
+                .import(&source, &body)
+                .context("signature import failed")?;
+            report_installed(&installed, json, out)?;
+        }
+        SignatureCmd::Export { output, source } => {
+            let body = rt
+                .block_on(Updater::new(cfg)?.export(&source))
+                .context("signature export failed")?;
+            std::fs::write(&output, &body).with_context(|| format!("writing {}", output.display()))?;
+            if !json {
+                writeln!(out, "Exported signed bundle of `{source}` to {}", output.display())?;
+            }
+        }
+        SignatureCmd::Keygen { output, algorithm } => {
+            let key = SigningKey::generate(algorithm)?;
+            key.save(&output)?;
+            let public = key.public_key();
+            if json {
+                writeln!(
+                    out,
+                    "{}",
+                    serde_json::json!({ "key_id": public.key_id(), "public_key": public })
+                )?;
+            } else {
+                let encoded = serde_json::to_value(&public)?;
+                writeln!(out, "Wrote {algorithm} signing key to {}", output.display())?;
+                writeln!(out, "Key id: {}", public.key_id())?;
+                writeln!(out, "\n[[sources.keys]]\nalgorithm = \"{algorithm}\"\nkey = {}", encoded["key"])?;
+            }
+        }
+        SignatureCmd::Build {
+            rules,
+            bundle_version,
+            valid_days,
+            output,
+        } => {
+            let issued_at = chrono::Utc::now();
+            let expires_at = issued_at + chrono::Duration::days(valid_days);
+            let bundle = build::build_bundle(&rules, bundle_version, issued_at, expires_at)?;
+            std::fs::write(&output, serde_json::to_vec_pretty(&bundle)?)
+                .with_context(|| format!("writing {}", output.display()))?;
+            if !json {
+                writeln!(
+                    out,
+                    "Built bundle {} with {} rule(s), expiring {} ({})",
+                    bundle.version,
+                    bundle.rules.len(),
+                    bundle.expires_at.format("%Y-%m-%d %H:%M UTC"),
+                    bundle.checksum
+                )?;
+            }
+        }
+        SignatureCmd::Sign { bundle, keys, output } => {
+            let payload = std::fs::read(&bundle).with_context(|| format!("reading {}", bundle.display()))?;
+            let keys = keys
+                .iter()
+                .map(|path| SigningKey::load(path))
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            let signers: Vec<&SigningKey> = keys.iter().collect();
+            let envelope = build::sign_bundle(payload, &signers)?;
+            std::fs::write(&output, serde_json::to_vec_pretty(&envelope)?)
+                .with_context(|| format!("writing {}", output.display()))?;
+            if !json {
+                let algorithms: Vec<String> = keys.iter().map(|key| key.algorithm.to_string()).collect();
+                writeln!(out, "Signed {} with {} to {}", bundle.display(), algorithms.join(", "), output.display())?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn run_rules(command: RulesCmd, json: bool, out: &mut dyn Write) -> anyhow::Result<()> {
+    match command {
+        RulesCmd::Lint { rules } => {
+            let diagnostics = rules::lint(&rules)?;
+            let errors = diagnostics.iter().filter(|d| d.severity == Severity::Error).count();
+            if json {
+                writeln!(out, "{}", serde_json::to_string_pretty(&diagnostics)?)?;
+            } else {
+                for diagnostic in &diagnostics {
+                    writeln!(out, "{diagnostic}")?;
+                }
+                writeln!(out, "{errors} error(s), {} warning(s)", diagnostics.len() - errors)?;
+            }
+            anyhow::ensure!(errors == 0, "rule lint failed");
         }
+        RulesCmd::Test { rules, corpus } => {
+            let report = rules::test_corpus(&[rules], &corpus)?;
+            if json {
+                writeln!(out, "{}", serde_json::to_string_pretty(&report)?)?;
+            } else {
+                for (rule, hits) in &report.hits {
+                    writeln!(out, "{rule}: {} file(s)", hits.len())?;
+                    for hit in hits {
+                        let note = match hit.expectation {
+                            Expectation::Expected => "expected",
+                            Expectation::Unexpected => "UNEXPECTED",
+                            Expectation::Unannotated => "unannotated",
+                        };
+                        writeln!(out, "  {} ({note})", hit.file.display())?;
+                    }
+                }
+                for missed in &report.missed {
+                    writeln!(out, "MISSED: {} did not match {}", missed.rule, missed.file.display())?;
+                }
+                writeln!(
+                    out,
+                    "Scanned {} file(s): {} unexpected match(es), {} missed",
+                    report.scanned,
+                    report.unexpected(),
+                    report.missed.len()
+                )?;
+            }
+            anyhow::ensure!(report.passed(), "corpus does not match its annotations");
+        }
+        RulesCmd::Compile { rules, output } => {
+            let files = rules::precompile(&rules, &output)?;
+            if !json {
+                writeln!(out, "Compiled {files} rule file(s) to {}", output.display())?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn report_installed(installed: &Installed, json: bool, out: &mut dyn Write) -> anyhow::Result<()> {
+    match installed {
+        Installed::Activated(generation) if json => writeln!(out, "{}", serde_json::to_string_pretty(generation)?)?,
+        Installed::Activated(generation) => writeln!(out, "Installed generation {}", describe(generation))?,
+        Installed::Held if json => writeln!(out, "{}", serde_json::json!({ "held": true }))?,
+        Installed::Held => writeln!(out, "Bundle matches a rolled-back generation; not reinstalled")?,
+        Installed::Unchanged if json => writeln!(out, "{}", serde_json::json!({ "unchanged": true }))?,
+        Installed::Unchanged => writeln!(out, "Already up to date")?,
     }
     Ok(())
 }
 
-fn show_metrics(json: bool) -> anyhow::Result<()> {
+fn describe(generation: &Generation) -> String {
+    let bundles: Vec<String> = generation
+        .bundles
+        .iter()
+        .map(|(source, version)| match generation.served_by.get(source) {
+            Some(url) => format!("{source} {version} from {url}"),
+            None => format!("{source} {version}"),
+        })
+        .collect();
+    format!(
+        "{} ({}; {})",
+        generation.id,
+        generation.created_at.format("%Y-%m-%d %H:%M UTC"),
+        bundles.join(", ")
+    )
+}
+
+fn show_metrics(json: bool, out: &mut dyn Write) -> anyhow::Result<()> {
+    let daemon = load_daemon_view()?;
+    let batch = match ipc::request(&daemon.ipc.socket_path, &Request::Metrics)? {
+        Response::Metrics(batch) => batch,
+        Response::Error(err) => anyhow::bail!("av-daemon: {err}"),
+        other => anyhow::bail!("unexpected reply from av-daemon: {other:?}"),
+    };
     if json {
-        println!("{}", serde_json::json!({"uptime": 0, "events": 0}));
+        writeln!(out, "{}", serde_json::to_string_pretty(&batch)?)?;
     } else {
-        println!("Uptime: 0s\nEvents processed: 0");
+        for point in &batch.points {
+            writeln!(out, "{} {}", point.name, point.value)?;
+        }
     }
     Ok(())
 }
+
+/// Update polling as reported by the running daemon, if it answers.
+fn daemon_update_status(socket: &Path) -> Option<UpdateStatus> {
+    match ipc::request(socket, &Request::UpdateStatus) {
+        Ok(Response::UpdateStatus(status)) => Some(status),
+        _ => None,
+    }
+}
+
+/// Live counts from a running daemon; the saved file lags by up to a tick.
+fn daemon_shadow_hits(socket: &Path) -> Option<BTreeMap<String, u64>> {
+    match ipc::request(socket, &Request::ShadowHits) {
+        Ok(Response::ShadowHits(hits)) => Some(hits),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::{BufRead, BufReader};
+    use std::net::TcpListener;
+
+    const RULE: &str = r#"
+rule InternalDropper : dropper
+{
+    meta:
+        description = "Dropper seen on build hosts"
+        author = "SOC"
+        ab_bucket = "canary"
+    strings:
+        $a = "stage2.bin"
+    condition:
+        $a
+}
+"#;
+
+    fn parse(args: &[&str]) -> Result<Cli, clap::Error> {
+        Cli::try_parse_from(std::iter::once("av-cli").chain(args.iter().copied()))
+    }
+
+    /// Run `av-cli <args>` and return its result with everything it printed.
+    fn run_cli(args: &[&str]) -> (anyhow::Result<()>, String) {
+        let mut out = Vec::new();
+        let result = run(parse(args).unwrap(), &mut out);
+        (result, String::from_utf8(out).unwrap())
+    }
+
+    fn path(path: &Path) -> &str {
+        path.to_str().unwrap()
+    }
+
+    /// A signing key, a one-source configuration trusting it and a bundle
+    /// signed with it per published version.
+    struct Publisher {
+        dir: tempfile::TempDir,
+        config: PathBuf,
+        public_key: serde_json::Value,
+    }
+
+    impl Publisher {
+        fn new() -> Self {
+            let dir = tempfile::tempdir().unwrap();
+            let key = dir.path().join("key.json");
+            let (result, output) = run_cli(&["--json", "signatures", "keygen", path(&key)]);
+            result.unwrap();
+            let printed: serde_json::Value = serde_json::from_str(&output).unwrap();
+            std::fs::write(dir.path().join("rules.yar"), RULE).unwrap();
+            let publisher = Self {
+                config: dir.path().join("signatures.toml"),
+                public_key: printed["public_key"].clone(),
+                dir,
+            };
+            publisher.configure(&[("default", "http://127.0.0.1:1/latest.json")]);
+            publisher
+        }
+
+        fn configure(&self, sources: &[(&str, &str)]) {
+            let mut toml = format!("cache_dir = {:?}\ngenerations = 3\n", self.dir.path().join("cache"));
+            for (name, url) in sources {
+                toml += &format!(
+                    "\n[[sources]]\nname = \"{name}\"\nurl = \"{url}\"\n\n[[sources.keys]]\nalgorithm = {}\nkey = {}\n",
+                    self.public_key["algorithm"], self.public_key["key"]
+                );
+            }
+            std::fs::write(&self.config, toml).unwrap();
+        }
+
+        /// Build and sign a bundle of the test rule with `signatures build` and `sign`.
+        fn publish(&self, version: &str) -> PathBuf {
+            let dir = self.dir.path();
+            let bundle = dir.join(format!("bundle-{version}.json"));
+            let signed = dir.join(format!("signed-{version}.json"));
+            let rules = dir.join("rules.yar");
+            let (result, output) = run_cli(&[
+                "signatures",
+                "build",
+                path(&rules),
+                "--bundle-version",
+                version,
+                "-o",
+                path(&bundle),
+            ]);
+            result.unwrap();
+            assert!(output.starts_with(&format!("Built bundle {version} with 1 rule(s)")), "{output}");
+            let key = dir.join("key.json");
+            let (result, _) = run_cli(&["signatures", "sign", path(&bundle), "--key", path(&key), "-o", path(&signed)]);
+            result.unwrap();
+            signed
+        }
+
+        fn signatures(&self, args: &[&str]) -> (anyhow::Result<()>, String) {
+            let mut full = vec!["--json", "signatures", "--config", path(&self.config)];
+            full.extend_from_slice(args);
+            run_cli(&full)
+        }
+    }
+
+    /// Serve `body` to every HTTP request until the test ends.
+    fn serve(body: Vec<u8>) -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut line = String::new();
+                while reader.read_line(&mut line).unwrap() > 0 && line != "\r\n" {
+                    line.clear();
+                }
+                let head = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                    body.len()
+                );
+                let _ = stream.write_all(head.as_bytes()).and_then(|()| stream.write_all(&body));
+            }
+        });
+        port
+    }
+
+    #[test]
+    fn parses_rules_commands() {
+        assert!(matches!(
+            parse(&["rules", "lint", "a.yar", "rules.d"]).unwrap().command,
+            Commands::Rules { command: RulesCmd::Lint { rules } } if rules.len() == 2
+        ));
+        assert!(matches!(
+            parse(&["rules", "test", "a.yar", "corpus"]).unwrap().command,
+            Commands::Rules { command: RulesCmd::Test { rules, corpus } }
+                if rules == Path::new("a.yar") && corpus == Path::new("corpus")
+        ));
+        assert!(matches!(
+            parse(&["rules", "compile", "a.yar", "-o", "rules.cache"]).unwrap().command,
+            Commands::Rules { command: RulesCmd::Compile { output, .. } } if output == Path::new("rules.cache")
+        ));
+        assert!(parse(&["rules", "lint"]).is_err());
+        assert!(parse(&["rules", "test", "a.yar"]).is_err());
+        assert!(parse(&["rules", "compile", "a.yar"]).is_err());
+    }
+
+    #[test]
+    fn parses_signatures_commands() {
+        let signatures = |args: &[&str]| match parse(args).unwrap().command {
+            Commands::Signatures { config, command, .. } => (config, command),
+            other => panic!("parsed as {other:?}"),
+        };
+        let (config, command) = signatures(&["signatures", "status"]);
+        assert_eq!(config, Path::new(SIGNATURES_CONFIG));
+        assert!(matches!(command, SignatureCmd::Status));
+        assert!(matches!(signatures(&["signatures", "generations"]).1, SignatureCmd::Generations));
+        assert!(matches!(signatures(&["signatures", "rollback"]).1, SignatureCmd::Rollback { to: None }));
+        assert!(matches!(signatures(&["signatures", "rollback", "--to", "3"]).1, SignatureCmd::Rollback { to: Some(3) }));
+        let (config, command) = signatures(&["signatures", "--config", "s.toml", "import", "b.json"]);
+        assert_eq!(config, Path::new("s.toml"));
+        assert!(matches!(command, SignatureCmd::Import { source, .. } if source == "default"));
+        assert!(matches!(
+            signatures(&["signatures", "export", "b.json", "--source", "vendor"]).1,
+            SignatureCmd::Export { source, .. } if source == "vendor"
+        ));
+        assert!(matches!(
+            signatures(&["signatures", "keygen", "key.json"]).1,
+            SignatureCmd::Keygen { algorithm: SignatureAlgorithm::Ed25519, .. }
+        ));
+        assert!(matches!(
+            signatures(&["signatures", "build", "rules.d", "--bundle-version", "1.2.0", "-o", "b.json"]).1,
+            SignatureCmd::Build { bundle_version, valid_days: 90, .. } if bundle_version == semver::Version::new(1, 2, 0)
+        ));
+        assert!(matches!(
+            signatures(&["signatures", "sign", "b.json", "--key", "a.json", "--key", "b.json", "-o", "s.json"]).1,
+            SignatureCmd::Sign { keys, .. } if keys.len() == 2
+        ));
+        assert!(parse(&["signatures", "keygen", "key.json", "--algorithm", "rsa"]).is_err());
+        assert!(parse(&["signatures", "build", "--bundle-version", "1.2.0", "-o", "b.json"]).is_err());
+        assert!(parse(&["signatures", "build", "rules.d", "--bundle-version", "one", "-o", "b.json"]).is_err());
+        assert!(parse(&["signatures", "sign", "b.json", "-o", "s.json"]).is_err());
+    }
+
+    #[test]
+    fn parses_quarantine_key_commands() {
+        let keys = |args: &[&str]| match parse(args).unwrap().command {
+            Commands::Quarantine {
+                command: QuarantineCmd::Keys { dir, command },
+            } => (dir, command),
+            other => panic!("parsed as {other:?}"),
+        };
+        let (dir, command) = keys(&["quarantine", "keys", "init"]);
+        assert_eq!(dir, Path::new(KEY_DIR));
+        assert!(matches!(command, QuarantineKeyCmd::Init));
+        let (dir, command) = keys(&["quarantine", "keys", "--dir", "/tmp/keys", "rotate"]);
+        assert_eq!(dir, Path::new("/tmp/keys"));
+        assert!(matches!(command, QuarantineKeyCmd::Rotate));
+        assert!(matches!(keys(&["quarantine", "keys", "list"]).1, QuarantineKeyCmd::List));
+        assert!(parse(&["quarantine", "keys"]).is_err());
+    }
+
+    #[test]
+    fn builds_signs_and_imports_a_bundle() {
+        let publisher = Publisher::new();
+        let signed = publisher.publish("1.0.0");
+
+        let (result, output) = publisher.signatures(&["import", path(&signed)]);
+        result.unwrap();
+        let generation: Generation = serde_json::from_str(&output).unwrap();
+        assert_eq!(generation.bundles["default"], semver::Version::new(1, 0, 0));
+
+        // The same release again changes nothing.
+        let (result, output) = publisher.signatures(&["import", path(&signed)]);
+        result.unwrap();
+        assert_eq!(output.trim(), r#"{"unchanged":true}"#);
+
+        // A source the configuration does not name is refused.
+        let (result, _) = publisher.signatures(&["import", path(&signed), "--source", "vendor"]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn rolls_back_to_the_previous_generation() {
+        let publisher = Publisher::new();
+        for version in ["1.0.0", "1.1.0"] {
+            let signed = publisher.publish(version);
+            publisher.signatures(&["import", path(&signed)]).0.unwrap();
+        }
+
+        let (result, output) = publisher.signatures(&["rollback"]);
+        result.unwrap();
+        let generation: Generation = serde_json::from_str(&output).unwrap();
+        assert_eq!(generation.bundles["default"], semver::Version::new(1, 0, 0));
+
+        let (result, output) = publisher.signatures(&["generations"]);
+        result.unwrap();
+        let listed: serde_json::Value = serde_json::from_str(&output).unwrap();
+        assert_eq!(listed["current"], generation.id);
+        assert_eq!(listed["generations"].as_array().unwrap().len(), 2);
+
+        // Nothing older is left to roll back to.
+        let (result, _) = publisher.signatures(&["rollback"]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn rules_lint_fails_on_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        let good = dir.path().join("good.yar");
+        std::fs::write(&good, RULE).unwrap();
+        let (result, output) = run_cli(&["rules", "lint", path(&good)]);
+        result.unwrap();
+        assert_eq!(output.trim(), "0 error(s), 0 warning(s)");
+
+        let bad = dir.path().join("bad.yar");
+        std::fs::write(&bad, "rule NoMeta { condition: true }").unwrap();
+        let (result, output) = run_cli(&["--json", "rules", "lint", path(&bad)]);
+        assert_eq!(result.unwrap_err().to_string(), "rule lint failed");
+        let diagnostics: Vec<serde_json::Value> = serde_json::from_str(&output).unwrap();
+        assert!(!diagnostics.is_empty());
+        assert!(diagnostics
+            .iter()
+            .all(|d| d["severity"] == "error" && d["rule"] == "NoMeta"));
+    }
+
+    #[test]
+    fn update_reports_installed_sources_before_failing() {
+        let publisher = Publisher::new();
+        let signed = std::fs::read(publisher.publish("1.0.0")).unwrap();
+        let port = serve(signed);
+        publisher.configure(&[
+            ("default", &format!("http://127.0.0.1:{port}/latest.json")),
+            ("down", "http://127.0.0.1:1/latest.json"),
+        ]);
+
+        let (result, output) = publisher.signatures(&["update"]);
+        let err = result.unwrap_err();
+        let failed = err.downcast_ref::<SourcesFailed>().expect("partial failure");
+        assert_eq!(failed.failures.len(), 1);
+        assert_eq!(failed.failures[0].0, "down");
+        assert!(format!("{err:#}").starts_with("signature update failed: source `down`"), "{err:#}");
+        let generation: Generation = serde_json::from_str(&output).unwrap();
+        assert_eq!(generation.bundles.keys().collect::<Vec<_>>(), ["default"]);
+        assert_eq!(generation.bundles["default"], semver::Version::new(1, 0, 0));
+    }
+}
@@ -0,0 +1,17 @@
+//! Serde adapter for byte strings carried as standard base64, for keys,
+//! signatures and wrapped secrets in JSON. Use with
+//! `#[serde(with = "av_core::base64_bytes")]`.
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use serde::{Deserialize, Deserializer, Serializer};
+
+pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&BASE64.encode(bytes))
+}
+
+/// Surrounding whitespace is ignored, e.g. from a key pasted into TOML.
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+    let text = String::deserialize(deserializer)?;
+    BASE64.decode(text.trim()).map_err(serde::de::Error::custom)
+}
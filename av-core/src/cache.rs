@@ -0,0 +1,145 @@
+//! Persistent scan cache keyed by path and file fingerprint.
+//!
+//! Incremental scans consult the cache to skip files that have not changed
+//! since their last scan. Any change to size, mtime, ctime or inode
+//! invalidates an entry, so a replaced or re-permissioned file is always
+//! rescanned.
+
+use std::collections::HashMap;
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{persist, RecommendedAction};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileFingerprint {
+    pub dev: u64,
+    pub ino: u64,
+    pub size: u64,
+    pub mtime: i64,
+    pub mtime_nsec: i64,
+    pub ctime: i64,
+    pub ctime_nsec: i64,
+}
+
+impl FileFingerprint {
+    pub fn from_metadata(meta: &fs::Metadata) -> Self {
+        Self {
+            dev: meta.dev(),
+            ino: meta.ino(),
+            size: meta.size(),
+            mtime: meta.mtime(),
+            mtime_nsec: meta.mtime_nsec(),
+            ctime: meta.ctime(),
+            ctime_nsec: meta.ctime_nsec(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CacheEntry {
+    pub fingerprint: FileFingerprint,
+    pub action: RecommendedAction,
+    pub scanned_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ScanCache {
+    entries: HashMap<PathBuf, CacheEntry>,
+    #[serde(skip)]
+    dirty: bool,
+}
+
+impl ScanCache {
+    /// Load a cache file. A missing file yields an empty cache.
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        match fs::read(path) {
+            Ok(bytes) => Ok(serde_json::from_slice(&bytes)?),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Persist via write-to-temp and rename so a crash never leaves a
+    /// truncated cache behind.
+    pub fn save(&mut self, path: &Path) -> anyhow::Result<()> {
+        persist::write_atomic(path, &serde_json::to_vec(self)?)?;
+        self.dirty = false;
+        Ok(())
+    }
+
+    /// Whether `path` was scanned and has not changed since.
+    pub fn is_fresh(&self, path: &Path, fingerprint: &FileFingerprint) -> bool {
+        self.entries
+            .get(path)
+            .map(|entry| entry.fingerprint == *fingerprint)
+            .unwrap_or(false)
+    }
+
+    pub fn get(&self, path: &Path) -> Option<&CacheEntry> {
+        self.entries.get(path)
+    }
+
+    pub fn record(&mut self, path: PathBuf, fingerprint: FileFingerprint, action: RecommendedAction) {
+        self.entries.insert(
+            path,
+            CacheEntry {
+                fingerprint,
+                action,
+                scanned_at: Utc::now(),
+            },
+        );
+        self.dirty = true;
+    }
+
+    /// Drop every entry, forcing the next incremental run to rescan
+    /// everything (e.g. after a rule update).
+    pub fn clear(&mut self) {
+        if !self.entries.is_empty() {
+            self.entries.clear();
+            self.dirty = true;
+        }
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn modified_files_are_not_fresh() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("sample.bin");
+        fs::write(&target, b"first").unwrap();
+
+        let mut cache = ScanCache::default();
+        let fp = FileFingerprint::from_metadata(&fs::metadata(&target).unwrap());
+        cache.record(target.clone(), fp, RecommendedAction::Allow);
+        assert!(cache.is_fresh(&target, &fp));
+
+        let cache_path = dir.path().join("state/cache.json");
+        cache.save(&cache_path).unwrap();
+        let reloaded = ScanCache::load(&cache_path).unwrap();
+        assert!(reloaded.is_fresh(&target, &fp));
+
+        fs::write(&target, b"second, longer").unwrap();
+        let changed = FileFingerprint::from_metadata(&fs::metadata(&target).unwrap());
+        assert!(!reloaded.is_fresh(&target, &changed));
+    }
+}
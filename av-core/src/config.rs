@@ -1,4 +1,5 @@
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::BTreeMap;
 use std::path::PathBuf;
 
 /// Top-level configuration for the scanning engine.
@@ -7,6 +8,7 @@ use std::path::PathBuf;
 /// workflows are disabled unless explicitly toggled by the user and
 /// confirmed through higher-level UI layers.
 #[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
 pub struct ScannerConfig {
     pub signature_sources: Vec<SignatureSource>,
     pub heuristic_threshold: f32,
@@ -14,6 +16,9 @@ pub struct ScannerConfig {
     pub max_scan_depth: usize,
     pub thread_pool_size: usize,
     pub enable_entropy_analysis: bool,
+    /// Fraction of hosts, from 0.0 to 1.0, that evaluate each non-baseline
+    /// A/B bucket in shadow mode. Unlisted buckets run nowhere.
+    pub ab_rollout: BTreeMap<String, f64>,
 }
 
 impl Default for ScannerConfig {
@@ -25,6 +30,7 @@ impl Default for ScannerConfig {
             max_scan_depth: 4,
             thread_pool_size: 4,
             enable_entropy_analysis: true,
+            ab_rollout: BTreeMap::new(),
         }
     }
 }
@@ -36,6 +42,12 @@ impl ScannerConfig {
     pub fn validate(&self) -> anyhow::Result<()> {
         anyhow::ensure!(self.heuristic_threshold >= 0.0 && self.heuristic_threshold <= 1.0);
         anyhow::ensure!(self.thread_pool_size >= 1 && self.thread_pool_size <= 32);
+        for (bucket, fraction) in &self.ab_rollout {
+            anyhow::ensure!(
+                (0.0..=1.0).contains(fraction),
+                "rollout of A/B bucket `{bucket}` must be between 0 and 1"
+            );
+        }
         Ok(())
     }
 }
@@ -45,6 +57,22 @@ impl ScannerConfig {
 pub struct SignatureSource {
     pub name: String,
     pub url: url::Url,
-    pub pinned_spki_sha256: String,
+    /// `sha256/<base64>` SPKI pins of the endpoint's leaf or intermediate
+    /// certificates: one, or a list with backup pins.
+    #[serde(deserialize_with = "one_or_many")]
+    pub pinned_spki_sha256: Vec<String>,
     pub local_cache: PathBuf,
 }
+
+fn one_or_many<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(pin) => vec![pin],
+        OneOrMany::Many(pins) => pins,
+    })
+}
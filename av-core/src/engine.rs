@@ -6,6 +6,7 @@ use tokio::io::AsyncReadExt;
 
 use crate::config::ScannerConfig;
 use crate::heuristics::{self, Score};
+use crate::signatures::RuleSet;
 
 #[derive(Debug, Clone)]
 pub struct ScanContext {
@@ -23,6 +24,25 @@ pub struct SignatureMatch {
     pub rule: String,
     pub namespace: String,
     pub metadata: serde_json::Value,
+    /// Recorded for evaluation only, e.g. from an experimental rule; never
+    /// changes the recommended action.
+    #[serde(default)]
+    pub shadow: bool,
+}
+
+/// Finds candidate signature matches in a file's data. Which of them count,
+/// and which only in shadow mode, is decided by the active [`RuleSet`].
+pub trait SignatureMatcher: Send + Sync {
+    fn matches(&self, data: &[u8]) -> Vec<SignatureMatch>;
+}
+
+/// Matches nothing: bundles carry rule metadata, not rule bodies, so far.
+pub struct NoSignatures;
+
+impl SignatureMatcher for NoSignatures {
+    fn matches(&self, _data: &[u8]) -> Vec<SignatureMatch> {
+        Vec::new()
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
@@ -31,9 +51,27 @@ pub struct EntropyReport {
     pub suspicious_regions: Vec<(u64, u64)>,
 }
 
-pub async fn scan_path(config: &ScannerConfig, ctx: &ScanContext) -> anyhow::Result<crate::ScanOutcome> {
-    let data = read_head(&ctx.target).await?;
-    let signatures = evaluate_signatures(&data).await?;
+pub async fn scan_path(
+    config: &ScannerConfig,
+    ctx: &ScanContext,
+    matcher: &dyn SignatureMatcher,
+    rules: &RuleSet,
+) -> anyhow::Result<crate::ScanOutcome> {
+    let file = File::open(&ctx.target).await?;
+    scan_file(config, ctx, file, matcher, rules).await
+}
+
+/// Scan an already opened file. `ctx.target` is only used for reporting and
+/// path-based heuristics; it is never reopened.
+pub async fn scan_file(
+    config: &ScannerConfig,
+    ctx: &ScanContext,
+    file: File,
+    matcher: &dyn SignatureMatcher,
+    rules: &RuleSet,
+) -> anyhow::Result<crate::ScanOutcome> {
+    let data = read_head(file).await?;
+    let signatures = evaluate_signatures(&data, matcher, rules);
     let heuristic_score = heuristics::score(&ctx.target, &data, config);
     let entropy = if config.enable_entropy_analysis {
         entropy(&data)
@@ -51,15 +89,28 @@ pub async fn scan_path(config: &ScannerConfig, ctx: &ScanContext) -> anyhow::Res
     })
 }
 
-async fn read_head(path: &PathBuf) -> anyhow::Result<Vec<u8>> {
-    let mut file = File::open(path).await?;
+async fn read_head(file: File) -> anyhow::Result<Vec<u8>> {
     let mut buffer = Vec::with_capacity(256 * 1024);
     file.take(256 * 1024).read_to_end(&mut buffer).await?;
     Ok(buffer)
 }
 
-async fn evaluate_signatures(_data: &[u8]) -> anyhow::Result<Vec<SignatureMatch>> {
-    Ok(vec![])
+/// Keep the matches of rules in `rules`, keyed `<namespace>/<rule>`, and
+/// flag those evaluated in shadow mode. Matches of expired, disabled or
+/// unsampled rules are dropped.
+fn evaluate_signatures(data: &[u8], matcher: &dyn SignatureMatcher, rules: &RuleSet) -> Vec<SignatureMatch> {
+    matcher
+        .matches(data)
+        .into_iter()
+        .filter_map(|mut hit| {
+            let id = format!("{}/{}", hit.namespace, hit.rule);
+            if !rules.contains(&id) {
+                return None;
+            }
+            hit.shadow = rules.is_shadow(&id);
+            Some(hit)
+        })
+        .collect()
 }
 
 fn entropy(_data: &[u8]) -> EntropyReport {
@@ -15,7 +15,7 @@ pub fn score(_path: &Path, _data: &[u8], config: &ScannerConfig) -> Score {
 }
 
 pub fn recommend(matches: &[SignatureMatch], score: Score, config: &ScannerConfig) -> crate::RecommendedAction {
-    if !matches.is_empty() {
+    if matches.iter().any(|m| !m.shadow) {
         return crate::RecommendedAction::Quarantine;
     }
 
@@ -0,0 +1,179 @@
+//! Control socket protocol between av-daemon and its clients.
+//!
+//! A client connects to the daemon's Unix socket, writes one JSON
+//! [`Request`] terminated by a newline and reads one JSON [`Response`]
+//! line back. Requests only read daemon state; nothing sent over the socket
+//! can change it.
+
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::telemetry::TelemetryBatch;
+
+pub const DEFAULT_SOCKET: &str = "/run/charmedwoa-av/daemon.sock";
+
+/// Longest request line the daemon reads.
+pub const MAX_REQUEST: usize = 4096;
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "kebab-case")]
+pub enum Request {
+    UpdateStatus,
+    Metrics,
+    /// Shadow-mode matches per rule since the counts were last reset.
+    ShadowHits,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Response {
+    UpdateStatus(UpdateStatus),
+    Metrics(TelemetryBatch),
+    /// Keyed `<namespace>/<rule>`.
+    ShadowHits(BTreeMap<String, u64>),
+    Error(String),
+}
+
+/// Signature update polling as seen by the daemon.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UpdateStatus {
+    pub enabled: bool,
+    pub last_attempt: Option<DateTime<Utc>>,
+    pub last_success: Option<DateTime<Utc>>,
+    /// Why the most recent attempt failed; cleared by the next success.
+    pub last_failure: Option<String>,
+    pub consecutive_failures: u32,
+    pub next_attempt: Option<DateTime<Utc>>,
+    /// Signature generation the daemon's active rules were loaded from.
+    pub generation: Option<u64>,
+    pub rules_loaded: usize,
+}
+
+/// Send one request to the daemon listening on `socket`.
+pub fn request(socket: &Path, request: &Request) -> anyhow::Result<Response> {
+    let mut stream =
+        UnixStream::connect(socket).with_context(|| format!("connecting to av-daemon at {}", socket.display()))?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    let mut line = serde_json::to_vec(request)?;
+    line.push(b'\n');
+    stream.write_all(&line)?;
+    let mut reply = String::new();
+    let read = BufReader::new(stream).read_line(&mut reply)?;
+    anyhow::ensure!(read > 0, "av-daemon closed the connection without replying");
+    serde_json::from_str(&reply).context("malformed reply from av-daemon")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::os::unix::net::UnixListener;
+
+    use crate::telemetry::MetricPoint;
+
+    fn round_trip<T: Serialize + serde::de::DeserializeOwned>(value: &T) -> T {
+        serde_json::from_str(&serde_json::to_string(value).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn requests_round_trip() {
+        for (request, wire) in [
+            (Request::UpdateStatus, r#"{"command":"update-status"}"#),
+            (Request::Metrics, r#"{"command":"metrics"}"#),
+            (Request::ShadowHits, r#"{"command":"shadow-hits"}"#),
+        ] {
+            assert_eq!(serde_json::to_string(&request).unwrap(), wire);
+            assert_eq!(round_trip(&request), request);
+        }
+    }
+
+    #[test]
+    fn responses_round_trip() {
+        let at = "2026-10-18T12:00:00Z".parse().unwrap();
+        let status = UpdateStatus {
+            enabled: true,
+            last_attempt: Some(at),
+            last_success: None,
+            last_failure: Some("source `default`: timed out".into()),
+            consecutive_failures: 2,
+            next_attempt: Some(at),
+            generation: Some(7),
+            rules_loaded: 120,
+        };
+        let mut batch = TelemetryBatch::new();
+        batch.push(MetricPoint {
+            name: "scans_total".into(),
+            value: 3.0,
+            timestamp: at,
+        });
+        let hits = BTreeMap::from([("default/InternalDropper".to_string(), 4)]);
+        for response in [
+            Response::UpdateStatus(status),
+            Response::UpdateStatus(UpdateStatus::default()),
+            Response::Metrics(batch),
+            Response::ShadowHits(hits),
+            Response::ShadowHits(BTreeMap::new()),
+            Response::Error("unknown command".into()),
+        ] {
+            assert_eq!(round_trip(&response), response);
+        }
+        assert_eq!(
+            serde_json::to_string(&Response::Error("busy".into())).unwrap(),
+            r#"{"error":"busy"}"#
+        );
+    }
+
+    /// Accept one connection, read its request line and answer with `reply`.
+    fn serve_once(reply: &'static [u8]) -> (tempfile::TempDir, std::path::PathBuf) {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("daemon.sock");
+        let listener = UnixListener::bind(&socket).unwrap();
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut line = String::new();
+            BufReader::new(&stream).read_line(&mut line).unwrap();
+            stream.write_all(reply).unwrap();
+        });
+        (dir, socket)
+    }
+
+    #[test]
+    fn request_reads_one_reply() {
+        let (_dir, socket) = serve_once(b"{\"shadow-hits\":{\"default/A\":1}}\n");
+        let reply = request(&socket, &Request::ShadowHits).unwrap();
+        assert_eq!(reply, Response::ShadowHits(BTreeMap::from([("default/A".to_string(), 1)])));
+    }
+
+    #[test]
+    fn request_fails_when_the_daemon_hangs_up() {
+        let (_dir, socket) = serve_once(b"");
+        let err = request(&socket, &Request::Metrics).unwrap_err();
+        assert_eq!(err.to_string(), "av-daemon closed the connection without replying");
+    }
+
+    #[test]
+    fn request_rejects_malformed_replies() {
+        for reply in [&b"not json\n"[..], b"{\"metrics\":\n", b"{\"reboot\":true}\n"] {
+            let (_dir, socket) = serve_once(reply);
+            let err = request(&socket, &Request::UpdateStatus).unwrap_err();
+            assert_eq!(err.to_string(), "malformed reply from av-daemon");
+        }
+    }
+
+    #[test]
+    fn request_fails_without_a_daemon() {
+        let dir = tempfile::tempdir().unwrap();
+        let err = request(&dir.path().join("daemon.sock"), &Request::UpdateStatus).unwrap_err();
+        assert!(err.to_string().starts_with("connecting to av-daemon at "));
+    }
+}
@@ -15,16 +15,27 @@
 //! - YARA-compatible rules are validated before execution, and every
 //!   decision passes through the heuristic fusion layer for suppressions.
 
+pub mod base64_bytes;
+pub mod cache;
 pub mod config;
 pub mod engine;
 pub mod heuristics;
+pub mod ipc;
 pub mod monitoring;
+pub mod persist;
+pub mod rules;
 pub mod signatures;
 pub mod telemetry;
 
 pub use config::ScannerConfig;
 
 use std::path::Path;
+use std::sync::Arc;
+
+use parking_lot::RwLock;
+
+use engine::{NoSignatures, SignatureMatcher};
+use signatures::RuleSet;
 
 /// High-level scanning interface that callers use to analyse a path.
 ///
@@ -34,6 +45,10 @@ use std::path::Path;
 /// explicit authorization from the initiating user.
 pub struct Scanner {
     config: ScannerConfig,
+    matcher: Box<dyn SignatureMatcher>,
+    /// Rules matches are checked against; swapped when a new rule set is
+    /// activated.
+    rules: RwLock<Arc<RuleSet>>,
 }
 
 impl Scanner {
@@ -42,8 +57,24 @@ impl Scanner {
     /// requirements (fanotify availability, NEON support, etc.) are surfaced
     /// before monitoring begins.
     pub fn new(config: ScannerConfig) -> anyhow::Result<Self> {
+        Self::with_matcher(config, Box::new(NoSignatures))
+    }
+
+    /// Construct a scanner that finds signature matches with `matcher`.
+    /// No rules are active until [`Scanner::set_rules`] is called.
+    pub fn with_matcher(config: ScannerConfig, matcher: Box<dyn SignatureMatcher>) -> anyhow::Result<Self> {
         config.validate()?;
-        Ok(Self { config })
+        Ok(Self {
+            config,
+            matcher,
+            rules: RwLock::new(Arc::default()),
+        })
+    }
+
+    /// Evaluate later scans against `rules`. Scans already running finish
+    /// with the rules they started with.
+    pub fn set_rules(&self, rules: Arc<RuleSet>) {
+        *self.rules.write() = rules;
     }
 
     /// Perform a synchronous scan of the provided path.
@@ -52,9 +83,19 @@ impl Scanner {
     /// I/O and returns heuristic scores and signature matches.
     pub async fn scan_path<P: AsRef<Path>>(&self, path: P) -> anyhow::Result<ScanOutcome> {
         let context = engine::ScanContext::new(path.as_ref().to_path_buf());
-        let result = engine::scan_path(&self.config, &context).await?;
+        let rules = Arc::clone(&self.rules.read());
+        let result = engine::scan_path(&self.config, &context, self.matcher.as_ref(), &rules).await?;
         Ok(result)
     }
+
+    /// Scan a file the caller has already opened, e.g. a descriptor handed
+    /// over by a more privileged process. `path` is used for reporting only.
+    pub async fn scan_file<P: AsRef<Path>>(&self, file: std::fs::File, path: P) -> anyhow::Result<ScanOutcome> {
+        let context = engine::ScanContext::new(path.as_ref().to_path_buf());
+        let rules = Arc::clone(&self.rules.read());
+        let file = tokio::fs::File::from_std(file);
+        engine::scan_file(&self.config, &context, file, self.matcher.as_ref(), &rules).await
+    }
 }
 
 /// Result of a scan, containing structured metadata suitable for JSON
@@ -0,0 +1,79 @@
+//! Crash-safe replacement of state files shared by the daemon, the
+//! signature cache and the quarantine store.
+
+use std::fs;
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+
+/// Replace `path` with `contents` via a temporary file beside it, so
+/// readers see either the old or the new file and never a partial one.
+/// The parent directory is synced too, making the rename itself durable.
+pub fn write_atomic(path: &Path, contents: &[u8]) -> anyhow::Result<()> {
+    fs::create_dir_all(parent(path))?;
+    let tmp = temp_path(path);
+    let mut file = fs::File::create(&tmp)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    commit(&tmp, path)
+}
+
+/// Create `path` with `mode` and write `contents` to it, e.g. a freshly
+/// generated key. Fails if the file exists rather than replace it.
+pub fn write_new(path: &Path, contents: &[u8], mode: u32) -> anyhow::Result<()> {
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(mode)
+        .open(path)
+        .with_context(|| format!("creating {}", path.display()))?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    sync_dir(parent(path))
+}
+
+/// Rename a fully written and synced `tmp` over `path` and sync the
+/// directory holding it.
+pub fn commit(tmp: &Path, path: &Path) -> anyhow::Result<()> {
+    fs::rename(tmp, path)?;
+    sync_dir(parent(path))
+}
+
+/// Sibling of `path` to stage a replacement in: `<file name>.tmp`.
+pub fn temp_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".tmp");
+    path.with_file_name(name)
+}
+
+/// Flush a directory's entries, e.g. after renaming a file into it.
+pub fn sync_dir(path: &Path) -> anyhow::Result<()> {
+    fs::File::open(path)?.sync_all()?;
+    Ok(())
+}
+
+fn parent(path: &Path) -> &Path {
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replaces_files_without_leaving_the_staging_copy() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state").join("cache.json");
+        write_atomic(&path, b"{}").unwrap();
+        write_atomic(&path, b"{\"entries\":{}}").unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"{\"entries\":{}}");
+        assert_eq!(temp_path(&path), dir.path().join("state").join("cache.json.tmp"));
+        let names: Vec<_> = fs::read_dir(path.parent().unwrap()).unwrap().map(|e| e.unwrap().file_name()).collect();
+        assert_eq!(names, ["cache.json"]);
+    }
+}
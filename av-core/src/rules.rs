@@ -0,0 +1,689 @@
+//! Tooling for YARA rule authors: lint, corpus tests and precompilation.
+//!
+//! Syntax is checked by libyara itself. Required `meta:` fields and regex
+//! cost are checked on the source, since compiled rules do not expose their
+//! metadata and libyara drops its warnings once a file compiles.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use serde::Serialize;
+
+use crate::persist;
+
+/// `meta:` fields every rule must carry.
+pub const REQUIRED_META: [&str; 3] = ["description", "author", "ab_bucket"];
+
+/// Expected matches of a corpus, keyed by file path relative to the corpus
+/// root, e.g. `{"elf/upx-packed": ["DemoARM64Malware"], "clean/ls": []}`.
+pub const EXPECTATIONS: &str = "expected.json";
+
+/// Fixed bytes a regex needs for libyara to pick a selective atom.
+const MIN_LITERAL: usize = 3;
+const SCAN_TIMEOUT_SECS: i32 = 30;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub file: PathBuf,
+    pub line: usize,
+    pub rule: Option<String>,
+    pub message: String,
+}
+
+impl std::fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let severity = match self.severity {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        write!(f, "{}:{}: {severity}: ", self.file.display(), self.line)?;
+        if let Some(rule) = &self.rule {
+            write!(f, "rule `{rule}`: ")?;
+        }
+        f.write_str(&self.message)
+    }
+}
+
+/// A rule as written, with what linting and packaging need from it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RuleSource {
+    pub name: String,
+    pub line: usize,
+    pub tags: Vec<String>,
+    /// `meta:` values, with string quotes removed.
+    pub meta: BTreeMap<String, String>,
+    /// Regex strings as `(line, pattern)`.
+    pub regexes: Vec<(usize, String)>,
+}
+
+/// Rule files under `paths`: files as given, directories searched for
+/// `.yar` and `.yara` files.
+pub fn rule_files(paths: &[PathBuf]) -> anyhow::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for path in paths {
+        if !path.is_dir() {
+            files.push(path.clone());
+            continue;
+        }
+        for entry in walkdir::WalkDir::new(path).sort_by_file_name() {
+            let entry = entry?;
+            let is_rule = entry
+                .path()
+                .extension()
+                .is_some_and(|ext| ext == "yar" || ext == "yara");
+            if entry.file_type().is_file() && is_rule {
+                files.push(entry.into_path());
+            }
+        }
+    }
+    anyhow::ensure!(!files.is_empty(), "no .yar or .yara files found");
+    Ok(files)
+}
+
+/// Check syntax, required meta fields, duplicate rule names across files and
+/// regexes likely to slow scanning down.
+pub fn lint(paths: &[PathBuf]) -> anyhow::Result<Vec<Diagnostic>> {
+    let mut diagnostics = Vec::new();
+    let mut seen: BTreeMap<String, (PathBuf, usize)> = BTreeMap::new();
+    for file in rule_files(paths)? {
+        match yara::Compiler::new()?.add_rules_file(&file) {
+            Ok(_) => {}
+            Err(yara::Error::Compile(errors)) => {
+                let failed = errors.iter().any(|err| err.level == yara::CompileErrorLevel::Error);
+                diagnostics.extend(errors.iter().map(|err| Diagnostic {
+                    severity: match err.level {
+                        yara::CompileErrorLevel::Error => Severity::Error,
+                        yara::CompileErrorLevel::Warning => Severity::Warning,
+                    },
+                    file: err.filename.as_ref().map_or_else(|| file.clone(), PathBuf::from),
+                    line: err.line,
+                    rule: None,
+                    message: err.message.clone(),
+                }));
+                // The source checks below assume source libyara accepts.
+                if failed {
+                    continue;
+                }
+            }
+            Err(err) => return Err(err).with_context(|| format!("compiling {}", file.display())),
+        }
+
+        let source = fs::read_to_string(&file).with_context(|| format!("reading {}", file.display()))?;
+        for rule in parse(&source) {
+            let mut report = |severity, line, message: String| {
+                diagnostics.push(Diagnostic {
+                    severity,
+                    file: file.clone(),
+                    line,
+                    rule: Some(rule.name.clone()),
+                    message,
+                })
+            };
+            match seen.get(&rule.name) {
+                // Duplicates within a file are already a compile error.
+                Some((other, line)) if *other != file => report(
+                    Severity::Error,
+                    rule.line,
+                    format!("also defined at {}:{line}", other.display()),
+                ),
+                Some(_) => {}
+                None => {
+                    seen.insert(rule.name.clone(), (file.clone(), rule.line));
+                }
+            }
+            for field in REQUIRED_META {
+                match rule.meta.get(field) {
+                    None => report(Severity::Error, rule.line, format!("missing meta field `{field}`")),
+                    Some(value) if value.trim().is_empty() => {
+                        report(Severity::Error, rule.line, format!("meta field `{field}` is empty"))
+                    }
+                    Some(_) => {}
+                }
+            }
+            for (line, pattern) in &rule.regexes {
+                for warning in regex_warnings(pattern) {
+                    report(Severity::Warning, *line, format!("/{pattern}/: {warning}"));
+                }
+            }
+        }
+    }
+    diagnostics.sort_by(|a, b| (&a.file, a.line).cmp(&(&b.file, b.line)));
+    Ok(diagnostics)
+}
+
+/// Compile rule files into a single rule set.
+pub fn compile(paths: &[PathBuf]) -> anyhow::Result<yara::Rules> {
+    let mut compiler = yara::Compiler::new()?;
+    for file in rule_files(paths)? {
+        compiler = compiler
+            .add_rules_file(&file)
+            .with_context(|| format!("compiling {}", file.display()))?;
+    }
+    Ok(compiler.compile_rules()?)
+}
+
+/// Compile rule files into a precompiled rule cache at `output`, replacing
+/// it atomically. Returns the number of rule files compiled.
+pub fn precompile(paths: &[PathBuf], output: &Path) -> anyhow::Result<usize> {
+    let files = rule_files(paths)?;
+    let mut rules = compile(&files)?;
+    let tmp = persist::temp_path(output);
+    let tmp_name = tmp.to_str().context("output path is not valid UTF-8")?;
+    rules
+        .save(tmp_name)
+        .with_context(|| format!("writing {}", tmp.display()))?;
+    fs::File::open(&tmp)?.sync_all()?;
+    persist::commit(&tmp, output)?;
+    Ok(files.len())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Expectation {
+    /// The file is annotated with this rule.
+    Expected,
+    /// The file is annotated, but not with this rule.
+    Unexpected,
+    /// The file has no annotation.
+    Unannotated,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Hit {
+    pub file: PathBuf,
+    pub expectation: Expectation,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Missed {
+    pub rule: String,
+    pub file: PathBuf,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct CorpusReport {
+    pub scanned: usize,
+    /// Corpus files each rule matched, relative to the corpus root. Rules
+    /// matching nothing are listed with no hits.
+    pub hits: BTreeMap<String, Vec<Hit>>,
+    /// Annotated matches that did not happen.
+    pub missed: Vec<Missed>,
+}
+
+impl CorpusReport {
+    pub fn unexpected(&self) -> usize {
+        self.hits
+            .values()
+            .flatten()
+            .filter(|hit| hit.expectation == Expectation::Unexpected)
+            .count()
+    }
+
+    pub fn passed(&self) -> bool {
+        self.missed.is_empty() && self.unexpected() == 0
+    }
+}
+
+/// Scan every file under `corpus` with the rules under `paths`, checking
+/// matches against the corpus's [`EXPECTATIONS`] file if it has one.
+pub fn test_corpus(paths: &[PathBuf], corpus: &Path) -> anyhow::Result<CorpusReport> {
+    let files = rule_files(paths)?;
+    let rules = compile(&files)?;
+    let mut report = CorpusReport::default();
+    for file in &files {
+        let source = fs::read_to_string(file).with_context(|| format!("reading {}", file.display()))?;
+        for rule in parse(&source) {
+            report.hits.entry(rule.name).or_default();
+        }
+    }
+
+    let expectations_path = corpus.join(EXPECTATIONS);
+    let expectations: BTreeMap<PathBuf, BTreeSet<String>> = match fs::read(&expectations_path) {
+        Ok(bytes) => {
+            serde_json::from_slice(&bytes).with_context(|| format!("parsing {}", expectations_path.display()))?
+        }
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+        Err(err) => return Err(err.into()),
+    };
+    for file in expectations.keys() {
+        anyhow::ensure!(
+            corpus.join(file).is_file(),
+            "{} annotates {}, which is not in the corpus",
+            expectations_path.display(),
+            file.display()
+        );
+    }
+
+    for entry in walkdir::WalkDir::new(corpus).sort_by_file_name() {
+        let entry = entry?;
+        if !entry.file_type().is_file() || entry.path() == expectations_path {
+            continue;
+        }
+        let relative = entry.path().strip_prefix(corpus)?.to_path_buf();
+        let matched: BTreeSet<String> = rules
+            .scan_file(entry.path(), SCAN_TIMEOUT_SECS)
+            .with_context(|| format!("scanning {}", entry.path().display()))?
+            .into_iter()
+            .map(|rule| rule.identifier.to_string())
+            .collect();
+        report.scanned += 1;
+
+        let expected = expectations.get(&relative);
+        for rule in &matched {
+            let expectation = match expected {
+                None => Expectation::Unannotated,
+                Some(rules) if rules.contains(rule) => Expectation::Expected,
+                Some(_) => Expectation::Unexpected,
+            };
+            report.hits.entry(rule.clone()).or_default().push(Hit {
+                file: relative.clone(),
+                expectation,
+            });
+        }
+        for rule in expected.into_iter().flatten().filter(|rule| !matched.contains(*rule)) {
+            report.missed.push(Missed {
+                rule: rule.clone(),
+                file: relative.clone(),
+            });
+        }
+    }
+    Ok(report)
+}
+
+/// Extract rules from YARA source. This is not a full parser; it expects
+/// source libyara accepts and the usual one-declaration-per-line layout.
+pub fn parse(source: &str) -> Vec<RuleSource> {
+    #[derive(PartialEq)]
+    enum Section {
+        Header,
+        Meta,
+        Strings,
+        Condition,
+    }
+
+    let stripped = strip_comments(source);
+    let mut rules = Vec::new();
+    let mut current: Option<RuleSource> = None;
+    let mut section = Section::Header;
+    for (index, line) in stripped.lines().enumerate() {
+        let trimmed = line.trim();
+        if let Some((name, tags)) = rule_header(trimmed) {
+            rules.extend(current.take());
+            current = Some(RuleSource {
+                name,
+                line: index + 1,
+                tags,
+                ..RuleSource::default()
+            });
+            section = Section::Header;
+            continue;
+        }
+        let Some(rule) = current.as_mut() else {
+            continue;
+        };
+        let mut body = trimmed.trim_start_matches('{').trim_start();
+        for (keyword, next) in [
+            ("meta:", Section::Meta),
+            ("strings:", Section::Strings),
+            ("condition:", Section::Condition),
+        ] {
+            if let Some(rest) = body.strip_prefix(keyword) {
+                body = rest.trim_start();
+                section = next;
+                break;
+            }
+        }
+        match section {
+            Section::Meta => {
+                if let Some((key, value)) = body.split_once('=') {
+                    let value = value.trim();
+                    let value = value
+                        .strip_prefix('"')
+                        .and_then(|v| v.strip_suffix('"'))
+                        .unwrap_or(value);
+                    rule.meta.entry(key.trim().to_string()).or_insert_with(|| value.to_string());
+                }
+            }
+            Section::Strings => {
+                let pattern = body
+                    .split_once('=')
+                    .and_then(|(_, value)| value.trim_start().strip_prefix('/'))
+                    .and_then(regex_body);
+                if let Some(pattern) = pattern {
+                    rule.regexes.push((index + 1, pattern.to_string()));
+                }
+            }
+            Section::Condition if body.starts_with('}') => rules.extend(current.take()),
+            Section::Header | Section::Condition => {}
+        }
+    }
+    rules.extend(current);
+    rules
+}
+
+/// `rule Name : tag1 tag2 {`, optionally `private` or `global`.
+fn rule_header(line: &str) -> Option<(String, Vec<String>)> {
+    let mut rest = line;
+    loop {
+        rest = rest.trim_start();
+        match rest.split_once(char::is_whitespace) {
+            Some(("private" | "global", tail)) => rest = tail,
+            Some(("rule", tail)) => {
+                rest = tail.trim_start();
+                break;
+            }
+            _ => return None,
+        }
+    }
+    let end = rest
+        .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+        .unwrap_or(rest.len());
+    let (name, tail) = rest.split_at(end);
+    if name.is_empty() {
+        return None;
+    }
+    let tags = tail
+        .trim_start()
+        .strip_prefix(':')
+        .map(|tags| {
+            tags.split('{')
+                .next()
+                .unwrap_or_default()
+                .split_whitespace()
+                .map(str::to_string)
+                .collect()
+        })
+        .unwrap_or_default();
+    Some((name.to_string(), tags))
+}
+
+/// The pattern of a regex literal whose opening `/` has been stripped.
+fn regex_body(literal: &str) -> Option<&str> {
+    let mut escaped = false;
+    for (index, c) in literal.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '/' => return Some(&literal[..index]),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Blank out comments, keeping line breaks so line numbers still match.
+fn strip_comments(source: &str) -> String {
+    let mut out = String::with_capacity(source.len());
+    let mut chars = source.chars().peekable();
+    let mut last = ' ';
+    while let Some(c) = chars.next() {
+        match c {
+            '/' if chars.peek() == Some(&'/') => {
+                while chars.peek().is_some_and(|&c| c != '\n') {
+                    chars.next();
+                }
+            }
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut prev = ' ';
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        out.push('\n');
+                    }
+                    if prev == '*' && c == '/' {
+                        break;
+                    }
+                    prev = c;
+                }
+            }
+            // Copy string and regex literals verbatim so `//` inside them
+            // is not taken for a comment.
+            '"' | '/' if c == '"' || last == '=' => {
+                out.push(c);
+                let mut escaped = false;
+                for d in chars.by_ref() {
+                    out.push(d);
+                    match d {
+                        _ if escaped => escaped = false,
+                        '\\' => escaped = true,
+                        '\n' => break,
+                        _ if d == c => break,
+                        _ => {}
+                    }
+                }
+            }
+            _ => out.push(c),
+        }
+        if !c.is_whitespace() {
+            last = c;
+        }
+    }
+    out
+}
+
+/// Why a regex is likely to slow scanning down, if it is.
+fn regex_warnings(pattern: &str) -> Vec<String> {
+    let mut warnings = Vec::new();
+    if has_unbounded_wildcard(pattern) {
+        warnings.push("unbounded wildcard; matching cost grows with file size".to_string());
+    }
+    let literal = longest_literal(pattern);
+    if literal < MIN_LITERAL {
+        warnings.push(format!(
+            "longest fixed run is {literal} byte(s); at least {MIN_LITERAL} are needed for a selective atom"
+        ));
+    }
+    warnings
+}
+
+fn has_unbounded_wildcard(pattern: &str) -> bool {
+    let bytes = pattern.as_bytes();
+    let mut escaped = false;
+    for (index, &b) in bytes.iter().enumerate() {
+        if escaped {
+            escaped = false;
+            continue;
+        }
+        match b {
+            b'\\' => escaped = true,
+            b'.' => {
+                let rest = &pattern[index + 1..];
+                let open_range = rest
+                    .strip_prefix('{')
+                    .and_then(|r| r.split_once('}'))
+                    .is_some_and(|(range, _)| range.ends_with(','));
+                if rest.starts_with('*') || rest.starts_with('+') || open_range {
+                    return true;
+                }
+            }
+            _ => {}
+        }
+    }
+    false
+}
+
+/// Length of the longest run of bytes every match must contain.
+fn longest_literal(pattern: &str) -> usize {
+    let (mut best, mut run) = (0usize, 0usize);
+    let mut chars = pattern.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some('d' | 'D' | 'w' | 'W' | 's' | 'S' | 'b' | 'B') | None => {
+                    best = best.max(run);
+                    run = 0;
+                }
+                Some('x') => {
+                    chars.next();
+                    chars.next();
+                    run += 1;
+                }
+                Some(_) => run += 1,
+            },
+            '[' => {
+                best = best.max(run);
+                run = 0;
+                let mut escaped = false;
+                for c in chars.by_ref() {
+                    match c {
+                        _ if escaped => escaped = false,
+                        '\\' => escaped = true,
+                        ']' => break,
+                        _ => {}
+                    }
+                }
+            }
+            // The preceding atom may be absent.
+            '*' | '?' => {
+                best = best.max(run.saturating_sub(1));
+                run = 0;
+            }
+            '{' => {
+                let range: String = chars.by_ref().take_while(|&c| c != '}').collect();
+                let min = range.split(',').next().unwrap_or_default().trim();
+                best = best.max(if min == "0" { run.saturating_sub(1) } else { run });
+                run = 0;
+            }
+            '+' | '.' | '(' | ')' | '|' | '^' | '$' => {
+                best = best.max(run);
+                run = 0;
+            }
+            _ => run += 1,
+        }
+    }
+    best.max(run)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RULES: &str = r#"
+// Fixture rules; `lint` flags the second one.
+rule Packed : packer elf
+{
+    meta:
+        description = "UPX-packed ELF"
+        author = "CharmedWOA Security"
+        ab_bucket = "baseline"
+    strings:
+        $elf = { 7F 45 4C 46 }
+        $upx = /UPX[0-9]{2}/ nocase
+    condition:
+        $elf at 0 and $upx
+}
+
+/* Incomplete metadata and a costly regex. */
+rule Sloppy
+{
+    meta:
+        description = "http://example // not a comment"
+        author = ""
+    strings:
+        $url = /h.+p[:\/]+[a-z]+/
+    condition:
+        $url
+}
+"#;
+
+    #[test]
+    fn lints_meta_and_regexes() {
+        let rules = parse(RULES);
+        assert_eq!(rules.len(), 2);
+        assert_eq!(rules[0].name, "Packed");
+        assert_eq!(rules[0].tags, ["packer", "elf"]);
+        assert_eq!(rules[0].meta["ab_bucket"], "baseline");
+        assert_eq!(rules[0].regexes, [(11, "UPX[0-9]{2}".to_string())]);
+        assert_eq!(rules[1].meta["description"], "http://example // not a comment");
+        assert_eq!(
+            rule_header("private rule Hidden : a b {"),
+            Some(("Hidden".to_string(), vec!["a".to_string(), "b".to_string()]))
+        );
+
+        assert_eq!(longest_literal("UPX[0-9]{2}"), 3);
+        assert_eq!(longest_literal("abc?d"), 2);
+        assert_eq!(longest_literal(r"\x7fELF"), 4);
+        assert!(has_unbounded_wildcard("a.{4,}b") && !has_unbounded_wildcard(r"a\.*b"));
+
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("fixture.yar"), RULES).unwrap();
+        fs::write(dir.path().join("broken.yara"), "rule Broken { condition: nope }").unwrap();
+        let diagnostics = lint(&[dir.path().to_path_buf()]).unwrap();
+        let messages: Vec<(Severity, Option<&str>, &str)> = diagnostics
+            .iter()
+            .map(|d| (d.severity, d.rule.as_deref(), d.message.as_str()))
+            .collect();
+        assert_eq!(messages[0].0, Severity::Error);
+        assert!(diagnostics[0].file.ends_with("broken.yara"));
+        assert_eq!(
+            &messages[1..],
+            [
+                (Severity::Error, Some("Sloppy"), "meta field `author` is empty"),
+                (Severity::Error, Some("Sloppy"), "missing meta field `ab_bucket`"),
+                (
+                    Severity::Warning,
+                    Some("Sloppy"),
+                    r"/h.+p[:\/]+[a-z]+/: unbounded wildcard; matching cost grows with file size"
+                ),
+                (
+                    Severity::Warning,
+                    Some("Sloppy"),
+                    r"/h.+p[:\/]+[a-z]+/: longest fixed run is 1 byte(s); at least 3 are needed for a selective atom"
+                ),
+            ]
+        );
+
+        let example = Path::new(env!("CARGO_MANIFEST_DIR")).join("../rules/example.yar");
+        assert_eq!(lint(&[example]).unwrap(), []);
+    }
+
+    #[test]
+    fn tests_corpus_and_precompiles() {
+        let dir = tempfile::tempdir().unwrap();
+        let rules = dir.path().join("rules.yar");
+        fs::write(&rules, RULES).unwrap();
+        let corpus = dir.path().join("corpus");
+        fs::create_dir_all(corpus.join("elf")).unwrap();
+        fs::write(corpus.join("elf/packed"), b"\x7fELF\x02\x01\x01 UPX01").unwrap();
+        fs::write(corpus.join("elf/plain"), b"\x7fELF\x02\x01\x01").unwrap();
+        fs::write(corpus.join("notes.txt"), b"see http://example").unwrap();
+        fs::write(
+            corpus.join(EXPECTATIONS),
+            r#"{"elf/packed": ["Packed"], "elf/plain": ["Packed"], "notes.txt": []}"#,
+        )
+        .unwrap();
+
+        let report = test_corpus(std::slice::from_ref(&rules), &corpus).unwrap();
+        assert_eq!(report.scanned, 3);
+        let hit = |file: &str, expectation| Hit {
+            file: file.into(),
+            expectation,
+        };
+        assert_eq!(report.hits["Packed"], [hit("elf/packed", Expectation::Expected)]);
+        assert_eq!(report.hits["Sloppy"], [hit("notes.txt", Expectation::Unexpected)]);
+        assert_eq!(
+            report.missed,
+            [Missed {
+                rule: "Packed".into(),
+                file: "elf/plain".into()
+            }]
+        );
+        assert!(!report.passed());
+
+        let cache = dir.path().join("rules.yarc");
+        assert_eq!(precompile(&[rules], &cache).unwrap(), 1);
+        let compiled = yara::Rules::load_from_file(cache.to_str().unwrap()).unwrap();
+        let matched = compiled.scan_mem(b"\x7fELF upx42", 5).unwrap();
+        assert_eq!(matched.iter().map(|r| r.identifier).collect::<Vec<_>>(), ["Packed"]);
+    }
+}
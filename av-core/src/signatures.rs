@@ -1,11 +1,23 @@
 //! Signature management primitives: validation, provenance, and AB testing.
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs;
+use std::path::Path;
 
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, Duration, Utc};
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+use crate::engine::SignatureMatch;
+use crate::persist;
+
+/// Rules nearing their `expires_at` within this window are logged at load.
+pub const EXPIRY_WARNING: Duration = Duration::days(7);
+
+/// A/B bucket enforced on every host. Rules without a bucket belong to it.
+pub const BASELINE_BUCKET: &str = "baseline";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct RuleMetadata {
     pub id: String,
     pub description: String,
@@ -14,6 +26,193 @@ pub struct RuleMetadata {
     pub created_at: DateTime<Utc>,
     pub expires_at: Option<DateTime<Utc>>,
     pub tags: Vec<String>,
+    #[serde(default)]
+    pub state: RuleState,
+}
+
+impl RuleMetadata {
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at.is_some_and(|at| at <= now)
+    }
+}
+
+/// Where a rule is in its lifecycle. Rules without a state are active.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RuleState {
+    /// Evaluated in shadow mode: matches are recorded but never drive the
+    /// recommended action.
+    Experimental,
+    #[default]
+    Active,
+    /// Still enforced; logged at load so it can be retired.
+    Deprecated,
+    /// Not loaded.
+    Disabled,
+}
+
+impl std::fmt::Display for RuleState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Experimental => "experimental",
+            Self::Active => "active",
+            Self::Deprecated => "deprecated",
+            Self::Disabled => "disabled",
+        })
+    }
+}
+
+/// Which non-baseline A/B buckets this host evaluates.
+///
+/// Each host gets a stable position in `[0, 1)` per bucket, derived from its
+/// machine id and the bucket name, and evaluates a bucket when that position
+/// falls below the bucket's rollout fraction. Raising the fraction only adds
+/// hosts, and hashing in the bucket name keeps the same hosts from being
+/// sampled for every experiment.
+#[derive(Debug, Clone, Default)]
+pub struct AbAssignment {
+    host_id: String,
+    rollout: BTreeMap<String, f64>,
+}
+
+impl AbAssignment {
+    pub fn new(host_id: impl Into<String>, rollout: BTreeMap<String, f64>) -> Self {
+        Self {
+            host_id: host_id.into(),
+            rollout,
+        }
+    }
+
+    /// Assignment keyed by `/etc/machine-id`.
+    pub fn for_this_host(rollout: BTreeMap<String, f64>) -> anyhow::Result<Self> {
+        let host_id = fs::read_to_string("/etc/machine-id")?;
+        Ok(Self::new(host_id.trim(), rollout))
+    }
+
+    pub fn position(&self, bucket: &str) -> f64 {
+        let mut hasher = Sha256::new();
+        hasher.update(self.host_id.as_bytes());
+        hasher.update([0]);
+        hasher.update(bucket.as_bytes());
+        let digest = hasher.finalize();
+        let head = u64::from_be_bytes(digest[..8].try_into().expect("sha256 is 32 bytes"));
+        (head >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Whether this host evaluates `bucket`. Buckets without a rollout
+    /// fraction run nowhere.
+    pub fn samples(&self, bucket: &str) -> bool {
+        bucket == BASELINE_BUCKET || self.position(bucket) < self.rollout.get(bucket).copied().unwrap_or(0.0)
+    }
+}
+
+/// Rules selected for evaluation, plus what the loader saw.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RuleSet {
+    pub rules: Vec<RuleMetadata>,
+    /// Ids of loaded rules evaluated in shadow mode.
+    pub shadow: BTreeSet<String>,
+    pub summary: LifecycleSummary,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LifecycleSummary {
+    /// Unexpired rules per lifecycle state, loaded or not.
+    pub states: BTreeMap<RuleState, usize>,
+    /// Rules skipped because `expires_at` has passed.
+    pub expired: usize,
+    /// Rules skipped because this host is outside their A/B bucket's sample.
+    pub unsampled: usize,
+    /// Loaded rules expiring within [`EXPIRY_WARNING`].
+    pub expiring: Vec<String>,
+}
+
+impl RuleSet {
+    /// Select the rules to evaluate as of `now`: expired and disabled rules
+    /// are skipped, as are rules in A/B buckets this host is not sampled
+    /// for. Sampled non-baseline and experimental rules run in shadow mode.
+    pub fn load(rules: impl IntoIterator<Item = RuleMetadata>, now: DateTime<Utc>, ab: &AbAssignment) -> Self {
+        let mut set = Self::default();
+        for rule in rules {
+            if rule.is_expired(now) {
+                set.summary.expired += 1;
+                continue;
+            }
+            *set.summary.states.entry(rule.state).or_default() += 1;
+            match rule.state {
+                RuleState::Disabled => continue,
+                RuleState::Deprecated => tracing::warn!(rule = %rule.id, "loading deprecated rule"),
+                RuleState::Experimental | RuleState::Active => {}
+            }
+            let bucket = rule.ab_bucket.as_deref().unwrap_or(BASELINE_BUCKET);
+            if !ab.samples(bucket) {
+                set.summary.unsampled += 1;
+                continue;
+            }
+            if bucket != BASELINE_BUCKET || rule.state == RuleState::Experimental {
+                set.shadow.insert(rule.id.clone());
+            }
+            if let Some(at) = rule.expires_at.filter(|at| *at <= now + EXPIRY_WARNING) {
+                tracing::warn!(rule = %rule.id, expires_at = %at, "rule expires soon");
+                set.summary.expiring.push(rule.id.clone());
+            }
+            set.rules.push(rule);
+        }
+        set.rules.sort_by(|a, b| a.id.cmp(&b.id));
+        set.summary.expiring.sort();
+        set
+    }
+
+    pub fn contains(&self, id: &str) -> bool {
+        self.rules.binary_search_by(|rule| rule.id.as_str().cmp(id)).is_ok()
+    }
+
+    pub fn is_shadow(&self, id: &str) -> bool {
+        self.shadow.contains(id)
+    }
+
+    /// When the first loaded rule expires, after which the set is stale.
+    pub fn next_expiry(&self) -> Option<DateTime<Utc>> {
+        self.rules.iter().filter_map(|rule| rule.expires_at).min()
+    }
+}
+
+/// Shadow-mode matches per rule, the evidence for promoting a rule to the
+/// baseline bucket.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ShadowHits {
+    pub rules: BTreeMap<String, u64>,
+    #[serde(skip)]
+    dirty: bool,
+}
+
+impl ShadowHits {
+    /// Load persisted counts. A missing file yields no hits.
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        match fs::read(path) {
+            Ok(bytes) => Ok(serde_json::from_slice(&bytes)?),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    pub fn save(&mut self, path: &Path) -> anyhow::Result<()> {
+        persist::write_atomic(path, &serde_json::to_vec_pretty(self)?)?;
+        self.dirty = false;
+        Ok(())
+    }
+
+    /// Count the shadow matches of one scan, keyed `<namespace>/<rule>`.
+    pub fn record(&mut self, matches: &[SignatureMatch]) {
+        for hit in matches.iter().filter(|m| m.shadow) {
+            *self.rules.entry(format!("{}/{}", hit.namespace, hit.rule)).or_default() += 1;
+            self.dirty = true;
+        }
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -21,6 +220,11 @@ pub struct RuleBundle {
     pub version: semver::Version,
     pub rules: HashMap<String, RuleMetadata>,
     pub checksum: String,
+    /// Signed release window. Publishers re-sign before `expires_at`, so a
+    /// client that keeps seeing an expired bundle knows updates are being
+    /// withheld.
+    pub issued_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
 }
 
 impl RuleBundle {
@@ -28,4 +232,119 @@ impl RuleBundle {
         anyhow::ensure!(self.checksum == expected_checksum, "bundle checksum mismatch");
         Ok(())
     }
+
+    /// `sha256:<hex>` over the version and the rules in id order. The
+    /// release window is left out so re-signing keeps the checksum.
+    pub fn content_checksum(&self) -> String {
+        let rules: BTreeMap<&String, &RuleMetadata> = self.rules.iter().collect();
+        let mut hasher = Sha256::new();
+        hasher.update(self.version.to_string().as_bytes());
+        hasher.update([0]);
+        hasher.update(serde_json::to_vec(&rules).expect("rule metadata serializes"));
+        let digest = hasher.finalize();
+        let hex: String = digest.iter().map(|b| format!("{b:02x}")).collect();
+        format!("sha256:{hex}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(id: &str, state: RuleState, expires_at: Option<&str>) -> RuleMetadata {
+        RuleMetadata {
+            id: id.into(),
+            description: String::new(),
+            provenance: "test".into(),
+            ab_bucket: None,
+            created_at: "2026-01-01T00:00:00Z".parse().unwrap(),
+            expires_at: expires_at.map(|at| at.parse().unwrap()),
+            tags: vec![],
+            state,
+        }
+    }
+
+    #[test]
+    fn loader_honours_expiry_and_state() {
+        let now = "2026-10-18T00:00:00Z".parse().unwrap();
+        let set = RuleSet::load(
+            [
+                rule("active", RuleState::Active, None),
+                rule("expired", RuleState::Active, Some("2026-10-17T00:00:00Z")),
+                rule("expiring", RuleState::Active, Some("2026-10-20T00:00:00Z")),
+                rule("trial", RuleState::Experimental, Some("2027-01-01T00:00:00Z")),
+                rule("old", RuleState::Deprecated, None),
+                rule("off", RuleState::Disabled, None),
+            ],
+            now,
+            &AbAssignment::default(),
+        );
+        let loaded: Vec<&str> = set.rules.iter().map(|r| r.id.as_str()).collect();
+        assert_eq!(loaded, ["active", "expiring", "old", "trial"]);
+        assert_eq!(set.summary.expired, 1);
+        assert_eq!(set.summary.expiring, ["expiring"]);
+        assert_eq!(
+            set.summary.states,
+            BTreeMap::from([
+                (RuleState::Experimental, 1),
+                (RuleState::Active, 2),
+                (RuleState::Deprecated, 1),
+                (RuleState::Disabled, 1),
+            ])
+        );
+        let legacy: RuleMetadata = serde_json::from_value(serde_json::json!({
+            "id": "legacy", "description": "", "provenance": "", "ab_bucket": null,
+            "created_at": "2026-01-01T00:00:00Z", "expires_at": null, "tags": [],
+        }))
+        .unwrap();
+        assert_eq!(legacy.state, RuleState::Active);
+    }
+
+    #[test]
+    fn buckets_run_in_shadow_on_a_stable_host_fraction() {
+        let rollout = BTreeMap::from([("canary".to_string(), 0.25)]);
+        let sampled = (0..1000)
+            .filter(|host| AbAssignment::new(format!("host-{host}"), rollout.clone()).samples("canary"))
+            .count();
+        assert!((200..300).contains(&sampled), "{sampled} of 1000 hosts sampled");
+
+        let host = (0..)
+            .map(|host| AbAssignment::new(format!("host-{host}"), rollout.clone()))
+            .find(|ab| ab.samples("canary"))
+            .unwrap();
+        assert!(host.samples("canary"), "assignment is deterministic");
+        assert!(!host.samples("unlisted"));
+
+        let bucketed = |id: &str, bucket: &str| RuleMetadata {
+            ab_bucket: Some(bucket.into()),
+            ..rule(id, RuleState::Active, None)
+        };
+        let now = "2026-10-18T00:00:00Z".parse().unwrap();
+        let set = RuleSet::load(
+            [
+                bucketed("DemoARM64Malware", BASELINE_BUCKET),
+                bucketed("new-packer", "canary"),
+                bucketed("other", "unlisted"),
+                rule("trial", RuleState::Experimental, None),
+            ],
+            now,
+            &host,
+        );
+        assert_eq!(set.rules.len(), 3);
+        assert_eq!(set.summary.unsampled, 1);
+        assert!(!set.is_shadow("DemoARM64Malware"));
+        assert!(set.is_shadow("new-packer") && set.is_shadow("trial"));
+
+        let hit = |rule: &str, shadow| SignatureMatch {
+            rule: rule.into(),
+            namespace: "vendor".into(),
+            metadata: serde_json::Value::Null,
+            shadow,
+        };
+        let mut hits = ShadowHits::default();
+        hits.record(&[hit("new-packer", true), hit("DemoARM64Malware", false)]);
+        hits.record(&[hit("new-packer", true)]);
+        assert_eq!(hits.rules, BTreeMap::from([("vendor/new-packer".to_string(), 2)]));
+        assert!(hits.is_dirty());
+    }
 }
@@ -0,0 +1,321 @@
+//! Daemon configuration as shipped in `/etc/charmedwoa-av/daemon.toml`.
+//!
+//! Every section is optional; missing keys fall back to the conservative
+//! defaults documented on each struct so a partial file never prevents the
+//! daemon from starting in audit-only mode.
+
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use av_core::config::ScannerConfig;
+
+const DEFAULT_CONFIG_PATH: &str = "/etc/charmedwoa-av/daemon.toml";
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DaemonConfig {
+    pub scanner: ScannerConfig,
+    pub realtime: RealtimeConfig,
+    pub battery: BatteryConfig,
+    pub scheduler: SchedulerConfig,
+    pub security: SecurityConfig,
+    pub workers: WorkerConfig,
+    pub shutdown: ShutdownConfig,
+    pub updates: UpdatesConfig,
+    pub ipc: IpcConfig,
+}
+
+/// Real-time interception settings.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RealtimeConfig {
+    /// Trees watched for real-time events.
+    pub watch_paths: Vec<PathBuf>,
+    pub fanotify: bool,
+    pub inotify_fallback: bool,
+    pub ebpf_probes: bool,
+    pub landlock_confine: bool,
+    /// Upper bound on queued scan jobs before non-exec work is rejected.
+    pub queue_depth: usize,
+}
+
+impl Default for RealtimeConfig {
+    fn default() -> Self {
+        Self {
+            watch_paths: vec![PathBuf::from("/home"), PathBuf::from("/tmp")],
+            fanotify: true,
+            inotify_fallback: true,
+            ebpf_probes: false,
+            landlock_confine: false,
+            queue_depth: 1024,
+        }
+    }
+}
+
+/// Power and thermal policy consumed by the `battery_saver` governor.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BatteryConfig {
+    pub strategy: BatteryStrategy,
+    pub thermal_guard: ThermalGuard,
+    /// Below this charge level (percent) scanning drops to a single worker.
+    pub low_battery_percent: u8,
+    /// Hottest thermal zone reading at which background work is deferred.
+    pub thermal_throttle_celsius: f32,
+    /// Hottest thermal zone reading at which scanning drops to a single worker.
+    pub thermal_critical_celsius: f32,
+    pub poll_interval_secs: u64,
+    /// sysfs roots, overridable so the governor can run against a fake tree.
+    pub power_supply_root: PathBuf,
+    pub thermal_root: PathBuf,
+}
+
+impl Default for BatteryConfig {
+    fn default() -> Self {
+        Self {
+            strategy: BatteryStrategy::Adaptive,
+            thermal_guard: ThermalGuard::Auto,
+            low_battery_percent: 20,
+            thermal_throttle_celsius: 75.0,
+            thermal_critical_celsius: 90.0,
+            poll_interval_secs: 30,
+            power_supply_root: PathBuf::from("/sys/class/power_supply"),
+            thermal_root: PathBuf::from("/sys/class/thermal"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BatteryStrategy {
+    /// Scale back on battery and low charge, run at full speed on AC.
+    Adaptive,
+    /// Ignore power source entirely.
+    Performance,
+    /// Always run as if on a low battery.
+    Saver,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ThermalGuard {
+    /// Throttle whenever readable thermal zones report high temperatures.
+    Auto,
+    Off,
+}
+
+/// Scheduled scan profiles and where their bookkeeping is persisted.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SchedulerConfig {
+    /// Last-run timestamps per profile, used to catch up after suspend.
+    pub state_path: PathBuf,
+    /// Fingerprints of scanned files, used by incremental profiles.
+    pub cache_path: PathBuf,
+    /// Per-rule match counts of shadow-mode rules.
+    pub shadow_hits_path: PathBuf,
+    pub profiles: Vec<ScanProfile>,
+}
+
+impl Default for SchedulerConfig {
+    fn default() -> Self {
+        Self {
+            state_path: PathBuf::from("/var/lib/av/state/scheduler.json"),
+            cache_path: PathBuf::from("/var/lib/av/state/scan-cache.json"),
+            shadow_hits_path: PathBuf::from("/var/lib/av/state/shadow-hits.json"),
+            profiles: vec![],
+        }
+    }
+}
+
+/// A named set of paths scanned on a cron or fixed-interval schedule.
+/// Exactly one of `cron` and `interval_secs` must be set.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ScanProfile {
+    pub name: String,
+    pub paths: Vec<PathBuf>,
+    /// Glob patterns; a matching directory is skipped with its subtree.
+    #[serde(default)]
+    pub excludes: Vec<String>,
+    #[serde(default = "default_profile_depth")]
+    pub max_depth: usize,
+    #[serde(default)]
+    pub priority: ScanPriority,
+    #[serde(default)]
+    pub mode: ScanMode,
+    #[serde(default)]
+    pub cron: Option<String>,
+    #[serde(default)]
+    pub interval_secs: Option<u64>,
+}
+
+fn default_profile_depth() -> usize {
+    16
+}
+
+/// Orders due profiles; everything below `High` is background work the
+/// power governor may defer.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScanPriority {
+    #[default]
+    Low,
+    Normal,
+    High,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScanMode {
+    Full,
+    /// Skip files whose fingerprint matches the scan cache.
+    #[default]
+    Incremental,
+}
+
+/// Sandboxing applied once the daemon has finished initializing.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SecurityConfig {
+    pub seccomp: SeccompMode,
+    pub seccomp_policy: PathBuf,
+    /// Writable under Landlock; everything else monitored is read-only.
+    pub quarantine_root: PathBuf,
+    pub log_dir: PathBuf,
+    /// Verified rule bundles, readable under Landlock.
+    pub rule_cache_dir: PathBuf,
+}
+
+impl Default for SecurityConfig {
+    fn default() -> Self {
+        Self {
+            seccomp: SeccompMode::Log,
+            seccomp_policy: PathBuf::from("/etc/charmedwoa-av/av-daemon.json"),
+            quarantine_root: PathBuf::from("/var/lib/av/quarantine"),
+            log_dir: PathBuf::from("/var/log/charmedwoa-av"),
+            rule_cache_dir: PathBuf::from("/var/lib/av/signatures"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SeccompMode {
+    Enforce,
+    /// Install the filter but turn every denial into `SCMP_ACT_LOG`.
+    Log,
+    Off,
+}
+
+/// Out-of-process scanning. Workers parse untrusted files without the
+/// broker's descriptors, filesystem access or syscall allowance.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WorkerConfig {
+    /// Scan in worker processes; `false` parses in the daemon itself.
+    pub isolate: bool,
+    /// A worker still busy after this long is killed and replaced.
+    pub scan_timeout_secs: u64,
+    /// Seccomp policy every worker enforces, whatever `security.seccomp`
+    /// says; a worker that cannot install it exits.
+    pub seccomp_policy: PathBuf,
+}
+
+impl Default for WorkerConfig {
+    fn default() -> Self {
+        Self {
+            isolate: true,
+            scan_timeout_secs: 30,
+            seccomp_policy: PathBuf::from("/etc/charmedwoa-av/av-scan-worker.json"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ShutdownConfig {
+    /// How long running scans may take to finish before they are aborted.
+    /// Keep below the unit's `TimeoutStopSec`.
+    pub drain_timeout_secs: u64,
+}
+
+impl Default for ShutdownConfig {
+    fn default() -> Self {
+        Self { drain_timeout_secs: 20 }
+    }
+}
+
+/// Background signature updates. Sources, keys and the bundle cache are
+/// read from `sources_config`, the file `av-cli signatures` uses.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UpdatesConfig {
+    pub enabled: bool,
+    pub sources_config: PathBuf,
+    pub interval_secs: u64,
+    /// Each poll is moved by up to this much either way so hosts booted
+    /// together do not hit the update server together.
+    pub jitter_secs: u64,
+    /// First retry delay after a failed poll, doubled per further failure.
+    pub retry_min_secs: u64,
+    pub retry_max_secs: u64,
+}
+
+impl Default for UpdatesConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            sources_config: PathBuf::from("/etc/charmedwoa-av/signatures.toml"),
+            interval_secs: 6 * 60 * 60,
+            jitter_secs: 30 * 60,
+            retry_min_secs: 60,
+            retry_max_secs: 60 * 60,
+        }
+    }
+}
+
+impl UpdatesConfig {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        anyhow::ensure!(self.interval_secs > 0, "updates.interval_secs must be positive");
+        anyhow::ensure!(
+            self.jitter_secs < self.interval_secs,
+            "updates.jitter_secs must be shorter than updates.interval_secs"
+        );
+        anyhow::ensure!(
+            0 < self.retry_min_secs && self.retry_min_secs <= self.retry_max_secs,
+            "updates.retry_min_secs must be positive and at most updates.retry_max_secs"
+        );
+        Ok(())
+    }
+}
+
+/// Local control socket queried by `av-cli`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct IpcConfig {
+    pub socket_path: PathBuf,
+}
+
+impl Default for IpcConfig {
+    fn default() -> Self {
+        Self {
+            socket_path: PathBuf::from(av_core::ipc::DEFAULT_SOCKET),
+        }
+    }
+}
+
+/// Load the daemon configuration. `AV_DAEMON_CONFIG` overrides the default
+/// path; a missing file yields the built-in defaults.
+pub fn load() -> anyhow::Result<DaemonConfig> {
+    let path = std::env::var_os("AV_DAEMON_CONFIG")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(DEFAULT_CONFIG_PATH));
+    let settings = config::Config::builder()
+        .add_source(config::File::from(path).required(false))
+        .build()?;
+    let cfg: DaemonConfig = settings.try_deserialize()?;
+    cfg.scanner.validate()?;
+    cfg.updates.validate()?;
+    Ok(cfg)
+}
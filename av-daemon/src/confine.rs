@@ -0,0 +1,205 @@
+//! Landlock filesystem confinement (`landlock_confine` feature).
+//!
+//! The ruleset is derived from the daemon configuration: monitored paths,
+//! rule caches and configuration are read-only; only the quarantine root,
+//! log directory, scheduler state, control socket directory and, with
+//! updates enabled, the signature cache may be written. Everything else is
+//! denied once the ruleset is in force. When scans run in worker processes
+//! the daemon binary and the system library directories are also
+//! executable so crashed workers can be re-spawned; the workers themselves
+//! drop every filesystem right with [`deny_all`].
+//!
+//! Landlock restricts the calling thread and the threads it spawns later,
+//! so [`apply`] must run before the async runtime starts its workers. Rights
+//! the running kernel does not know about are dropped (best effort), and the
+//! achieved level is reported instead of failing the start.
+
+use std::path::{Path, PathBuf};
+
+use landlock::{
+    path_beneath_rules, Access, AccessFs, LandlockStatus, RestrictionStatus, Ruleset, RulesetAttr,
+    RulesetCreatedAttr, RulesetError, RulesetStatus, ABI,
+};
+
+use crate::config::DaemonConfig;
+
+/// Newest ABI whose rights we request; older kernels get the subset they
+/// understand.
+const TARGET_ABI: ABI = ABI::V5;
+
+/// Resolver and name-service files needed for signature updates.
+const SYSTEM_READ_PATHS: &[&str] = &["/etc/resolv.conf", "/etc/hosts", "/etc/nsswitch.conf", "/etc/ssl/certs"];
+
+/// Dynamic loader and shared libraries needed to exec a scan worker.
+const SYSTEM_EXEC_PATHS: &[&str] = &["/lib", "/lib64", "/usr/lib", "/usr/lib64"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfinementLevel {
+    Full,
+    /// Enforced with only the rights the kernel's ABI supports.
+    Partial,
+    /// Kernel lacks Landlock or it is disabled at boot.
+    Unsupported,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ConfinementReport {
+    pub level: ConfinementLevel,
+    /// Landlock ABI version reported by the kernel, if any.
+    pub abi: Option<i32>,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct LandlockPaths {
+    pub read_only: Vec<PathBuf>,
+    pub read_write: Vec<PathBuf>,
+    /// Readable and executable.
+    pub executable: Vec<PathBuf>,
+}
+
+impl LandlockPaths {
+    /// `exe` is the daemon binary, re-executed to start scan workers.
+    pub fn from_config(cfg: &DaemonConfig, exe: &Path) -> Self {
+        let mut read_only: Vec<PathBuf> = cfg.realtime.watch_paths.clone();
+        read_only.extend(cfg.scheduler.profiles.iter().flat_map(|p| p.paths.iter().cloned()));
+        read_only.extend(cfg.scanner.signature_sources.iter().map(|s| s.local_cache.clone()));
+        if !cfg.updates.enabled {
+            read_only.push(cfg.security.rule_cache_dir.clone());
+        }
+        read_only.push(cfg.security.seccomp_policy.clone());
+        read_only.push(cfg.battery.power_supply_root.clone());
+        read_only.push(cfg.battery.thermal_root.clone());
+        // sysfs class entries are symlinks into the device tree.
+        read_only.push(PathBuf::from("/sys/devices"));
+        read_only.extend(SYSTEM_READ_PATHS.iter().map(PathBuf::from));
+
+        let mut read_write = vec![cfg.security.quarantine_root.clone(), cfg.security.log_dir.clone()];
+        // The update poller installs new generations into the rule cache.
+        if cfg.updates.enabled {
+            read_write.push(cfg.security.rule_cache_dir.clone());
+        }
+        read_write.extend(
+            [
+                &cfg.scheduler.state_path,
+                &cfg.scheduler.cache_path,
+                &cfg.scheduler.shadow_hits_path,
+                &cfg.ipc.socket_path,
+            ]
+                .into_iter()
+                .filter_map(|path| path.parent().map(PathBuf::from)),
+        );
+
+        let mut executable = vec![];
+        if cfg.workers.isolate {
+            read_only.push(cfg.workers.seccomp_policy.clone());
+            executable.push(exe.to_path_buf());
+            executable.extend(SYSTEM_EXEC_PATHS.iter().map(PathBuf::from));
+        }
+
+        read_only.sort();
+        read_only.dedup();
+        read_write.sort();
+        read_write.dedup();
+        Self {
+            read_only,
+            read_write,
+            executable,
+        }
+    }
+}
+
+/// Build and enforce the ruleset on the calling thread. Paths that do not
+/// exist are skipped rather than failing the ruleset.
+pub fn apply(paths: &LandlockPaths) -> Result<ConfinementReport, RulesetError> {
+    let read = AccessFs::ReadFile | AccessFs::ReadDir;
+    let status = Ruleset::default()
+        .handle_access(AccessFs::from_all(TARGET_ABI))?
+        .create()?
+        .add_rules(path_beneath_rules(&paths.read_only, read))?
+        .add_rules(path_beneath_rules(&paths.read_write, AccessFs::from_all(TARGET_ABI)))?
+        .add_rules(path_beneath_rules(&paths.executable, read | AccessFs::Execute))?
+        .restrict_self()?;
+    Ok(report(&status))
+}
+
+/// Revoke all filesystem access on the calling thread. Descriptors that are
+/// already open, or received later over a socket, keep working.
+pub fn deny_all() -> Result<ConfinementReport, RulesetError> {
+    let status = Ruleset::default()
+        .handle_access(AccessFs::from_all(TARGET_ABI))?
+        .create()?
+        .restrict_self()?;
+    Ok(report(&status))
+}
+
+fn report(status: &RestrictionStatus) -> ConfinementReport {
+    let level = match status.ruleset {
+        RulesetStatus::FullyEnforced => ConfinementLevel::Full,
+        RulesetStatus::PartiallyEnforced => ConfinementLevel::Partial,
+        RulesetStatus::NotEnforced => ConfinementLevel::Unsupported,
+    };
+    let abi = match status.landlock {
+        LandlockStatus::Available { effective_abi, kernel_abi } => Some(kernel_abi.unwrap_or(effective_abi as i32)),
+        LandlockStatus::NotEnabled | LandlockStatus::NotImplemented => None,
+    };
+    ConfinementReport { level, abi }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn derives_paths_from_config() {
+        let cfg = DaemonConfig::default();
+        let paths = LandlockPaths::from_config(&cfg, Path::new("/usr/lib/charmedwoa-av/av-daemon"));
+        assert!(paths.read_write.contains(&PathBuf::from("/var/lib/av/quarantine")));
+        assert!(paths.read_write.contains(&PathBuf::from("/var/log/charmedwoa-av")));
+        assert!(paths.read_write.contains(&PathBuf::from("/var/lib/av/state")));
+        assert!(paths.read_write.contains(&PathBuf::from("/var/lib/av/signatures")));
+        assert!(paths.read_write.contains(&PathBuf::from("/run/charmedwoa-av")));
+        assert!(!paths.read_only.iter().any(|p| p == &PathBuf::from("/var/lib/av/quarantine")));
+        assert!(paths.executable.contains(&PathBuf::from("/usr/lib/charmedwoa-av/av-daemon")));
+
+        let mut cfg = cfg;
+        cfg.updates.enabled = false;
+        let paths = LandlockPaths::from_config(&cfg, Path::new("/usr/lib/charmedwoa-av/av-daemon"));
+        assert!(paths.read_only.contains(&PathBuf::from("/var/lib/av/signatures")));
+        assert!(!paths.read_write.contains(&PathBuf::from("/var/lib/av/signatures")));
+    }
+
+    #[test]
+    fn confined_thread_cannot_escape_ruleset() {
+        let dir = tempfile::tempdir().unwrap();
+        let monitored = dir.path().join("home");
+        let quarantine = dir.path().join("quarantine");
+        let outside = dir.path().join("outside");
+        for d in [&monitored, &quarantine, &outside] {
+            fs::create_dir_all(d).unwrap();
+        }
+        fs::write(monitored.join("sample"), b"data").unwrap();
+        fs::write(outside.join("secret"), b"data").unwrap();
+
+        let paths = LandlockPaths {
+            read_only: vec![monitored.clone()],
+            read_write: vec![quarantine.clone()],
+            executable: vec![],
+        };
+        // Landlock is per-thread; confine a scratch thread, not the harness.
+        std::thread::spawn(move || {
+            let report = apply(&paths).unwrap();
+            if report.level == ConfinementLevel::Unsupported {
+                return;
+            }
+            assert!(report.abi.is_some());
+            assert!(fs::read(monitored.join("sample")).is_ok());
+            assert!(fs::write(monitored.join("dropped"), b"x").is_err());
+            assert!(fs::write(quarantine.join("item"), b"x").is_ok());
+            assert!(fs::read(outside.join("secret")).is_err());
+        })
+        .join()
+        .unwrap();
+    }
+}
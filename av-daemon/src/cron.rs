@@ -0,0 +1,175 @@
+//! Minimal five-field cron expressions for scheduled scans.
+//!
+//! Supports `*`, single values, ranges, lists and `/step` in the usual
+//! `minute hour day-of-month month day-of-week` order. As in Vixie cron, a
+//! day matches if either restricted day field matches.
+
+use std::str::FromStr;
+
+use chrono::{DateTime, Datelike, Duration, LocalResult, NaiveDateTime, TimeZone, Timelike};
+
+/// Upper bound on search steps; plenty for any valid expression (the worst
+/// case, Feb 29 on a given weekday, recurs within 28 years of days).
+const MAX_STEPS: usize = 200_000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CronSchedule {
+    minutes: u64,
+    hours: u64,
+    days_of_month: u64,
+    months: u64,
+    days_of_week: u64,
+    dom_restricted: bool,
+    dow_restricted: bool,
+}
+
+impl FromStr for CronSchedule {
+    type Err = anyhow::Error;
+
+    fn from_str(expr: &str) -> anyhow::Result<Self> {
+        let fields: Vec<&str> = expr.split_whitespace().collect();
+        anyhow::ensure!(fields.len() == 5, "cron expression `{expr}` must have five fields");
+        let mut days_of_week = parse_field(fields[4], 0, 7)?;
+        // Both 0 and 7 mean Sunday.
+        if days_of_week & (1 << 7) != 0 {
+            days_of_week = (days_of_week | 1) & !(1 << 7);
+        }
+        Ok(Self {
+            minutes: parse_field(fields[0], 0, 59)?,
+            hours: parse_field(fields[1], 0, 23)?,
+            days_of_month: parse_field(fields[2], 1, 31)?,
+            months: parse_field(fields[3], 1, 12)?,
+            days_of_week,
+            dom_restricted: fields[2] != "*",
+            dow_restricted: fields[4] != "*",
+        })
+    }
+}
+
+impl CronSchedule {
+    /// First matching minute strictly after `after`, in `after`'s zone.
+    /// Local times skipped by a DST jump are passed over.
+    pub fn next_after<Tz: TimeZone>(&self, after: &DateTime<Tz>) -> Option<DateTime<Tz>> {
+        let tz = after.timezone();
+        let start = after.naive_local().with_second(0)?.with_nanosecond(0)?;
+        let mut t = start + Duration::minutes(1);
+        for _ in 0..MAX_STEPS {
+            if !bit(self.months, t.month()) {
+                t = first_of_next_month(t)?;
+            } else if !self.day_matches(&t) {
+                t = t.date().succ_opt()?.and_hms_opt(0, 0, 0)?;
+            } else if !bit(self.hours, t.hour()) {
+                t = t.with_minute(0)? + Duration::hours(1);
+            } else if !bit(self.minutes, t.minute()) {
+                t += Duration::minutes(1);
+            } else {
+                match tz.from_local_datetime(&t) {
+                    LocalResult::Single(dt) => return Some(dt),
+                    LocalResult::Ambiguous(earliest, _) => return Some(earliest),
+                    LocalResult::None => t += Duration::minutes(1),
+                }
+            }
+        }
+        None
+    }
+
+    fn day_matches(&self, t: &NaiveDateTime) -> bool {
+        let dom = bit(self.days_of_month, t.day());
+        let dow = bit(self.days_of_week, t.weekday().num_days_from_sunday());
+        match (self.dom_restricted, self.dow_restricted) {
+            (true, true) => dom || dow,
+            (true, false) => dom,
+            (false, true) => dow,
+            (false, false) => true,
+        }
+    }
+}
+
+fn bit(mask: u64, value: u32) -> bool {
+    mask & (1 << value) != 0
+}
+
+fn first_of_next_month(t: NaiveDateTime) -> Option<NaiveDateTime> {
+    let (year, month) = if t.month() == 12 {
+        (t.year() + 1, 1)
+    } else {
+        (t.year(), t.month() + 1)
+    };
+    t.date()
+        .with_day(1)?
+        .with_year(year)?
+        .with_month(month)?
+        .and_hms_opt(0, 0, 0)
+}
+
+fn parse_field(field: &str, min: u32, max: u32) -> anyhow::Result<u64> {
+    let mut mask = 0u64;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u32>()?),
+            None => (part, 1),
+        };
+        anyhow::ensure!(step > 0, "cron step must be positive in `{field}`");
+        let (lo, hi) = if range == "*" {
+            (min, max)
+        } else if let Some((lo, hi)) = range.split_once('-') {
+            (lo.parse()?, hi.parse()?)
+        } else {
+            let value: u32 = range.parse()?;
+            // `5/15` means "from 5 to the end, every 15".
+            (value, if part.contains('/') { max } else { value })
+        };
+        anyhow::ensure!(
+            lo >= min && hi <= max && lo <= hi,
+            "cron field `{field}` out of range {min}-{max}"
+        );
+        for value in (lo..=hi).step_by(step as usize) {
+            mask |= 1 << value;
+        }
+    }
+    Ok(mask)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    fn at(s: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc)
+    }
+
+    #[test]
+    fn nightly_schedule_rolls_to_next_day() {
+        let cron: CronSchedule = "30 2 * * *".parse().unwrap();
+        assert_eq!(
+            cron.next_after(&at("2026-03-10T01:00:00Z")),
+            Some(at("2026-03-10T02:30:00Z"))
+        );
+        assert_eq!(
+            cron.next_after(&at("2026-03-10T02:30:00Z")),
+            Some(at("2026-03-11T02:30:00Z"))
+        );
+        assert_eq!(
+            cron.next_after(&at("2026-12-31T23:59:00Z")),
+            Some(at("2027-01-01T02:30:00Z"))
+        );
+    }
+
+    #[test]
+    fn steps_lists_and_weekdays() {
+        let cron: CronSchedule = "*/20 9-17 * * 1-5".parse().unwrap();
+        // 2026-03-14 is a Saturday.
+        assert_eq!(
+            cron.next_after(&at("2026-03-13T17:45:00Z")),
+            Some(at("2026-03-16T09:00:00Z"))
+        );
+        let sunday: CronSchedule = "0 4 * * 7".parse().unwrap();
+        assert_eq!(
+            sunday.next_after(&at("2026-03-10T00:00:00Z")),
+            Some(at("2026-03-15T04:00:00Z"))
+        );
+        assert!("61 * * * *".parse::<CronSchedule>().is_err());
+        assert!("* * *".parse::<CronSchedule>().is_err());
+    }
+}
@@ -0,0 +1,490 @@
+//! Bounded scan queue drained by a resizable set of scan tasks.
+//!
+//! Producers (real-time monitoring, scheduled scans) submit jobs tagged with
+//! a [`ScanClass`]. The active [`ScanBudget`] decides how many scans may run
+//! concurrently, how deep the queue may grow and whether background work is
+//! held back. On-access scans are never deferred behind background work.
+//!
+//! On shutdown the queue is closed and emptied; scans already running get a
+//! deadline to finish before they are aborted.
+//!
+//! Scans themselves run on a [`ScanBackend`]: isolated worker processes in
+//! production, or the in-process [`Scanner`] when isolation is disabled.
+
+use std::collections::{HashMap, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use parking_lot::Mutex;
+use tokio::sync::Notify;
+use tokio::task::AbortHandle;
+use tracing::{debug, info, warn};
+
+use av_core::cache::{FileFingerprint, ScanCache};
+use av_core::signatures::{RuleSet, ShadowHits};
+use av_core::{RecommendedAction, ScanOutcome, Scanner};
+
+/// Something that can turn a path into a verdict.
+#[async_trait]
+pub trait ScanBackend: Send + Sync {
+    async fn scan(&self, path: &Path) -> anyhow::Result<ScanOutcome>;
+
+    /// Evaluate scans started from now on against `rules`.
+    fn set_rules(&self, rules: Arc<RuleSet>);
+}
+
+#[async_trait]
+impl ScanBackend for Scanner {
+    async fn scan(&self, path: &Path) -> anyhow::Result<ScanOutcome> {
+        self.scan_path(path).await
+    }
+
+    fn set_rules(&self, rules: Arc<RuleSet>) {
+        Scanner::set_rules(self, rules);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScanClass {
+    /// A file was opened or modified while real-time monitoring is active.
+    OnAccess,
+    /// Scheduled or opportunistic work nobody is waiting on.
+    Background,
+}
+
+#[derive(Debug, Clone)]
+pub struct ScanJob {
+    pub path: PathBuf,
+    pub class: ScanClass,
+}
+
+/// Resource envelope the dispatcher operates within.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScanBudget {
+    pub workers: usize,
+    pub queue_depth: usize,
+    pub defer_background: bool,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum DispatchError {
+    #[error("scan queue is full ({0} jobs)")]
+    QueueFull(usize),
+    #[error("scan queue is closed for shutdown")]
+    Closed,
+}
+
+/// What [`Dispatcher::shutdown`] had to throw away.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DrainReport {
+    /// Queued jobs that never started.
+    pub dropped: usize,
+    /// Running scans aborted at the deadline.
+    pub cancelled: usize,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct QueueStats {
+    pub queued: usize,
+    pub running: usize,
+    pub budget: ScanBudget,
+}
+
+#[derive(Clone)]
+pub struct Dispatcher {
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    state: Mutex<State>,
+    /// Every completed scan is recorded so incremental runs can skip it.
+    cache: Arc<Mutex<ScanCache>>,
+    /// Matches of shadow-mode rules, which never affect a verdict.
+    shadow_hits: Arc<Mutex<ShadowHits>>,
+    /// Wakes the pump when work arrives, a slot frees up or the budget grows.
+    work: Notify,
+    /// Wakes producers waiting for queue capacity.
+    space: Notify,
+    /// Wakes shutdown waiting for running scans to finish.
+    idle: Notify,
+}
+
+struct State {
+    budget: ScanBudget,
+    urgent: VecDeque<ScanJob>,
+    background: VecDeque<ScanJob>,
+    /// Running scans by id. The abort handle is filled in right after the
+    /// task is spawned.
+    running: HashMap<u64, Option<AbortHandle>>,
+    next_id: u64,
+    closed: bool,
+}
+
+impl State {
+    fn queued(&self) -> usize {
+        self.urgent.len() + self.background.len()
+    }
+}
+
+impl Dispatcher {
+    pub fn new(budget: ScanBudget, cache: Arc<Mutex<ScanCache>>, shadow_hits: Arc<Mutex<ShadowHits>>) -> Self {
+        Self {
+            shared: Arc::new(Shared {
+                cache,
+                shadow_hits,
+                state: Mutex::new(State {
+                    budget,
+                    urgent: VecDeque::new(),
+                    background: VecDeque::new(),
+                    running: HashMap::new(),
+                    next_id: 0,
+                    closed: false,
+                }),
+                work: Notify::new(),
+                space: Notify::new(),
+                idle: Notify::new(),
+            }),
+        }
+    }
+
+    /// Queue a job, or reject it once the queue is full.
+    pub fn submit(&self, job: ScanJob) -> Result<(), DispatchError> {
+        {
+            let mut state = self.shared.state.lock();
+            if state.closed {
+                return Err(DispatchError::Closed);
+            }
+            let queued = state.queued();
+            if queued >= state.budget.queue_depth {
+                return Err(DispatchError::QueueFull(queued));
+            }
+            match job.class {
+                ScanClass::OnAccess => state.urgent.push_back(job),
+                ScanClass::Background => state.background.push_back(job),
+            }
+        }
+        self.shared.work.notify_one();
+        Ok(())
+    }
+
+    /// Queue a job, waiting for room instead of failing when the queue is
+    /// full. Intended for producers that can apply backpressure; only
+    /// fails once the dispatcher is shutting down.
+    pub async fn submit_wait(&self, job: ScanJob) -> Result<(), DispatchError> {
+        loop {
+            let space = self.shared.space.notified();
+            tokio::pin!(space);
+            space.as_mut().enable();
+            match self.submit(job.clone()) {
+                Err(DispatchError::QueueFull(_)) => space.await,
+                result => return result,
+            }
+        }
+    }
+
+    pub fn budget(&self) -> ScanBudget {
+        self.shared.state.lock().budget
+    }
+
+    /// Swap in a new budget. Shrinking takes effect as running scans
+    /// finish; nothing in flight is interrupted.
+    pub fn set_budget(&self, budget: ScanBudget) {
+        self.shared.state.lock().budget = budget;
+        self.shared.work.notify_one();
+        self.shared.space.notify_waiters();
+    }
+
+    pub fn stats(&self) -> QueueStats {
+        let state = self.shared.state.lock();
+        QueueStats {
+            queued: state.queued(),
+            running: state.running.len(),
+            budget: state.budget,
+        }
+    }
+
+    /// Drain the queue until shutdown, running at most `budget.workers`
+    /// scans at a time.
+    pub async fn run(&self, backend: Arc<dyn ScanBackend>) {
+        loop {
+            let work = self.shared.work.notified();
+            while let Some((id, job)) = self.next_job() {
+                let dispatcher = self.clone();
+                let backend = Arc::clone(&backend);
+                let task = tokio::spawn(async move {
+                    execute(backend.as_ref(), &dispatcher.shared, &job).await;
+                    dispatcher.finish(id);
+                });
+                // A scan that already finished has removed its own entry.
+                if let Some(slot) = self.shared.state.lock().running.get_mut(&id) {
+                    *slot = Some(task.abort_handle());
+                }
+            }
+            if self.shared.state.lock().closed {
+                return;
+            }
+            work.await;
+        }
+    }
+
+    /// Stop accepting jobs, drop everything still queued and give running
+    /// scans until `deadline` to finish before aborting them.
+    pub async fn shutdown(&self, deadline: Duration) -> DrainReport {
+        let dropped = {
+            let mut state = self.shared.state.lock();
+            state.closed = true;
+            let dropped = state.queued();
+            state.urgent.clear();
+            state.background.clear();
+            dropped
+        };
+        self.shared.work.notify_one();
+        self.shared.space.notify_waiters();
+
+        let drained = tokio::time::timeout(deadline, async {
+            loop {
+                let idle = self.shared.idle.notified();
+                tokio::pin!(idle);
+                idle.as_mut().enable();
+                if self.shared.state.lock().running.is_empty() {
+                    return;
+                }
+                idle.await;
+            }
+        })
+        .await;
+
+        let mut state = self.shared.state.lock();
+        let cancelled = if drained.is_ok() { 0 } else { state.running.len() };
+        for handle in state.running.drain().filter_map(|(_, handle)| handle) {
+            handle.abort();
+        }
+        DrainReport { dropped, cancelled }
+    }
+
+    fn next_job(&self) -> Option<(u64, ScanJob)> {
+        let job = {
+            let mut state = self.shared.state.lock();
+            if state.closed || state.running.len() >= state.budget.workers.max(1) {
+                return None;
+            }
+            let job = match state.urgent.pop_front() {
+                Some(job) => Some(job),
+                None if !state.budget.defer_background => state.background.pop_front(),
+                None => None,
+            };
+            job.map(|job| {
+                let id = state.next_id;
+                state.next_id += 1;
+                state.running.insert(id, None);
+                (id, job)
+            })
+        };
+        if job.is_some() {
+            self.shared.space.notify_waiters();
+        }
+        job
+    }
+
+    fn finish(&self, id: u64) {
+        self.shared.state.lock().running.remove(&id);
+        self.shared.work.notify_one();
+        self.shared.idle.notify_waiters();
+    }
+}
+
+async fn execute(backend: &dyn ScanBackend, shared: &Shared, job: &ScanJob) {
+    // Fingerprint before reading so a write racing the scan invalidates the
+    // cache entry instead of being masked by it.
+    let fingerprint = tokio::fs::metadata(&job.path)
+        .await
+        .ok()
+        .map(|meta| FileFingerprint::from_metadata(&meta));
+    let result = backend.scan(&job.path).await;
+    if let (Ok(outcome), Some(fingerprint)) = (&result, fingerprint) {
+        shared
+            .cache
+            .lock()
+            .record(job.path.clone(), fingerprint, outcome.recommended_action.clone());
+    }
+    if let Ok(outcome) = &result {
+        for hit in outcome.signatures.iter().filter(|m| m.shadow) {
+            info!(path = %outcome.path, rule = %hit.rule, namespace = %hit.namespace, "shadow rule matched");
+        }
+        shared.shadow_hits.lock().record(&outcome.signatures);
+    }
+    match result {
+        Ok(outcome) if outcome.recommended_action == RecommendedAction::Allow => {
+            debug!(path = %outcome.path, class = ?job.class, "scan clean");
+        }
+        Ok(outcome) => {
+            warn!(
+                path = %outcome.path,
+                class = ?job.class,
+                action = ?outcome.recommended_action,
+                signatures = outcome.signatures.len(),
+                "scan flagged file"
+            );
+        }
+        Err(err) => warn!(path = %job.path.display(), error = %format!("{err:#}"), "scan failed"),
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use av_core::engine::{SignatureMatch, SignatureMatcher};
+    use av_core::signatures::{AbAssignment, RuleMetadata, RuleState};
+    use chrono::Utc;
+
+    fn job(path: &str, class: ScanClass) -> ScanJob {
+        ScanJob {
+            path: PathBuf::from(path),
+            class,
+        }
+    }
+
+    #[test]
+    fn deferred_background_work_waits_behind_budget() {
+        let dispatcher = Dispatcher::new(
+            ScanBudget {
+                workers: 4,
+                queue_depth: 2,
+                defer_background: true,
+            },
+            Arc::new(Mutex::new(ScanCache::default())),
+            Arc::default(),
+        );
+        dispatcher.submit(job("/home/a", ScanClass::Background)).unwrap();
+        dispatcher.submit(job("/home/b", ScanClass::OnAccess)).unwrap();
+        assert!(matches!(
+            dispatcher.submit(job("/home/c", ScanClass::OnAccess)),
+            Err(DispatchError::QueueFull(2))
+        ));
+
+        assert_eq!(dispatcher.next_job().unwrap().1.class, ScanClass::OnAccess);
+        assert!(dispatcher.next_job().is_none());
+
+        dispatcher.set_budget(ScanBudget {
+            defer_background: false,
+            ..dispatcher.budget()
+        });
+        assert_eq!(dispatcher.next_job().unwrap().1.class, ScanClass::Background);
+        assert_eq!(dispatcher.stats().running, 2);
+    }
+
+    /// Never finishes, standing in for a wedged parser.
+    struct Stalled;
+
+    #[async_trait]
+    impl ScanBackend for Stalled {
+        async fn scan(&self, _path: &Path) -> anyhow::Result<ScanOutcome> {
+            std::future::pending().await
+        }
+
+        fn set_rules(&self, _rules: Arc<RuleSet>) {}
+    }
+
+    #[tokio::test]
+    async fn shutdown_drops_queue_and_cancels_overdue_scans() {
+        let dispatcher = Dispatcher::new(
+            ScanBudget {
+                workers: 1,
+                queue_depth: 8,
+                defer_background: false,
+            },
+            Arc::new(Mutex::new(ScanCache::default())),
+            Arc::default(),
+        );
+        dispatcher.submit(job("/home/a", ScanClass::OnAccess)).unwrap();
+        dispatcher.submit(job("/home/b", ScanClass::Background)).unwrap();
+        let pump = tokio::spawn({
+            let dispatcher = dispatcher.clone();
+            async move { dispatcher.run(Arc::new(Stalled)).await }
+        });
+        while dispatcher.stats().running == 0 {
+            tokio::task::yield_now().await;
+        }
+
+        let report = dispatcher.shutdown(Duration::from_millis(20)).await;
+        assert_eq!(report, DrainReport { dropped: 1, cancelled: 1 });
+        assert_eq!(dispatcher.stats().running, 0);
+        assert!(matches!(
+            dispatcher.submit(job("/home/c", ScanClass::OnAccess)),
+            Err(DispatchError::Closed)
+        ));
+        pump.await.unwrap();
+    }
+
+    /// Reports a fixed set of `vendor/<rule>` matches for every file.
+    pub(crate) struct Matches(pub &'static [&'static str]);
+
+    impl SignatureMatcher for Matches {
+        fn matches(&self, _data: &[u8]) -> Vec<SignatureMatch> {
+            let hit = |rule: &&str| SignatureMatch {
+                rule: rule.to_string(),
+                namespace: "vendor".into(),
+                metadata: serde_json::Value::Null,
+                shadow: false,
+            };
+            self.0.iter().map(hit).collect()
+        }
+    }
+
+    /// Rules as loaded on a host sampled for nothing but the baseline.
+    pub(crate) fn rule_set(rules: &[(&str, RuleState)]) -> Arc<RuleSet> {
+        let rules = rules.iter().map(|&(id, state)| RuleMetadata {
+            id: id.into(),
+            description: String::new(),
+            provenance: "vendor 1.0.0".into(),
+            ab_bucket: None,
+            created_at: Utc::now(),
+            expires_at: None,
+            tags: vec![],
+            state,
+        });
+        Arc::new(RuleSet::load(rules, Utc::now(), &AbAssignment::default()))
+    }
+
+    #[tokio::test]
+    async fn shadow_matches_are_counted_but_not_acted_on() {
+        let scanner = Scanner::with_matcher(Default::default(), Box::new(Matches(&["new-packer", "retired"]))).unwrap();
+        scanner.set_rules(rule_set(&[
+            ("vendor/new-packer", RuleState::Experimental),
+            ("vendor/retired", RuleState::Disabled),
+        ]));
+
+        let sample = tempfile::NamedTempFile::new().unwrap();
+        let cache = Arc::new(Mutex::new(ScanCache::default()));
+        let shadow_hits = Arc::new(Mutex::new(ShadowHits::default()));
+        let dispatcher = Dispatcher::new(
+            ScanBudget {
+                workers: 1,
+                queue_depth: 8,
+                defer_background: false,
+            },
+            Arc::clone(&cache),
+            Arc::clone(&shadow_hits),
+        );
+        let pump = tokio::spawn({
+            let dispatcher = dispatcher.clone();
+            async move { dispatcher.run(Arc::new(scanner)).await }
+        });
+        dispatcher
+            .submit(ScanJob {
+                path: sample.path().to_owned(),
+                class: ScanClass::OnAccess,
+            })
+            .unwrap();
+        while cache.lock().get(sample.path()).is_none() {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+
+        assert_eq!(shadow_hits.lock().rules, [("vendor/new-packer".to_string(), 1)].into());
+        assert_eq!(cache.lock().get(sample.path()).unwrap().action, RecommendedAction::Allow);
+        dispatcher.shutdown(Duration::from_secs(1)).await;
+        pump.await.unwrap();
+    }
+}
@@ -0,0 +1,210 @@
+//! Control socket serving `av-cli` queries; see [`av_core::ipc`].
+//!
+//! The socket is bound before the seccomp filter goes on, since only
+//! accepting on an existing socket is allowed afterwards. It is created
+//! group-accessible (0660) so members of the daemon's group can query it
+//! without root.
+
+use std::fs;
+use std::io::ErrorKind;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use chrono::Utc;
+use parking_lot::Mutex;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tracing::{debug, warn};
+
+use av_core::ipc::{Request, Response, UpdateStatus, MAX_REQUEST};
+use av_core::signatures::ShadowHits;
+use av_core::telemetry::{MetricPoint, TelemetryBatch};
+
+use crate::dispatch::Dispatcher;
+use crate::shutdown::ShutdownSignal;
+use crate::updates::ActiveRules;
+
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Bind `path`, replacing a socket left behind by an earlier run.
+pub fn bind(path: &Path) -> anyhow::Result<UnixListener> {
+    match fs::remove_file(path) {
+        Err(err) if err.kind() != ErrorKind::NotFound => {
+            return Err(err).with_context(|| format!("removing stale socket {}", path.display()))
+        }
+        _ => {}
+    }
+    let listener = UnixListener::bind(path).with_context(|| format!("binding {}", path.display()))?;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o660))?;
+    Ok(listener)
+}
+
+/// Daemon state the control socket reports on.
+#[derive(Clone)]
+pub struct Control {
+    pub started: Instant,
+    pub dispatcher: Dispatcher,
+    pub rules: Arc<ActiveRules>,
+    pub updates: Arc<Mutex<UpdateStatus>>,
+    pub shadow_hits: Arc<Mutex<ShadowHits>>,
+}
+
+impl Control {
+    pub async fn serve(self, listener: UnixListener, mut shutdown: ShutdownSignal) {
+        loop {
+            tokio::select! {
+                accepted = listener.accept() => match accepted {
+                    Ok((stream, _)) => {
+                        let control = self.clone();
+                        tokio::spawn(async move {
+                            match tokio::time::timeout(CLIENT_TIMEOUT, control.handle(stream)).await {
+                                Ok(Err(err)) => debug!(error = %err, "control client failed"),
+                                Err(_) => debug!("control client timed out"),
+                                Ok(Ok(())) => {}
+                            }
+                        });
+                    }
+                    Err(err) => {
+                        warn!(error = %err, "control socket accept failed");
+                        tokio::time::sleep(Duration::from_secs(1)).await;
+                    }
+                },
+                _ = shutdown.wait() => return,
+            }
+        }
+    }
+
+    async fn handle(&self, stream: UnixStream) -> anyhow::Result<()> {
+        let (read, mut write) = stream.into_split();
+        let mut line = String::new();
+        BufReader::new(read.take(MAX_REQUEST as u64)).read_line(&mut line).await?;
+        let response = match serde_json::from_str(&line) {
+            Ok(request) => self.respond(request),
+            Err(err) => Response::Error(format!("malformed request: {err}")),
+        };
+        let mut reply = serde_json::to_vec(&response)?;
+        reply.push(b'\n');
+        write.write_all(&reply).await?;
+        Ok(())
+    }
+
+    pub fn respond(&self, request: Request) -> Response {
+        match request {
+            Request::UpdateStatus => Response::UpdateStatus(self.update_status()),
+            Request::Metrics => Response::Metrics(self.metrics()),
+            Request::ShadowHits => Response::ShadowHits(self.shadow_hits.lock().rules.clone()),
+        }
+    }
+
+    fn update_status(&self) -> UpdateStatus {
+        let mut status = self.updates.lock().clone();
+        status.generation = self.rules.generation();
+        status.rules_loaded = self.rules.get().rules.len();
+        status
+    }
+
+    fn metrics(&self) -> TelemetryBatch {
+        let now = Utc::now();
+        let stats = self.dispatcher.stats();
+        let status = self.update_status();
+        let mut batch = TelemetryBatch::new();
+        let mut point = |name: &str, value: f64| {
+            batch.push(MetricPoint {
+                name: name.to_string(),
+                value,
+                timestamp: now,
+            })
+        };
+        point("daemon.uptime_secs", self.started.elapsed().as_secs_f64());
+        point("scans.queued", stats.queued as f64);
+        point("scans.running", stats.running as f64);
+        point("signatures.rules_loaded", status.rules_loaded as f64);
+        point("signatures.shadow_rules", self.rules.get().shadow.len() as f64);
+        if let Some(generation) = status.generation {
+            point("signatures.generation", generation as f64);
+        }
+        point("updates.consecutive_failures", status.consecutive_failures as f64);
+        if let Some(at) = status.last_success {
+            point("updates.last_success_timestamp", at.timestamp() as f64);
+            point("updates.since_last_success_secs", (now - at).num_seconds() as f64);
+        }
+        if let Some(at) = status.last_attempt {
+            point("updates.last_attempt_timestamp", at.timestamp() as f64);
+        }
+        batch
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use av_core::signatures::AbAssignment;
+    use av_core::engine::SignatureMatch;
+    use av_signatures::store::BundleStore;
+
+    use crate::dispatch::ScanBudget;
+
+    #[tokio::test]
+    async fn answers_update_status_metrics_and_shadow_hits() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("daemon.sock");
+        let budget = ScanBudget {
+            workers: 1,
+            queue_depth: 8,
+            defer_background: false,
+        };
+        let control = Control {
+            started: Instant::now(),
+            dispatcher: Dispatcher::new(budget, Default::default(), Default::default()),
+            rules: Arc::new(ActiveRules::load(BundleStore::new(dir.path(), 1), AbAssignment::default())),
+            updates: Arc::new(Mutex::new(UpdateStatus {
+                enabled: true,
+                last_failure: Some("fetching https://updates.example: timed out".into()),
+                consecutive_failures: 2,
+                ..Default::default()
+            })),
+            shadow_hits: Default::default(),
+        };
+        let shadow_hits = Arc::clone(&control.shadow_hits);
+        let (_trigger, shutdown) = crate::shutdown::channel();
+        tokio::spawn(control.serve(bind(&socket).unwrap(), shutdown));
+        assert_eq!(fs::metadata(&socket).unwrap().permissions().mode() & 0o777, 0o660);
+
+        let query = |request| {
+            let socket = socket.clone();
+            tokio::task::spawn_blocking(move || av_core::ipc::request(&socket, &request).unwrap())
+        };
+        match query(Request::UpdateStatus).await.unwrap() {
+            Response::UpdateStatus(status) => {
+                assert_eq!(status.consecutive_failures, 2);
+                assert!(status.last_failure.unwrap().contains("timed out"));
+                assert_eq!((status.generation, status.rules_loaded), (None, 0));
+            }
+            other => panic!("unexpected response {other:?}"),
+        }
+        match query(Request::Metrics).await.unwrap() {
+            Response::Metrics(batch) => {
+                let failures = batch.points.iter().find(|p| p.name == "updates.consecutive_failures").unwrap();
+                assert_eq!(failures.value, 2.0);
+                assert!(!batch.points.iter().any(|p| p.name == "updates.last_success_timestamp"));
+            }
+            other => panic!("unexpected response {other:?}"),
+        }
+
+        // Counts are served live, not from the file saved on the next tick.
+        let hit = SignatureMatch {
+            namespace: "vendor".into(),
+            rule: "new-packer".into(),
+            metadata: serde_json::Value::Null,
+            shadow: true,
+        };
+        shadow_hits.lock().record(&[hit.clone(), hit]);
+        match query(Request::ShadowHits).await.unwrap() {
+            Response::ShadowHits(hits) => assert_eq!(hits.get("vendor/new-packer"), Some(&2)),
+            other => panic!("unexpected response {other:?}"),
+        }
+    }
+}
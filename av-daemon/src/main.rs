@@ -3,40 +3,205 @@
 //! Runs unprivileged by default. Capabilities, if needed, are attached via
 //! systemd unit overrides and documented in the security guide.
 
-use std::time::Duration;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use anyhow::Context;
-use tokio::signal;
-use tokio::sync::Notify;
-use tracing::{error, info};
+use parking_lot::Mutex;
+use tracing::{info, warn};
 
-use av_core::{config::ScannerConfig, monitoring::MonitoringReport, Scanner};
+use av_core::cache::ScanCache;
+use av_core::ipc::UpdateStatus;
+use av_core::signatures::{AbAssignment, ShadowHits};
+use av_core::{monitoring::MonitoringReport, Scanner};
+use av_signatures::Updater;
 
+use crate::config::DaemonConfig;
+use crate::dispatch::{Dispatcher, ScanBackend, ScanBudget};
+use crate::shutdown::ShutdownSignal;
+
+mod config;
+#[cfg(feature = "landlock_confine")]
+mod confine;
+mod cron;
+mod dispatch;
+mod ipc;
+mod notify;
+#[cfg(feature = "battery_saver")]
+mod power;
+mod scheduler;
+#[cfg(feature = "seccomp_filter")]
+mod seccomp;
 mod security;
+mod shutdown;
+mod updates;
+mod worker;
 
-#[tokio::main]
-async fn main() -> anyhow::Result<()> {
+fn main() -> anyhow::Result<()> {
     init_logging();
 
-    let config = load_config().context("failed to load daemon config")?;
-    let scanner = Scanner::new(config.clone()).context("failed to init scanner")?;
+    if std::env::args_os().nth(1).is_some_and(|arg| arg == worker::WORKER_FLAG) {
+        return worker::worker_main();
+    }
+    if std::env::args_os().nth(1).is_some_and(|arg| arg == worker::SPAWNER_FLAG) {
+        return worker::spawner_main();
+    }
+
+    let config = config::load().context("failed to load daemon config")?;
+    // Resolved through /proc, which Landlock does not grant.
+    let exe = std::env::current_exe().context("failed to locate daemon binary")?;
+    let notifier = Arc::new(notify::Notifier::from_env());
+    let sources = updates::load_sources(&config.updates.sources_config).context("failed to load update sources")?;
+    let ab = AbAssignment::for_this_host(config.scanner.ab_rollout.clone()).unwrap_or_else(|err| {
+        warn!(error = %err, "no machine id; only baseline rules are evaluated");
+        AbAssignment::new("", Default::default())
+    });
+    let rules = Arc::new(updates::ActiveRules::load(sources.store(), ab));
+    // Reads proxy CA bundles, which Landlock may not grant.
+    let updater = match (config.updates.enabled, sources.sources.is_empty()) {
+        (false, _) => {
+            info!("signature updates disabled by configuration");
+            None
+        }
+        (true, true) => {
+            warn!(config = %config.updates.sources_config.display(), "no signature update sources configured");
+            None
+        }
+        (true, false) => Some(Updater::new(sources).context("failed to set up signature updates")?),
+    };
+
+    // Landlock only confines the calling thread and threads it creates
+    // afterwards, so it has to be in force before the runtime spawns its
+    // worker pool.
+    security::apply_landlock(&config, &exe);
+
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .context("failed to start async runtime")?
+        .block_on(run(config, exe, notifier, updater, rules))
+}
+
+async fn run(
+    config: DaemonConfig,
+    exe: std::path::PathBuf,
+    notifier: Arc<notify::Notifier>,
+    updater: Option<Updater>,
+    rules: Arc<updates::ActiveRules>,
+) -> anyhow::Result<()> {
+    let started = Instant::now();
+    let backend: Arc<dyn ScanBackend> = if config.workers.isolate {
+        Arc::new(
+            worker::WorkerPool::start(exe, &config, config.scanner.thread_pool_size)
+                .context("failed to start scan workers")?,
+        )
+    } else {
+        warn!("scan worker isolation disabled; untrusted files are parsed in the daemon");
+        Arc::new(Scanner::new(config.scanner.clone()).context("failed to init scanner")?)
+    };
+    backend.set_rules(rules.get());
 
-    // Apply sandboxing as early as possible in the process lifetime. The
-    // actual policies are shipped separately; here we merely invoke the
-    // helper so the scaffold documents the flow.
-    security::install_seccomp_filter();
     security::load_apparmor_profile();
 
-    let shutdown = Notify::new();
-    let shutdown_signal = shutdown.clone();
+    let base_budget = ScanBudget {
+        workers: config.scanner.thread_pool_size,
+        queue_depth: config.realtime.queue_depth,
+        defer_background: false,
+    };
+    let cache = ScanCache::load(&config.scheduler.cache_path).unwrap_or_else(|err| {
+        warn!(error = %err, "discarding unreadable scan cache");
+        ScanCache::default()
+    });
+    let cache = Arc::new(Mutex::new(cache));
+    let shadow_hits = ShadowHits::load(&config.scheduler.shadow_hits_path).unwrap_or_else(|err| {
+        warn!(error = %err, "discarding unreadable shadow hit counts");
+        ShadowHits::default()
+    });
+    let shadow_hits = Arc::new(Mutex::new(shadow_hits));
+    let dispatcher = Dispatcher::new(base_budget, Arc::clone(&cache), Arc::clone(&shadow_hits));
+    {
+        let dispatcher = dispatcher.clone();
+        let backend = Arc::clone(&backend);
+        tokio::spawn(async move { dispatcher.run(backend).await });
+    }
+
+    #[cfg(feature = "battery_saver")]
+    {
+        let governor = power::PowerGovernor::new(config.battery.clone(), base_budget);
+        tokio::spawn(governor.run(dispatcher.clone()));
+    }
 
+    let (trigger, shutdown) = shutdown::channel();
+    let mut signals = shutdown::Signals::install().context("failed to install signal handlers")?;
     tokio::spawn(async move {
-        if let Err(err) = watch_shutdown(shutdown_signal).await {
-            error!(error = %err, "shutdown watcher failed");
-        }
+        let signal = signals.recv().await;
+        info!(signal, "shutdown requested");
+        trigger.trigger();
     });
 
-    run_monitor_loop(scanner, &config, &shutdown).await?;
+    let scheduler = scheduler::Scheduler::new(&config.scheduler, dispatcher.clone(), Arc::clone(&cache))
+        .context("invalid scan schedule")?;
+    let scheduler = tokio::spawn(scheduler.run(shutdown.clone()));
+
+    let update_status = Arc::new(Mutex::new(UpdateStatus::default()));
+    let poller = updates::UpdatePoller::new(
+        updater,
+        config.updates.clone(),
+        Arc::clone(&rules),
+        backend,
+        Arc::clone(&cache),
+        Arc::clone(&update_status),
+    );
+    tokio::spawn(poller.run(shutdown.clone()));
+
+    let control = ipc::Control {
+        started,
+        dispatcher: dispatcher.clone(),
+        rules,
+        updates: update_status,
+        shadow_hits: Arc::clone(&shadow_hits),
+    };
+    match ipc::bind(&config.ipc.socket_path) {
+        Ok(listener) => {
+            tokio::spawn(control.serve(listener, shutdown.clone()));
+        }
+        Err(err) => warn!(error = %format!("{err:#}"), "control socket unavailable; av-cli cannot query the daemon"),
+    }
+
+    // Sandboxing goes on last: every task, thread pool and file the daemon
+    // needs exists by now, so the filter can be as tight as the policy.
+    security::install_seccomp_filter(&config.security);
+
+    notifier.ready(&notify::status_line(true, &dispatcher.stats()));
+    if let Some(interval) = notifier.watchdog_interval() {
+        let notifier = Arc::clone(&notifier);
+        tokio::spawn(async move {
+            let mut ticks = tokio::time::interval(interval);
+            loop {
+                ticks.tick().await;
+                notifier.watchdog();
+            }
+        });
+    }
+
+    let state = PersistedState { cache, shadow_hits };
+    run_monitor_loop(&dispatcher, &config, &notifier, &state, shutdown).await?;
+    notifier.stopping();
+
+    // Closing the queue also releases the scheduler if it is blocked on
+    // backpressure, so it can be awaited afterwards.
+    let drain = dispatcher
+        .shutdown(Duration::from_secs(config.shutdown.drain_timeout_secs))
+        .await;
+    if drain.cancelled > 0 {
+        warn!(dropped = drain.dropped, cancelled = drain.cancelled, "scans cancelled at shutdown deadline");
+    } else {
+        info!(dropped = drain.dropped, "in-flight scans drained");
+    }
+    let _ = scheduler.await;
+
+    state.save(&config);
+    let _ = std::fs::remove_file(&config.ipc.socket_path);
     info!("daemon exiting cleanly");
     Ok(())
 }
@@ -47,34 +212,57 @@ fn init_logging() {
         .try_init();
 }
 
-fn load_config() -> anyhow::Result<ScannerConfig> {
-    let mut settings = config::Config::builder();
-    settings = settings.set_default("heuristic_threshold", 0.8)?;
-    let cfg: ScannerConfig = settings.build()?.try_deserialize()?;
-    Ok(cfg)
+/// State written back to disk on every monitor tick and at shutdown, so a
+/// crash or watchdog kill loses at most one tick's worth.
+struct PersistedState {
+    cache: Arc<Mutex<ScanCache>>,
+    shadow_hits: Arc<Mutex<ShadowHits>>,
+}
+
+impl PersistedState {
+    fn save(&self, config: &DaemonConfig) {
+        let mut cache = self.cache.lock();
+        if cache.is_dirty() {
+            if let Err(err) = cache.save(&config.scheduler.cache_path) {
+                warn!(path = %config.scheduler.cache_path.display(), error = %err, "failed to persist scan cache");
+            }
+        }
+        drop(cache);
+        let mut shadow_hits = self.shadow_hits.lock();
+        if shadow_hits.is_dirty() {
+            if let Err(err) = shadow_hits.save(&config.scheduler.shadow_hits_path) {
+                warn!(path = %config.scheduler.shadow_hits_path.display(), error = %err, "failed to persist shadow hit counts");
+            }
+        }
+    }
 }
 
 async fn run_monitor_loop(
-    scanner: Scanner,
-    _config: &ScannerConfig,
-    shutdown: &Notify,
+    dispatcher: &Dispatcher,
+    config: &DaemonConfig,
+    notifier: &notify::Notifier,
+    state: &PersistedState,
+    mut shutdown: ShutdownSignal,
 ) -> anyhow::Result<()> {
     loop {
         tokio::select! {
-            _ = shutdown.notified() => {
+            _ = shutdown.wait() => {
                 break;
             }
             _ = tokio::time::sleep(Duration::from_secs(30)) => {
                 let report = MonitoringReport { events: vec![], degraded_mode: true };
-                info!(?report, "monitoring report placeholder");
+                let stats = dispatcher.stats();
+                info!(
+                    ?report,
+                    queued = stats.queued,
+                    running = stats.running,
+                    budget = ?stats.budget,
+                    "monitoring report placeholder"
+                );
+                notifier.status(&notify::status_line(report.degraded_mode, &stats));
+                state.save(config);
             }
         }
     }
     Ok(())
 }
-
-async fn watch_shutdown(shutdown: Notify) -> anyhow::Result<()> {
-    signal::ctrl_c().await?;
-    shutdown.notify_waiters();
-    Ok(())
-}
@@ -0,0 +1,167 @@
+//! systemd service notifications (`sd_notify(3)`) without libsystemd.
+//!
+//! The protocol is a newline-separated `KEY=value` datagram sent to the
+//! `AF_UNIX` socket named by `$NOTIFY_SOCKET`, either a filesystem path or
+//! an abstract name starting with `@`. When the variable is unset (not
+//! started by systemd, or `Type=simple`) every call is a no-op.
+//!
+//! The socket is connected once at startup: the seccomp policy only admits
+//! `AF_INET` sockets and Landlock may hide the socket path later on.
+
+use std::ffi::{OsStr, OsString};
+use std::io;
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+use std::time::Duration;
+
+use tracing::{debug, warn};
+
+use crate::dispatch::QueueStats;
+
+#[derive(Debug)]
+pub struct Notifier {
+    socket: Option<UnixDatagram>,
+    watchdog: Option<Duration>,
+}
+
+impl Notifier {
+    /// Read `$NOTIFY_SOCKET`, `$WATCHDOG_USEC` and `$WATCHDOG_PID`.
+    pub fn from_env() -> Self {
+        Self::from_vars(
+            std::env::var_os("NOTIFY_SOCKET"),
+            std::env::var("WATCHDOG_USEC").ok(),
+            std::env::var("WATCHDOG_PID").ok(),
+        )
+    }
+
+    pub fn from_vars(socket: Option<OsString>, watchdog_usec: Option<String>, watchdog_pid: Option<String>) -> Self {
+        let socket = socket.and_then(|addr| match connect(&addr) {
+            Ok(socket) => Some(socket),
+            Err(err) => {
+                warn!(socket = ?addr, error = %err, "cannot reach systemd notify socket");
+                None
+            }
+        });
+        // The watchdog is meant for us only if WATCHDOG_PID is unset or ours.
+        let for_us = watchdog_pid.is_none_or(|pid| pid.parse() == Ok(std::process::id()));
+        let watchdog = watchdog_usec
+            .and_then(|usec| usec.parse::<u64>().ok())
+            .filter(|&usec| usec > 0 && for_us)
+            .map(Duration::from_micros);
+        Self { socket, watchdog }
+    }
+
+    /// How often to send `WATCHDOG=1`: half the configured `WatchdogSec`.
+    pub fn watchdog_interval(&self) -> Option<Duration> {
+        self.socket.as_ref().and(self.watchdog).map(|timeout| timeout / 2)
+    }
+
+    pub fn ready(&self, status: &str) {
+        self.send(&format!("READY=1\nSTATUS={status}"));
+    }
+
+    pub fn status(&self, status: &str) {
+        self.send(&format!("STATUS={status}"));
+    }
+
+    pub fn watchdog(&self) {
+        self.send("WATCHDOG=1");
+    }
+
+    pub fn stopping(&self) {
+        self.send("STOPPING=1\nSTATUS=shutting down");
+    }
+
+    fn send(&self, message: &str) {
+        let Some(socket) = &self.socket else {
+            return;
+        };
+        match socket.send(message.as_bytes()) {
+            Ok(_) => {}
+            // systemd is behind; the next update supersedes this one.
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => debug!("notify socket full, dropping update"),
+            Err(err) => warn!(error = %err, "sd_notify failed"),
+        }
+    }
+}
+
+/// Human-readable `STATUS=` line shown by `systemctl status`.
+pub fn status_line(audit_only: bool, stats: &QueueStats) -> String {
+    let mode = if audit_only { "audit-only" } else { "enforcing" };
+    let deferred = if stats.budget.defer_background {
+        ", background scans deferred"
+    } else {
+        ""
+    };
+    format!(
+        "{mode}; {} queued, {} running, {} workers{deferred}",
+        stats.queued, stats.running, stats.budget.workers
+    )
+}
+
+fn connect(addr: &OsStr) -> io::Result<UnixDatagram> {
+    let socket = UnixDatagram::unbound()?;
+    match addr.as_bytes() {
+        [b'@', name @ ..] => socket.connect_addr(&SocketAddr::from_abstract_name(name)?)?,
+        _ => socket.connect(addr)?,
+    }
+    socket.set_nonblocking(true)?;
+    Ok(socket)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dispatch::ScanBudget;
+
+    fn recv(socket: &UnixDatagram) -> String {
+        let mut buf = [0u8; 512];
+        let n = socket.recv(&mut buf).unwrap();
+        String::from_utf8(buf[..n].to_vec()).unwrap()
+    }
+
+    #[test]
+    fn sends_state_changes_to_notify_socket() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notify");
+        let systemd = UnixDatagram::bind(&path).unwrap();
+
+        let notifier = Notifier::from_vars(Some(path.into_os_string()), Some("20000000".into()), None);
+        assert_eq!(notifier.watchdog_interval(), Some(Duration::from_secs(10)));
+
+        let stats = QueueStats {
+            queued: 3,
+            running: 1,
+            budget: ScanBudget {
+                workers: 2,
+                queue_depth: 16,
+                defer_background: true,
+            },
+        };
+        notifier.ready(&status_line(true, &stats));
+        assert_eq!(
+            recv(&systemd),
+            "READY=1\nSTATUS=audit-only; 3 queued, 1 running, 2 workers, background scans deferred"
+        );
+        notifier.watchdog();
+        assert_eq!(recv(&systemd), "WATCHDOG=1");
+        notifier.stopping();
+        assert_eq!(recv(&systemd), "STOPPING=1\nSTATUS=shutting down");
+    }
+
+    #[test]
+    fn abstract_socket_and_foreign_watchdog() {
+        let name = format!("av-daemon-notify-test-{}", std::process::id());
+        let systemd = UnixDatagram::bind_addr(&SocketAddr::from_abstract_name(&name).unwrap()).unwrap();
+
+        let notifier = Notifier::from_vars(Some(format!("@{name}").into()), Some("20000000".into()), Some("1".into()));
+        assert_eq!(notifier.watchdog_interval(), None);
+        notifier.status("idle");
+        assert_eq!(recv(&systemd), "STATUS=idle");
+
+        let unset = Notifier::from_vars(None, Some("20000000".into()), None);
+        assert_eq!(unset.watchdog_interval(), None);
+        unset.ready("ignored");
+    }
+}
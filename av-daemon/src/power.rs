@@ -0,0 +1,242 @@
+//! Battery and thermal aware scan governor (`battery_saver` feature).
+//!
+//! Power-supply and thermal-zone state is read straight from sysfs so the
+//! governor works on hosts without upower. Readings are folded into a
+//! [`ScanBudget`] that the dispatcher applies: fewer workers and a shallower
+//! queue on battery or when hot, with background scans parked until
+//! conditions recover. Exec scans are never deferred.
+
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+use tokio::time::MissedTickBehavior;
+use tracing::info;
+
+use crate::config::{BatteryConfig, BatteryStrategy, ThermalGuard};
+use crate::dispatch::{Dispatcher, ScanBudget};
+
+/// Point-in-time view of the power and thermal state.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PowerSnapshot {
+    pub on_battery: bool,
+    pub battery_percent: Option<u8>,
+    pub max_temp_celsius: Option<f32>,
+}
+
+pub struct PowerGovernor {
+    cfg: BatteryConfig,
+    base: ScanBudget,
+}
+
+impl PowerGovernor {
+    /// `base` is the budget used when running on AC at normal temperatures.
+    pub fn new(cfg: BatteryConfig, base: ScanBudget) -> Self {
+        Self { cfg, base }
+    }
+
+    pub fn snapshot(&self) -> PowerSnapshot {
+        let mut snapshot = read_power_supply(&self.cfg.power_supply_root);
+        if self.cfg.thermal_guard == ThermalGuard::Auto {
+            snapshot.max_temp_celsius = read_max_temp(&self.cfg.thermal_root);
+        }
+        snapshot
+    }
+
+    /// Derive the budget for a snapshot. Each constraint can only tighten
+    /// the base budget, never loosen it.
+    pub fn plan(&self, snapshot: &PowerSnapshot) -> ScanBudget {
+        let base = self.base;
+        let halved = ScanBudget {
+            workers: (base.workers / 2).max(1),
+            queue_depth: base.queue_depth,
+            defer_background: true,
+        };
+        let minimal = ScanBudget {
+            workers: 1,
+            queue_depth: (base.queue_depth / 4).max(1),
+            defer_background: true,
+        };
+
+        let low_battery = snapshot
+            .battery_percent
+            .map(|pct| pct < self.cfg.low_battery_percent)
+            .unwrap_or(false);
+        let mut budget = match self.cfg.strategy {
+            BatteryStrategy::Performance => base,
+            BatteryStrategy::Saver => minimal,
+            BatteryStrategy::Adaptive if snapshot.on_battery && low_battery => minimal,
+            BatteryStrategy::Adaptive if snapshot.on_battery => halved,
+            BatteryStrategy::Adaptive => base,
+        };
+
+        if let Some(temp) = snapshot.max_temp_celsius {
+            if temp >= self.cfg.thermal_critical_celsius {
+                budget = tighten(budget, minimal);
+            } else if temp >= self.cfg.thermal_throttle_celsius {
+                budget = tighten(budget, halved);
+            }
+        }
+        budget
+    }
+
+    /// Poll sysfs and push budget changes into the dispatcher.
+    pub async fn run(self, dispatcher: Dispatcher) {
+        let period = Duration::from_secs(self.cfg.poll_interval_secs.max(1));
+        let mut ticker = tokio::time::interval(period);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        let mut current = None;
+        loop {
+            ticker.tick().await;
+            let snapshot = self.snapshot();
+            let budget = self.plan(&snapshot);
+            if current != Some(budget) {
+                info!(?snapshot, ?budget, "power governor adjusted scan budget");
+                dispatcher.set_budget(budget);
+                current = Some(budget);
+            }
+        }
+    }
+}
+
+fn tighten(a: ScanBudget, b: ScanBudget) -> ScanBudget {
+    ScanBudget {
+        workers: a.workers.min(b.workers),
+        queue_depth: a.queue_depth.min(b.queue_depth),
+        defer_background: a.defer_background || b.defer_background,
+    }
+}
+
+/// Walk `/sys/class/power_supply`. The host counts as on battery when a
+/// battery reports discharging and no mains/USB supply is online; machines
+/// without a battery are always treated as on AC.
+fn read_power_supply(root: &Path) -> PowerSnapshot {
+    let mut snapshot = PowerSnapshot::default();
+    let Ok(entries) = fs::read_dir(root) else {
+        return snapshot;
+    };
+
+    let mut external_online = false;
+    let mut discharging = false;
+    for entry in entries.flatten() {
+        let dir = entry.path();
+        match read_trimmed(&dir.join("type")).as_deref() {
+            Some("Battery") => {
+                if let Some(pct) = read_trimmed(&dir.join("capacity")).and_then(|v| v.parse::<u8>().ok()) {
+                    let pct = pct.min(100);
+                    snapshot.battery_percent = Some(snapshot.battery_percent.map_or(pct, |cur| cur.min(pct)));
+                }
+                if read_trimmed(&dir.join("status")).as_deref() == Some("Discharging") {
+                    discharging = true;
+                }
+            }
+            Some(_) if read_trimmed(&dir.join("online")).as_deref() == Some("1") => {
+                external_online = true;
+            }
+            _ => {}
+        }
+    }
+    snapshot.on_battery = discharging && !external_online;
+    snapshot
+}
+
+/// Hottest `thermal_zone*/temp` reading in degrees Celsius. Zones that fail
+/// to read or report non-positive values (disabled sensors) are ignored.
+fn read_max_temp(root: &Path) -> Option<f32> {
+    let entries = fs::read_dir(root).ok()?;
+    entries
+        .flatten()
+        .filter(|entry| entry.file_name().to_string_lossy().starts_with("thermal_zone"))
+        .filter_map(|entry| read_trimmed(&entry.path().join("temp"))?.parse::<i64>().ok())
+        .filter(|millidegrees| *millidegrees > 0)
+        .map(|millidegrees| millidegrees as f32 / 1000.0)
+        .reduce(f32::max)
+}
+
+fn read_trimmed(path: &Path) -> Option<String> {
+    fs::read_to_string(path).ok().map(|s| s.trim().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BASE: ScanBudget = ScanBudget {
+        workers: 4,
+        queue_depth: 1024,
+        defer_background: false,
+    };
+
+    fn write(root: &Path, rel: &str, value: &str) {
+        let path = root.join(rel);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, format!("{value}\n")).unwrap();
+    }
+
+    fn governor(sysfs: &Path) -> PowerGovernor {
+        let cfg = BatteryConfig {
+            power_supply_root: sysfs.join("class/power_supply"),
+            thermal_root: sysfs.join("class/thermal"),
+            ..BatteryConfig::default()
+        };
+        PowerGovernor::new(cfg, BASE)
+    }
+
+    #[test]
+    fn full_budget_on_ac() {
+        let sysfs = tempfile::tempdir().unwrap();
+        write(sysfs.path(), "class/power_supply/AC/type", "Mains");
+        write(sysfs.path(), "class/power_supply/AC/online", "1");
+        write(sysfs.path(), "class/power_supply/BAT0/type", "Battery");
+        write(sysfs.path(), "class/power_supply/BAT0/capacity", "80");
+        write(sysfs.path(), "class/power_supply/BAT0/status", "Charging");
+        write(sysfs.path(), "class/thermal/thermal_zone0/temp", "45000");
+
+        let gov = governor(sysfs.path());
+        let snapshot = gov.snapshot();
+        assert!(!snapshot.on_battery);
+        assert_eq!(snapshot.battery_percent, Some(80));
+        assert_eq!(snapshot.max_temp_celsius, Some(45.0));
+        assert_eq!(gov.plan(&snapshot), BASE);
+    }
+
+    #[test]
+    fn battery_and_low_charge_scale_back() {
+        let sysfs = tempfile::tempdir().unwrap();
+        write(sysfs.path(), "class/power_supply/AC/type", "Mains");
+        write(sysfs.path(), "class/power_supply/AC/online", "0");
+        write(sysfs.path(), "class/power_supply/BAT0/type", "Battery");
+        write(sysfs.path(), "class/power_supply/BAT0/capacity", "55");
+        write(sysfs.path(), "class/power_supply/BAT0/status", "Discharging");
+
+        let gov = governor(sysfs.path());
+        let budget = gov.plan(&gov.snapshot());
+        assert_eq!(budget.workers, 2);
+        assert_eq!(budget.queue_depth, 1024);
+        assert!(budget.defer_background);
+
+        write(sysfs.path(), "class/power_supply/BAT0/capacity", "12");
+        let budget = gov.plan(&gov.snapshot());
+        assert_eq!(budget.workers, 1);
+        assert_eq!(budget.queue_depth, 256);
+    }
+
+    #[test]
+    fn hot_thermal_zone_throttles_even_on_ac() {
+        let sysfs = tempfile::tempdir().unwrap();
+        write(sysfs.path(), "class/thermal/thermal_zone0/temp", "40000");
+        write(sysfs.path(), "class/thermal/thermal_zone1/temp", "92500");
+        write(sysfs.path(), "class/thermal/thermal_zone2/temp", "-273000");
+
+        let gov = governor(sysfs.path());
+        let snapshot = gov.snapshot();
+        assert!(!snapshot.on_battery);
+        assert_eq!(snapshot.max_temp_celsius, Some(92.5));
+        assert_eq!(gov.plan(&snapshot).workers, 1);
+
+        let mut cfg = gov.cfg.clone();
+        cfg.thermal_guard = ThermalGuard::Off;
+        let gov = PowerGovernor::new(cfg, BASE);
+        assert_eq!(gov.plan(&gov.snapshot()), BASE);
+    }
+}
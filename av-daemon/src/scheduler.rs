@@ -0,0 +1,384 @@
+//! Cron and interval driven scan profiles.
+//!
+//! Profiles are checked against the wall clock once a minute instead of
+//! sleeping until the next occurrence, so a run missed while the machine was
+//! suspended or the daemon was stopped is caught up shortly afterwards. A
+//! catch-up runs once no matter how many occurrences were missed.
+//!
+//! Files are fed to the dispatcher as background jobs; incremental profiles
+//! skip anything whose fingerprint still matches the scan cache. A run cut
+//! short by shutdown is not recorded, so it is caught up on the next start.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration as StdDuration;
+
+use anyhow::Context;
+use chrono::{DateTime, Duration, Local, Utc};
+use glob::{MatchOptions, Pattern};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+use tokio::time::MissedTickBehavior;
+use tracing::{debug, info, warn};
+use walkdir::WalkDir;
+
+use av_core::cache::{FileFingerprint, ScanCache};
+use av_core::persist;
+
+use crate::config::{ScanMode, ScanPriority, ScanProfile, SchedulerConfig};
+use crate::cron::CronSchedule;
+use crate::dispatch::{Dispatcher, ScanClass, ScanJob};
+use crate::shutdown::ShutdownSignal;
+
+const TICK: StdDuration = StdDuration::from_secs(60);
+
+const GLOB_OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: true,
+    require_literal_separator: true,
+    require_literal_leading_dot: false,
+};
+
+#[derive(Debug, Clone)]
+pub enum Schedule {
+    Cron(CronSchedule),
+    Interval(Duration),
+}
+
+impl Schedule {
+    pub fn from_profile(profile: &ScanProfile) -> anyhow::Result<Self> {
+        match (&profile.cron, profile.interval_secs) {
+            (Some(expr), None) => Ok(Self::Cron(
+                expr.parse().with_context(|| format!("profile `{}`", profile.name))?,
+            )),
+            (None, Some(secs)) if secs > 0 => Ok(Self::Interval(Duration::seconds(secs as i64))),
+            _ => anyhow::bail!(
+                "profile `{}` needs exactly one of `cron` or a positive `interval_secs`",
+                profile.name
+            ),
+        }
+    }
+
+    /// Cron expressions are evaluated in local time so "02:30" means the
+    /// user's night, not UTC's.
+    pub fn next_after(&self, last: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        match self {
+            Self::Cron(cron) => cron
+                .next_after(&last.with_timezone(&Local))
+                .map(|next| next.with_timezone(&Utc)),
+            Self::Interval(every) => Some(last + *every),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ProfileState {
+    /// When the profile was first seen; the schedule counts from here until
+    /// the first run completes.
+    anchor: DateTime<Utc>,
+    last_run: Option<DateTime<Utc>>,
+    last_submitted: u64,
+    last_skipped: u64,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct SchedulerState {
+    profiles: BTreeMap<String, ProfileState>,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct RunSummary {
+    submitted: u64,
+    skipped: u64,
+    /// The dispatcher closed before every file was queued.
+    interrupted: bool,
+}
+
+struct ProfileEntry {
+    profile: ScanProfile,
+    schedule: Schedule,
+    excludes: Vec<Pattern>,
+}
+
+pub struct Scheduler {
+    entries: Vec<ProfileEntry>,
+    state_path: PathBuf,
+    cache_path: PathBuf,
+    state: SchedulerState,
+    dispatcher: Dispatcher,
+    cache: Arc<Mutex<ScanCache>>,
+}
+
+impl Scheduler {
+    /// Validates every profile up front so a typo in a schedule fails the
+    /// daemon start instead of silently never running.
+    pub fn new(cfg: &SchedulerConfig, dispatcher: Dispatcher, cache: Arc<Mutex<ScanCache>>) -> anyhow::Result<Self> {
+        let entries = cfg
+            .profiles
+            .iter()
+            .map(|profile| {
+                let excludes = profile
+                    .excludes
+                    .iter()
+                    .map(|pattern| {
+                        Pattern::new(pattern).with_context(|| format!("profile `{}` exclude `{pattern}`", profile.name))
+                    })
+                    .collect::<anyhow::Result<Vec<_>>>()?;
+                Ok(ProfileEntry {
+                    profile: profile.clone(),
+                    schedule: Schedule::from_profile(profile)?,
+                    excludes,
+                })
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        let state = load_state(&cfg.state_path).unwrap_or_else(|err| {
+            warn!(path = %cfg.state_path.display(), error = %err, "discarding unreadable scheduler state");
+            SchedulerState::default()
+        });
+
+        Ok(Self {
+            entries,
+            state_path: cfg.state_path.clone(),
+            cache_path: cfg.cache_path.clone(),
+            state,
+            dispatcher,
+            cache,
+        })
+    }
+
+    pub async fn run(mut self, mut shutdown: ShutdownSignal) {
+        let mut ticker = tokio::time::interval(TICK);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            tokio::select! {
+                _ = ticker.tick() => self.tick(Utc::now()).await,
+                _ = shutdown.wait() => return,
+            }
+        }
+    }
+
+    async fn tick(&mut self, now: DateTime<Utc>) {
+        let mut due = self.due_profiles(now);
+        due.sort_by_key(|&idx| std::cmp::Reverse(self.entries[idx].profile.priority));
+
+        for idx in due {
+            let name = self.entries[idx].profile.name.clone();
+            if self.entries[idx].profile.priority < ScanPriority::High && self.dispatcher.budget().defer_background {
+                debug!(profile = %name, "scheduled scan deferred by power governor");
+                continue;
+            }
+
+            let summary = self.run_profile(&self.entries[idx]).await;
+            if summary.interrupted {
+                info!(profile = %name, submitted = summary.submitted, "scheduled scan interrupted by shutdown");
+                return;
+            }
+            info!(
+                profile = %name,
+                submitted = summary.submitted,
+                skipped = summary.skipped,
+                "scheduled scan queued"
+            );
+            if let Some(entry) = self.state.profiles.get_mut(&name) {
+                entry.last_run = Some(now);
+                entry.last_submitted = summary.submitted;
+                entry.last_skipped = summary.skipped;
+            }
+            self.persist_state();
+        }
+
+        let mut cache = self.cache.lock();
+        if cache.is_dirty() {
+            if let Err(err) = cache.save(&self.cache_path) {
+                warn!(path = %self.cache_path.display(), error = %err, "failed to persist scan cache");
+            }
+        }
+    }
+
+    /// Indexes of profiles whose next occurrence after their last run (or
+    /// first sighting) has already passed.
+    fn due_profiles(&mut self, now: DateTime<Utc>) -> Vec<usize> {
+        let mut anchored = false;
+        let mut due = Vec::new();
+        for (idx, entry) in self.entries.iter().enumerate() {
+            let state = self
+                .state
+                .profiles
+                .entry(entry.profile.name.clone())
+                .or_insert_with(|| {
+                    anchored = true;
+                    ProfileState {
+                        anchor: now,
+                        last_run: None,
+                        last_submitted: 0,
+                        last_skipped: 0,
+                    }
+                });
+            let since = state.last_run.unwrap_or(state.anchor);
+            if entry.schedule.next_after(since).is_some_and(|next| next <= now) {
+                due.push(idx);
+            }
+        }
+        if anchored {
+            self.persist_state();
+        }
+        due
+    }
+
+    async fn run_profile(&self, entry: &ProfileEntry) -> RunSummary {
+        let class = match entry.profile.priority {
+            ScanPriority::High => ScanClass::OnAccess,
+            _ => ScanClass::Background,
+        };
+        let (tx, mut rx) = mpsc::channel(256);
+        let roots = entry.profile.paths.clone();
+        let max_depth = entry.profile.max_depth;
+        let excludes = entry.excludes.clone();
+        let walker = tokio::task::spawn_blocking(move || walk(&roots, max_depth, &excludes, &tx));
+
+        let mut summary = RunSummary::default();
+        while let Some((path, fingerprint)) = rx.recv().await {
+            if entry.profile.mode == ScanMode::Incremental && self.cache.lock().is_fresh(&path, &fingerprint) {
+                summary.skipped += 1;
+                continue;
+            }
+            if self.dispatcher.submit_wait(ScanJob { path, class }).await.is_err() {
+                summary.interrupted = true;
+                break;
+            }
+            summary.submitted += 1;
+        }
+        // Stops the walker at its next send.
+        drop(rx);
+        if let Err(err) = walker.await {
+            warn!(profile = %entry.profile.name, error = %err, "filesystem walk aborted");
+        }
+        summary
+    }
+
+    fn persist_state(&self) {
+        if let Err(err) = save_state(&self.state_path, &self.state) {
+            warn!(path = %self.state_path.display(), error = %err, "failed to persist scheduler state");
+        }
+    }
+}
+
+fn walk(roots: &[PathBuf], max_depth: usize, excludes: &[Pattern], tx: &mpsc::Sender<(PathBuf, FileFingerprint)>) {
+    for root in roots {
+        let entries = WalkDir::new(root)
+            .max_depth(max_depth)
+            .follow_links(false)
+            .into_iter()
+            .filter_entry(|entry| !excludes.iter().any(|p| p.matches_path_with(entry.path(), GLOB_OPTIONS)));
+        for entry in entries {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(err) => {
+                    debug!(error = %err, "skipping unreadable entry");
+                    continue;
+                }
+            };
+            if !entry.file_type().is_file() {
+                continue;
+            }
+            let Ok(meta) = entry.metadata() else { continue };
+            let fingerprint = FileFingerprint::from_metadata(&meta);
+            if tx.blocking_send((entry.into_path(), fingerprint)).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+fn load_state(path: &Path) -> anyhow::Result<SchedulerState> {
+    match fs::read(path) {
+        Ok(bytes) => Ok(serde_json::from_slice(&bytes)?),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(SchedulerState::default()),
+        Err(err) => Err(err.into()),
+    }
+}
+
+fn save_state(path: &Path, state: &SchedulerState) -> anyhow::Result<()> {
+    persist::write_atomic(path, &serde_json::to_vec_pretty(state)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use av_core::RecommendedAction;
+
+    use super::*;
+    use crate::dispatch::ScanBudget;
+
+    fn profile(root: &Path, mode: ScanMode) -> ScanProfile {
+        ScanProfile {
+            name: "nightly".into(),
+            paths: vec![root.to_path_buf()],
+            excludes: vec![format!("{}/skip", root.display())],
+            max_depth: 8,
+            priority: ScanPriority::Low,
+            mode,
+            cron: None,
+            interval_secs: Some(3600),
+        }
+    }
+
+    fn scheduler(dir: &Path, profile: ScanProfile, cache: ScanCache) -> Scheduler {
+        let cfg = SchedulerConfig {
+            state_path: dir.join("state/scheduler.json"),
+            cache_path: dir.join("state/cache.json"),
+            shadow_hits_path: dir.join("state/shadow-hits.json"),
+            profiles: vec![profile],
+        };
+        let dispatcher = Dispatcher::new(
+            ScanBudget {
+                workers: 1,
+                queue_depth: 64,
+                defer_background: false,
+            },
+            Arc::new(Mutex::new(ScanCache::default())),
+            Arc::default(),
+        );
+        Scheduler::new(&cfg, dispatcher, Arc::new(Mutex::new(cache))).unwrap()
+    }
+
+    #[tokio::test]
+    async fn missed_runs_catch_up_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut sched = scheduler(dir.path(), profile(dir.path(), ScanMode::Full), ScanCache::default());
+        let start = Utc::now();
+
+        assert!(sched.due_profiles(start).is_empty());
+        // Six hours asleep: one catch-up run, not six.
+        let resumed = start + Duration::hours(6);
+        assert_eq!(sched.due_profiles(resumed), vec![0]);
+        sched.tick(resumed).await;
+        assert!(sched.due_profiles(resumed + Duration::minutes(1)).is_empty());
+
+        // State survives a restart.
+        let reloaded = scheduler(dir.path(), profile(dir.path(), ScanMode::Full), ScanCache::default());
+        assert_eq!(reloaded.state.profiles["nightly"].last_run, Some(resumed));
+    }
+
+    #[tokio::test]
+    async fn incremental_runs_skip_unchanged_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("home");
+        fs::create_dir_all(root.join("skip")).unwrap();
+        fs::write(root.join("unchanged.sh"), b"echo hi").unwrap();
+        fs::write(root.join("new.bin"), b"\x7fELF").unwrap();
+        fs::write(root.join("skip/ignored.bin"), b"\x7fELF").unwrap();
+
+        let mut cache = ScanCache::default();
+        let unchanged = root.join("unchanged.sh");
+        let fingerprint = FileFingerprint::from_metadata(&fs::metadata(&unchanged).unwrap());
+        cache.record(unchanged, fingerprint, RecommendedAction::Allow);
+
+        let sched = scheduler(dir.path(), profile(&root, ScanMode::Incremental), cache);
+        let summary = sched.run_profile(&sched.entries[0]).await;
+        assert_eq!((summary.submitted, summary.skipped), (1, 1));
+        assert_eq!(sched.dispatcher.stats().queued, 1);
+    }
+}
@@ -4,14 +4,131 @@
 //! audit-only mode if any mechanism is unavailable, preserving the hard
 //! requirement for graceful degradation on Ubuntu 25.10 pre-release builds.
 
-use tracing::warn;
+use std::path::Path;
+
+#[cfg(all(feature = "seccomp_filter", feature = "landlock_confine"))]
+use anyhow::Context;
+use tracing::{info, warn};
+
+use crate::config::{DaemonConfig, SeccompMode, SecurityConfig};
+
+/// Confine filesystem access with Landlock when enabled via
+/// `realtime.landlock_confine`. Must be called before any other thread is
+/// spawned; see [`crate::confine`]. `exe` is the daemon binary, which must
+/// stay executable for scan workers to be started.
+pub fn apply_landlock(cfg: &DaemonConfig, exe: &Path) {
+    if !cfg.realtime.landlock_confine {
+        info!("Landlock confinement disabled by configuration");
+        return;
+    }
 
-pub fn install_seccomp_filter() {
     #[cfg(feature = "landlock_confine")]
     {
-        warn!("Landlock confinement requested but not yet wired - placeholder");
+        use crate::confine::{self, ConfinementLevel, LandlockPaths};
+
+        let paths = LandlockPaths::from_config(cfg, exe);
+        match confine::apply(&paths) {
+            Ok(report) if report.level == ConfinementLevel::Unsupported => {
+                warn!("Landlock unavailable on this kernel, continuing unconfined");
+            }
+            Ok(report) => info!(
+                level = ?report.level,
+                abi = ?report.abi,
+                read_only = paths.read_only.len(),
+                read_write = paths.read_write.len(),
+                executable = paths.executable.len(),
+                "Landlock ruleset enforced"
+            ),
+            Err(err) => warn!(error = %err, "Landlock ruleset not applied, continuing unconfined"),
+        }
+    }
+
+    #[cfg(not(feature = "landlock_confine"))]
+    {
+        let _ = exe;
+        warn!("Landlock confinement requested but the daemon was built without landlock_confine");
+    }
+}
+
+/// Compile and install the seccomp policy. Must run after every thread
+/// pool and file descriptor the daemon needs has been set up: the filter
+/// is synchronised across existing threads and inherited by new ones.
+pub fn install_seccomp_filter(cfg: &SecurityConfig) {
+    if cfg.seccomp == SeccompMode::Off {
+        warn!("seccomp filter disabled by configuration");
+        return;
+    }
+
+    #[cfg(feature = "seccomp_filter")]
+    {
+        let prog = compile_policy(cfg.seccomp, &cfg.seccomp_policy);
+        install_compiled(prog, cfg.seccomp, &cfg.seccomp_policy);
+    }
+
+    #[cfg(not(feature = "seccomp_filter"))]
+    warn!("built without the seccomp_filter feature; syscalls are unrestricted");
+}
+
+/// Sandbox a scan worker before it accepts requests: Landlock with no
+/// filesystem rights at all, then the worker seccomp policy, enforced
+/// whatever the daemon's own `security.seccomp` mode. Scans only touch
+/// descriptors the broker passes in, so neither restriction gets in their
+/// way. Unlike the daemon, a worker fails closed: any error here means it
+/// must not parse anything.
+#[cfg(all(feature = "seccomp_filter", feature = "landlock_confine"))]
+pub fn confine_worker(policy: &Path) -> anyhow::Result<()> {
+    // Read the policy while the filesystem is still reachable.
+    let prog = compile_policy(SeccompMode::Enforce, policy)?;
+    let report = revoke_filesystem()?;
+    crate::seccomp::install(&prog).context("scan worker seccomp filter not installed")?;
+    info!(
+        level = ?report.level,
+        policy = %policy.display(),
+        instructions = prog.len(),
+        "scan worker confined"
+    );
+    Ok(())
+}
+
+#[cfg(not(all(feature = "seccomp_filter", feature = "landlock_confine")))]
+pub fn confine_worker(_policy: &Path) -> anyhow::Result<()> {
+    anyhow::bail!("built without seccomp_filter and landlock_confine; scan workers cannot be confined")
+}
+
+/// Drop every filesystem right of the calling thread, failing if the kernel
+/// cannot enforce Landlock at all.
+#[cfg(feature = "landlock_confine")]
+pub fn revoke_filesystem() -> anyhow::Result<crate::confine::ConfinementReport> {
+    use crate::confine::{self, ConfinementLevel};
+
+    let report = confine::deny_all().map_err(|err| anyhow::anyhow!("Landlock ruleset not applied: {err}"))?;
+    anyhow::ensure!(report.level != ConfinementLevel::Unsupported, "Landlock is unavailable on this kernel");
+    Ok(report)
+}
+
+#[cfg(feature = "seccomp_filter")]
+fn compile_policy(mode: SeccompMode, path: &Path) -> anyhow::Result<Vec<libc::sock_filter>> {
+    use crate::seccomp::{FilterMode, SeccompPolicy};
+
+    let mode = match mode {
+        SeccompMode::Log => FilterMode::LogOnly,
+        _ => FilterMode::Enforce,
+    };
+    SeccompPolicy::load(path).and_then(|policy| policy.compile(mode))
+}
+
+#[cfg(feature = "seccomp_filter")]
+fn install_compiled(prog: anyhow::Result<Vec<libc::sock_filter>>, mode: SeccompMode, path: &Path) {
+    let installed = prog.and_then(|prog| crate::seccomp::install(&prog).map(|()| prog.len()));
+    match installed {
+        Ok(instructions) => info!(
+            policy = %path.display(),
+            ?mode,
+            instructions,
+            "seccomp filter installed"
+        ),
+        Err(err) => warn!(error = %format!("{err:#}"), "seccomp filter not installed, continuing unconfined"),
     }
-    warn!("Seccomp filter installation is a stub in the scaffold");
 }
 
 pub fn load_apparmor_profile() {
@@ -0,0 +1,57 @@
+//! Shutdown coordination.
+//!
+//! A single trigger, fired by SIGTERM (systemd stop) or SIGINT, is observed
+//! by every long-running task through a cloneable [`ShutdownSignal`]. The
+//! ordered teardown itself (close the queue, drain scans, flush state) lives
+//! in `main` where all the pieces are in scope.
+
+use tokio::signal::unix::{signal, Signal, SignalKind};
+use tokio::sync::watch;
+
+pub fn channel() -> (ShutdownTrigger, ShutdownSignal) {
+    let (tx, rx) = watch::channel(false);
+    (ShutdownTrigger(tx), ShutdownSignal(rx))
+}
+
+pub struct ShutdownTrigger(watch::Sender<bool>);
+
+impl ShutdownTrigger {
+    pub fn trigger(&self) {
+        self.0.send_replace(true);
+    }
+}
+
+#[derive(Clone)]
+pub struct ShutdownSignal(watch::Receiver<bool>);
+
+impl ShutdownSignal {
+    /// Resolve once shutdown has been requested, immediately if it already
+    /// has. A dropped trigger counts as a request.
+    pub async fn wait(&mut self) {
+        let _ = self.0.wait_for(|&requested| requested).await;
+    }
+}
+
+/// SIGTERM and SIGINT handlers. Installed up front: registering later would
+/// need syscalls the seccomp policy no longer allows.
+pub struct Signals {
+    term: Signal,
+    int: Signal,
+}
+
+impl Signals {
+    pub fn install() -> std::io::Result<Self> {
+        Ok(Self {
+            term: signal(SignalKind::terminate())?,
+            int: signal(SignalKind::interrupt())?,
+        })
+    }
+
+    /// Name of the first signal received.
+    pub async fn recv(&mut self) -> &'static str {
+        tokio::select! {
+            _ = self.term.recv() => "SIGTERM",
+            _ = self.int.recv() => "SIGINT",
+        }
+    }
+}
@@ -0,0 +1,359 @@
+//! Background signature updates and the daemon's active rule set.
+//!
+//! Sources are polled every `updates.interval_secs`, moved by a random
+//! jitter so a fleet booted together spreads its requests out. A failed poll
+//! is retried after an exponentially growing delay, capped at
+//! `updates.retry_max_secs`, again with jitter. Polls are compared against
+//! the wall clock on a short tick rather than slept for, so one missed while
+//! suspended runs soon after resume.
+//!
+//! The active rules are reloaded whenever the store's active generation
+//! changes, whether this poller installed it or `av-cli signatures import`
+//! or `rollback` did, without restarting the daemon. They are also reloaded
+//! on the tick after a loaded rule's `expires_at` passes, so an expired rule
+//! stops matching even if no new generation arrives. Each reload empties the
+//! scan cache, whose verdicts were reached under the previous rules.
+
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration as StdDuration;
+
+use anyhow::Context;
+use chrono::{DateTime, Duration, Utc};
+use parking_lot::{Mutex, RwLock};
+use rand::Rng;
+use tokio::time::MissedTickBehavior;
+use tracing::{info, warn};
+
+use av_core::cache::ScanCache;
+use av_core::ipc::UpdateStatus;
+use av_core::signatures::{AbAssignment, RuleSet};
+use av_signatures::store::{BundleStore, Installed};
+use av_signatures::{SourcesFailed, UpdateConfig, Updater};
+
+use crate::config::UpdatesConfig;
+use crate::dispatch::ScanBackend;
+use crate::shutdown::ShutdownSignal;
+
+const TICK: StdDuration = StdDuration::from_secs(30);
+
+/// Read the update sources the same way `av-cli signatures` does; a missing
+/// file yields no sources.
+pub fn load_sources(path: &Path) -> anyhow::Result<UpdateConfig> {
+    let settings = config::Config::builder()
+        .add_source(config::File::from(path).required(false))
+        .build()?;
+    settings
+        .try_deserialize()
+        .with_context(|| format!("reading {}", path.display()))
+}
+
+/// Delay before the next poll after `failures` consecutive failed ones.
+/// `unit` is a uniform random number in `[0, 1)`.
+pub fn next_delay(cfg: &UpdatesConfig, failures: u32, unit: f64) -> Duration {
+    let secs = if failures == 0 {
+        let jitter = cfg.jitter_secs as f64 * (2.0 * unit - 1.0);
+        cfg.interval_secs as f64 + jitter
+    } else {
+        let doublings = (failures - 1).min(31);
+        let cap = cfg.retry_min_secs.saturating_mul(1 << doublings).min(cfg.retry_max_secs) as f64;
+        // Equal jitter: at least half the backoff, so retries stay spaced out.
+        cap / 2.0 + cap / 2.0 * unit
+    };
+    Duration::seconds(secs.round() as i64)
+}
+
+/// Rules of the store's active generation, as selected for this host.
+pub struct ActiveRules {
+    store: BundleStore,
+    ab: AbAssignment,
+    current: RwLock<(Option<u64>, Arc<RuleSet>)>,
+}
+
+impl ActiveRules {
+    pub fn load(store: BundleStore, ab: AbAssignment) -> Self {
+        let rules = Self {
+            store,
+            ab,
+            current: RwLock::new((None, Arc::new(RuleSet::default()))),
+        };
+        if let Err(err) = rules.refresh() {
+            warn!(error = %format!("{err:#}"), "no signature rules loaded");
+        }
+        rules
+    }
+
+    pub fn get(&self) -> Arc<RuleSet> {
+        Arc::clone(&self.current.read().1)
+    }
+
+    pub fn generation(&self) -> Option<u64> {
+        self.current.read().0
+    }
+
+    /// Reload if another generation became active since the last load or
+    /// a loaded rule has expired. Returns whether the rules were swapped.
+    pub fn refresh(&self) -> anyhow::Result<bool> {
+        self.refresh_at(Utc::now())
+    }
+
+    fn refresh_at(&self, now: DateTime<Utc>) -> anyhow::Result<bool> {
+        let generation = self.store.current()?.map(|g| g.id);
+        let expired = self.get().next_expiry().is_some_and(|at| at <= now);
+        if generation == self.generation() && !expired {
+            return Ok(false);
+        }
+        let merged = self.store.rules()?.unwrap_or_default();
+        let rules = RuleSet::load(merged.rules.into_values(), now, &self.ab);
+        info!(
+            generation = ?generation,
+            loaded = rules.rules.len(),
+            shadow = rules.shadow.len(),
+            expired = rules.summary.expired,
+            "signature rules activated"
+        );
+        *self.current.write() = (generation, Arc::new(rules));
+        Ok(true)
+    }
+}
+
+pub struct UpdatePoller {
+    /// `None` when updates are disabled; imports and rollbacks made with
+    /// `av-cli` are still picked up.
+    updater: Option<Updater>,
+    cfg: UpdatesConfig,
+    rules: Arc<ActiveRules>,
+    /// Handed every rule set [`ActiveRules`] swaps in.
+    backend: Arc<dyn ScanBackend>,
+    cache: Arc<Mutex<ScanCache>>,
+    status: Arc<Mutex<UpdateStatus>>,
+}
+
+impl UpdatePoller {
+    pub fn new(
+        updater: Option<Updater>,
+        cfg: UpdatesConfig,
+        rules: Arc<ActiveRules>,
+        backend: Arc<dyn ScanBackend>,
+        cache: Arc<Mutex<ScanCache>>,
+        status: Arc<Mutex<UpdateStatus>>,
+    ) -> Self {
+        status.lock().enabled = updater.is_some();
+        Self {
+            updater,
+            cfg,
+            rules,
+            backend,
+            cache,
+            status,
+        }
+    }
+
+    pub async fn run(self, mut shutdown: ShutdownSignal) {
+        // The first poll only waits long enough to spread out a fleet's boot.
+        let startup = rand::thread_rng().gen_range(0..self.cfg.retry_min_secs);
+        let mut next = self.updater.as_ref().map(|_| Utc::now() + Duration::seconds(startup as i64));
+        self.status.lock().next_attempt = next;
+
+        let mut ticker = tokio::time::interval(TICK);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            tokio::select! {
+                _ = ticker.tick() => match (&self.updater, next) {
+                    (Some(updater), Some(due)) if Utc::now() >= due => next = Some(self.poll(updater).await),
+                    _ => self.reload(),
+                },
+                _ = shutdown.wait() => return,
+            }
+        }
+    }
+
+    /// Poll every source once and return when to poll next.
+    async fn poll(&self, updater: &Updater) -> DateTime<Utc> {
+        let started = Utc::now();
+        let result = updater.update().await;
+        let mut status = self.status.lock();
+        status.last_attempt = Some(started);
+        // Sources that verified are installed even when others failed.
+        let installed = match &result {
+            Ok(installed) => Some(installed),
+            Err(err) => err.downcast_ref::<SourcesFailed>().map(|failed| &failed.installed),
+        };
+        match installed {
+            Some(Installed::Activated(generation)) => info!(generation = generation.id, "signature update installed"),
+            Some(Installed::Held) => info!("signature update matches a rolled-back generation; kept on hold"),
+            Some(Installed::Unchanged) if result.is_ok() => info!("signatures up to date"),
+            _ => {}
+        }
+        match result {
+            Ok(_) => {
+                status.last_success = Some(Utc::now());
+                status.last_failure = None;
+                status.consecutive_failures = 0;
+            }
+            Err(err) => {
+                let reason = format!("{err:#}");
+                status.consecutive_failures += 1;
+                warn!(error = %reason, failures = status.consecutive_failures, "signature update failed");
+                status.last_failure = Some(reason);
+            }
+        }
+        let delay = next_delay(&self.cfg, status.consecutive_failures, rand::thread_rng().gen());
+        let next = Utc::now() + delay;
+        status.next_attempt = Some(next);
+        drop(status);
+        self.reload();
+        next
+    }
+
+    fn reload(&self) {
+        match self.rules.refresh() {
+            Ok(true) => {
+                self.backend.set_rules(self.rules.get());
+                self.cache.lock().clear();
+            }
+            Ok(false) => {}
+            Err(err) => warn!(error = %format!("{err:#}"), "failed to reload signature rules"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use av_core::cache::FileFingerprint;
+    use av_core::signatures::{RuleBundle, RuleMetadata, RuleState};
+    use av_core::{RecommendedAction, Scanner};
+    use av_signatures::merge::MergedRules;
+
+    #[test]
+    fn delays_jitter_around_interval_and_back_off_on_failure() {
+        let cfg = UpdatesConfig {
+            interval_secs: 3600,
+            jitter_secs: 600,
+            retry_min_secs: 60,
+            retry_max_secs: 900,
+            ..UpdatesConfig::default()
+        };
+        assert_eq!(next_delay(&cfg, 0, 0.0), Duration::seconds(3000));
+        assert_eq!(next_delay(&cfg, 0, 0.5), Duration::seconds(3600));
+        assert_eq!(next_delay(&cfg, 0, 0.999), Duration::seconds(4199));
+
+        assert_eq!(next_delay(&cfg, 1, 0.0), Duration::seconds(30));
+        assert_eq!(next_delay(&cfg, 3, 0.999), Duration::seconds(240));
+        // 60 << 4 = 960 is capped at 900.
+        assert_eq!(next_delay(&cfg, 5, 0.0), Duration::seconds(450));
+        assert_eq!(next_delay(&cfg, u32::MAX, 0.999), Duration::seconds(900));
+    }
+
+    #[test]
+    fn reloads_when_the_active_generation_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let rules = ActiveRules::load(BundleStore::new(dir.path(), 2), AbAssignment::default());
+        assert_eq!((rules.generation(), rules.get().rules.len()), (None, 0));
+        assert!(!rules.refresh().unwrap());
+
+        let mut merged = MergedRules::default();
+        let rule = RuleMetadata {
+            id: "default/Dropper".into(),
+            description: String::new(),
+            provenance: "default 1.0.0".into(),
+            ab_bucket: None,
+            created_at: Utc::now(),
+            expires_at: None,
+            tags: vec![],
+            state: RuleState::Active,
+        };
+        merged.rules.insert(rule.id.clone(), rule);
+        let bundle = RuleBundle {
+            version: "1.0.0".parse().unwrap(),
+            rules: Default::default(),
+            checksum: String::new(),
+            issued_at: Utc::now(),
+            expires_at: Utc::now() + Duration::days(30),
+        };
+        BundleStore::new(dir.path(), 2)
+            .install(&[("default", &bundle)], &merged, Default::default(), Utc::now())
+            .unwrap();
+
+        assert!(rules.refresh().unwrap());
+        assert_eq!((rules.generation(), rules.get().rules.len()), (Some(1), 1));
+        assert!(!rules.refresh().unwrap());
+    }
+
+    #[test]
+    fn drops_rules_once_they_expire_within_a_generation() {
+        let dir = tempfile::tempdir().unwrap();
+        let now = Utc::now();
+        let rule = |id: &str, expires_at| RuleMetadata {
+            id: id.into(),
+            description: String::new(),
+            provenance: "default 1.0.0".into(),
+            ab_bucket: None,
+            created_at: now,
+            expires_at,
+            tags: vec![],
+            state: RuleState::Active,
+        };
+        let mut merged = MergedRules::default();
+        for rule in [rule("default/Dropper", None), rule("default/Campaign", Some(now + Duration::hours(1)))] {
+            merged.rules.insert(rule.id.clone(), rule);
+        }
+        let bundle = RuleBundle {
+            version: "1.0.0".parse().unwrap(),
+            rules: Default::default(),
+            checksum: String::new(),
+            issued_at: now,
+            expires_at: now + Duration::days(30),
+        };
+        BundleStore::new(dir.path(), 2)
+            .install(&[("default", &bundle)], &merged, Default::default(), now)
+            .unwrap();
+        let rules = ActiveRules::load(BundleStore::new(dir.path(), 2), AbAssignment::default());
+        assert_eq!(rules.get().rules.len(), 2);
+
+        assert!(!rules.refresh_at(now + Duration::minutes(30)).unwrap());
+        assert!(rules.refresh_at(now + Duration::hours(2)).unwrap());
+        let active = rules.get();
+        assert_eq!(rules.generation(), Some(1));
+        assert_eq!(active.rules.iter().map(|r| r.id.as_str()).collect::<Vec<_>>(), ["default/Dropper"]);
+        assert_eq!(active.summary.expired, 1);
+        assert!(!rules.refresh_at(now + Duration::hours(3)).unwrap());
+    }
+
+    #[test]
+    fn swapped_rules_empty_the_scan_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let rules = Arc::new(ActiveRules::load(BundleStore::new(dir.path(), 2), AbAssignment::default()));
+        let backend = Arc::new(Scanner::new(Default::default()).unwrap());
+        let cache = Arc::new(Mutex::new(ScanCache::default()));
+        let sample = tempfile::NamedTempFile::new().unwrap();
+        let fingerprint = FileFingerprint::from_metadata(&sample.as_file().metadata().unwrap());
+        cache.lock().record(sample.path().to_owned(), fingerprint, RecommendedAction::Allow);
+        let poller = UpdatePoller::new(
+            None,
+            UpdatesConfig::default(),
+            Arc::clone(&rules),
+            backend,
+            Arc::clone(&cache),
+            Arc::default(),
+        );
+
+        poller.reload();
+        assert_eq!(cache.lock().len(), 1, "unchanged rules keep the cache");
+
+        let bundle = RuleBundle {
+            version: "1.0.0".parse().unwrap(),
+            rules: Default::default(),
+            checksum: String::new(),
+            issued_at: Utc::now(),
+            expires_at: Utc::now() + Duration::days(30),
+        };
+        BundleStore::new(dir.path(), 2)
+            .install(&[("default", &bundle)], &MergedRules::default(), Default::default(), Utc::now())
+            .unwrap();
+        poller.reload();
+        assert_eq!(rules.generation(), Some(1));
+        assert!(cache.lock().is_empty());
+    }
+}
@@ -0,0 +1,649 @@
+//! Privilege-separated scanner worker processes.
+//!
+//! Parsing untrusted ELF files, archives and scripts happens in children
+//! re-executed from the daemon binary with [`WORKER_FLAG`]. They are started
+//! by a spawner process ([`SPAWNER_FLAG`]) that the daemon launches before
+//! installing its seccomp filter, so the daemon itself never needs `execve`
+//! or `kill`: it receives a pidfd for each worker instead. The broker opens
+//! each file itself and hands the descriptor over a `SOCK_SEQPACKET` socket
+//! (`SCM_RIGHTS`); the worker answers with a JSON [`ScanOutcome`]. A new
+//! rule set reaches each worker the same way, as a memfd sent ahead of its
+//! next scan. Before it
+//! accepts any request a worker revokes all filesystem access and installs
+//! the narrower worker seccomp policy, so a parser exploit reaches neither
+//! the broker's fanotify descriptors, the quarantine key nor the network.
+//! A worker that cannot do both reports why and exits; the pool does not
+//! start without confined workers.
+//!
+//! A worker that crashes, breaks protocol or overruns the scan timeout is
+//! killed and replaced; the scan that triggered it fails.
+
+use std::fs::File;
+use std::io::{self, Seek, Write};
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd, RawFd};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Context;
+use async_trait::async_trait;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Semaphore;
+use tracing::{info, warn};
+
+use av_core::config::ScannerConfig;
+use av_core::signatures::RuleSet;
+use av_core::{ScanOutcome, Scanner};
+
+use crate::config::DaemonConfig;
+use crate::dispatch::ScanBackend;
+
+/// First argument that turns the daemon binary into a scan worker.
+pub const WORKER_FLAG: &str = "--scan-worker";
+
+/// First argument that turns the daemon binary into the worker spawner. The
+/// second is the binary workers are started from.
+pub const SPAWNER_FLAG: &str = "--scan-worker-spawner";
+
+/// Largest message either side accepts; outcomes are far smaller.
+const MAX_MESSAGE: usize = 1 << 20;
+
+/// Name of the memfd rule sets are sent in, as shown in `/proc/<pid>/fd`.
+const RULES_MEMFD: &[u8] = b"av-rules\0";
+
+/// Sent once, right after the worker starts.
+#[derive(Serialize, Deserialize)]
+struct WorkerInit {
+    scanner: ScannerConfig,
+    seccomp_policy: PathBuf,
+}
+
+/// The worker's answer to [`WorkerInit`]: confined, or why not.
+type Ready = Result<(), String>;
+
+/// Accompanies each descriptor.
+#[derive(Serialize, Deserialize)]
+enum Request {
+    /// Scan the file. The path is only used for reporting.
+    Scan { path: PathBuf },
+    /// Evaluate later scans against the JSON [`RuleSet`] in the file. Not
+    /// answered.
+    Rules,
+}
+
+/// Scan errors are returned as text; the worker stays usable afterwards.
+type ScanResponse = Result<ScanOutcome, String>;
+
+/// The spawner's answer to a request: the worker's pid, sent along with a
+/// pidfd for it.
+type SpawnResponse = Result<u32, String>;
+
+/// Fixed-size set of scan workers, started eagerly and replaced on failure.
+pub struct WorkerPool {
+    /// Channel to the spawner; one request is in flight at a time.
+    spawner: Mutex<OwnedFd>,
+    init: Vec<u8>,
+    timeout: Duration,
+    idle: Mutex<Vec<WorkerProcess>>,
+    slots: Semaphore,
+    /// Version and JSON of the rules workers should hold. Version 0 is the
+    /// empty set a worker starts with.
+    rules: Mutex<(u64, Arc<[u8]>)>,
+}
+
+/// Killed when dropped; the spawner reaps it.
+struct WorkerProcess {
+    pid: u32,
+    pidfd: OwnedFd,
+    channel: Arc<OwnedFd>,
+    /// Version of the rules this worker was last sent.
+    rules: u64,
+}
+
+impl WorkerProcess {
+    fn kill(&self) {
+        // SAFETY: plain syscall on a descriptor we own; no siginfo is passed.
+        unsafe {
+            libc::syscall(
+                libc::SYS_pidfd_send_signal,
+                self.pidfd.as_raw_fd(),
+                libc::SIGKILL,
+                std::ptr::null::<libc::siginfo_t>(),
+                0,
+            )
+        };
+    }
+}
+
+impl Drop for WorkerProcess {
+    fn drop(&mut self) {
+        self.kill();
+    }
+}
+
+impl WorkerPool {
+    /// Start the spawner and `size` workers from `exe`, normally the running
+    /// daemon binary. Must run before the daemon's seccomp filter goes on.
+    pub fn start(exe: PathBuf, cfg: &DaemonConfig, size: usize) -> anyhow::Result<Self> {
+        let init = WorkerInit {
+            scanner: cfg.scanner.clone(),
+            seccomp_policy: cfg.workers.seccomp_policy.clone(),
+        };
+        let size = size.max(1);
+        let pool = Self {
+            spawner: Mutex::new(start_spawner(&exe)?),
+            init: serde_json::to_vec(&init)?,
+            timeout: Duration::from_secs(cfg.workers.scan_timeout_secs.max(1)),
+            idle: Mutex::new(Vec::with_capacity(size)),
+            slots: Semaphore::new(size),
+            rules: Mutex::new((0, Arc::from(serde_json::to_vec(&RuleSet::default())?))),
+        };
+        for _ in 0..size {
+            let worker = pool.spawn()?;
+            pool.idle.lock().push(worker);
+        }
+        info!(workers = size, timeout = ?pool.timeout, "scan workers started");
+        Ok(pool)
+    }
+
+    fn spawn(&self) -> anyhow::Result<WorkerProcess> {
+        let worker = request_worker(&self.spawner.lock())?;
+        send_message(worker.channel.as_fd(), &self.init, None).context("failed to initialise scan worker")?;
+        let (reply, _) = recv_message(worker.channel.as_fd())?.context("scan worker exited during start-up")?;
+        let ready: Ready = serde_json::from_slice(&reply)?;
+        ready.map_err(anyhow::Error::msg).context("scan worker could not be confined")?;
+        Ok(worker)
+    }
+
+    /// Kill a misbehaving worker and put a fresh one in its place. If the
+    /// replacement cannot start, the next scan tries again.
+    fn replace(&self, worker: WorkerProcess, reason: &str) {
+        warn!(pid = worker.pid, reason, "restarting scan worker");
+        drop(worker);
+        match self.spawn() {
+            Ok(worker) => self.idle.lock().push(worker),
+            Err(err) => warn!(error = %format!("{err:#}"), "scan worker restart failed"),
+        }
+    }
+}
+
+#[async_trait]
+impl ScanBackend for WorkerPool {
+    async fn scan(&self, path: &Path) -> anyhow::Result<ScanOutcome> {
+        let _slot = self.slots.acquire().await?;
+        let file = open_for_scan(path).await?;
+        let idle = self.idle.lock().pop();
+        let mut worker = match idle {
+            Some(worker) => worker,
+            None => self.spawn()?,
+        };
+
+        let (version, rules) = self.rules.lock().clone();
+        let rules = (worker.rules != version).then_some(rules);
+        let channel = Arc::clone(&worker.channel);
+        let request = serde_json::to_vec(&Request::Scan { path: path.to_path_buf() })?;
+        let exchange = tokio::task::spawn_blocking(move || {
+            if let Some(rules) = rules {
+                send_rules(&channel, &rules)?;
+            }
+            exchange(&channel, &request, &file)
+        });
+        match tokio::time::timeout(self.timeout, exchange).await {
+            Ok(Ok(Ok(response))) => {
+                worker.rules = version;
+                self.idle.lock().push(worker);
+                response.map_err(anyhow::Error::msg)
+            }
+            Ok(Ok(Err(err))) => {
+                self.replace(worker, "protocol failure");
+                Err(err.context("scan worker failed"))
+            }
+            Ok(Err(err)) => {
+                self.replace(worker, "protocol failure");
+                Err(err.into())
+            }
+            // Killing the worker closes its end, which unblocks the exchange.
+            Err(_) => {
+                self.replace(worker, "timeout");
+                anyhow::bail!("scan worker timed out after {:?}", self.timeout)
+            }
+        }
+    }
+
+    /// Workers pick the rules up before their next scan.
+    fn set_rules(&self, rules: Arc<RuleSet>) {
+        match serde_json::to_vec(&*rules) {
+            Ok(json) => {
+                let mut current = self.rules.lock();
+                *current = (current.0 + 1, Arc::from(json));
+            }
+            Err(err) => warn!(error = %err, "failed to serialise rules for scan workers"),
+        }
+    }
+}
+
+/// Launch `exe` as the spawner, connected over a socket on its stdin.
+fn start_spawner(exe: &Path) -> anyhow::Result<OwnedFd> {
+    let (daemon, spawner) = seqpacket_pair()?;
+    Command::new(exe)
+        .arg(SPAWNER_FLAG)
+        .arg(exe)
+        .stdin(Stdio::from(spawner))
+        // Only the daemon itself talks to systemd.
+        .env_remove("NOTIFY_SOCKET")
+        .env_remove("WATCHDOG_USEC")
+        .env_remove("WATCHDOG_PID")
+        .spawn()
+        .with_context(|| format!("failed to start scan worker spawner {}", exe.display()))?;
+    Ok(daemon)
+}
+
+/// Have the spawner start a worker on a fresh channel.
+fn request_worker(spawner: &OwnedFd) -> anyhow::Result<WorkerProcess> {
+    let (broker, worker) = seqpacket_pair()?;
+    send_message(spawner.as_fd(), b"spawn", Some(worker.as_fd())).context("scan worker spawner unreachable")?;
+    drop(worker);
+    let (reply, pidfd) = recv_message(spawner.as_fd())?.context("scan worker spawner exited")?;
+    let response: SpawnResponse = serde_json::from_slice(&reply)?;
+    let pid = response.map_err(anyhow::Error::msg).context("failed to start scan worker")?;
+    Ok(WorkerProcess {
+        pid,
+        pidfd: pidfd.context("spawner sent no pidfd")?,
+        channel: Arc::new(broker),
+        rules: 0,
+    })
+}
+
+/// Open read-only without blocking on FIFOs and refuse anything that is not
+/// a regular file, so workers are only ever handed plain data.
+async fn open_for_scan(path: &Path) -> anyhow::Result<File> {
+    let file = tokio::fs::OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_NONBLOCK | libc::O_NOCTTY)
+        .open(path)
+        .await?
+        .into_std()
+        .await;
+    anyhow::ensure!(file.metadata()?.is_file(), "{} is not a regular file", path.display());
+    Ok(file)
+}
+
+/// Send `rules` (JSON) in a memfd rather than inline, since a rule set can
+/// outgrow [`MAX_MESSAGE`].
+fn send_rules(channel: &OwnedFd, rules: &[u8]) -> anyhow::Result<()> {
+    // SAFETY: the name is NUL-terminated and the flags are valid.
+    let fd = unsafe { libc::memfd_create(RULES_MEMFD.as_ptr().cast(), libc::MFD_CLOEXEC) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error()).context("failed to create rule memfd");
+    }
+    // SAFETY: the descriptor was just created and is owned by nobody else.
+    let mut file = unsafe { File::from_raw_fd(fd) };
+    file.write_all(rules)?;
+    file.rewind()?;
+    send_message(channel.as_fd(), &serde_json::to_vec(&Request::Rules)?, Some(file.as_fd()))?;
+    Ok(())
+}
+
+/// One request/response round trip on the broker side.
+fn exchange(channel: &OwnedFd, request: &[u8], file: &File) -> anyhow::Result<ScanResponse> {
+    send_message(channel.as_fd(), request, Some(file.as_fd()))?;
+    let (reply, _) = recv_message(channel.as_fd())?.context("scan worker exited")?;
+    Ok(serde_json::from_slice(&reply)?)
+}
+
+/// Entry point for `av-daemon --scan-worker-spawner <exe>`. The channel to
+/// the daemon is stdin.
+pub fn spawner_main() -> anyhow::Result<()> {
+    let exe = std::env::args_os().nth(2).context("spawner started without a worker binary")?;
+    // SAFETY: the daemon passes our end of the socket pair as fd 0 and
+    // nothing else in this process owns it.
+    let channel = unsafe { OwnedFd::from_raw_fd(libc::STDIN_FILENO) };
+    // SAFETY: plain prctl; workers die with the spawner, which dies with the
+    // daemon.
+    unsafe { libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGKILL) };
+    spawn_workers(channel.as_fd(), Path::new(&exe))
+}
+
+/// Start a worker for each request, on the channel it carries, until the
+/// daemon closes its end.
+fn spawn_workers(channel: BorrowedFd<'_>, exe: &Path) -> anyhow::Result<()> {
+    while let Some((_, fd)) = recv_message(channel)? {
+        let spawned = match fd {
+            Some(fd) => spawn_worker(exe, fd).map_err(|err| format!("{err:#}")),
+            None => Err("spawn request carried no worker channel".into()),
+        };
+        let (response, pidfd): (SpawnResponse, _) = match spawned {
+            Ok((pid, pidfd)) => (Ok(pid), Some(pidfd)),
+            Err(err) => (Err(err), None),
+        };
+        send_message(channel, &serde_json::to_vec(&response)?, pidfd.as_ref().map(|fd| fd.as_fd()))?;
+    }
+    Ok(())
+}
+
+fn spawn_worker(exe: &Path, channel: OwnedFd) -> anyhow::Result<(u32, OwnedFd)> {
+    let mut child = Command::new(exe)
+        .arg(WORKER_FLAG)
+        .stdin(Stdio::from(channel))
+        .spawn()
+        .with_context(|| format!("failed to start scan worker {}", exe.display()))?;
+    // Not reaped yet, so the pid still names this child.
+    let pidfd = match pidfd_open(child.id()) {
+        Ok(pidfd) => pidfd,
+        Err(err) => {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(err).context("pidfd_open failed");
+        }
+    };
+    let pid = child.id();
+    std::thread::spawn(move || child.wait());
+    Ok((pid, pidfd))
+}
+
+fn pidfd_open(pid: u32) -> io::Result<OwnedFd> {
+    // SAFETY: plain syscall without pointer arguments.
+    let fd = unsafe { libc::syscall(libc::SYS_pidfd_open, pid as libc::pid_t, 0) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: the descriptor was just created and is owned by nobody else.
+    Ok(unsafe { OwnedFd::from_raw_fd(fd as RawFd) })
+}
+
+/// Entry point for `av-daemon --scan-worker`. The channel to the broker is
+/// stdin.
+pub fn worker_main() -> anyhow::Result<()> {
+    // SAFETY: the broker passes our end of the socket pair as fd 0 and
+    // nothing else in this process owns it.
+    let channel = unsafe { OwnedFd::from_raw_fd(libc::STDIN_FILENO) };
+    // SAFETY: plain prctl; a worker must not outlive its broker.
+    unsafe { libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGKILL) };
+
+    let Some((init, _)) = recv_message(channel.as_fd())? else {
+        return Ok(());
+    };
+    let init: WorkerInit = serde_json::from_slice(&init).context("malformed worker init")?;
+    let scanner = Scanner::new(init.scanner).context("failed to init scanner")?;
+    // No runtime threads exist yet; the blocking pool is created lazily and
+    // inherits both restrictions.
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .context("failed to start worker runtime")?;
+    if let Err(err) = crate::security::confine_worker(&init.seccomp_policy) {
+        let ready: Ready = Err(format!("{err:#}"));
+        send_message(channel.as_fd(), &serde_json::to_vec(&ready)?, None)?;
+        return Err(err.context("refusing to scan unconfined"));
+    }
+    send_message(channel.as_fd(), &serde_json::to_vec(&Ready::Ok(()))?, None)?;
+
+    serve(channel.as_fd(), &scanner, &runtime)
+}
+
+/// Answer requests until the broker closes the channel. A rule set that
+/// cannot be read ends the worker, as the broker has no answer to wait for.
+fn serve(channel: BorrowedFd<'_>, scanner: &Scanner, runtime: &tokio::runtime::Runtime) -> anyhow::Result<()> {
+    while let Some((request, fd)) = recv_message(channel)? {
+        let response: ScanResponse = match (serde_json::from_slice::<Request>(&request), fd) {
+            (Ok(Request::Rules), Some(fd)) => {
+                let rules: RuleSet =
+                    serde_json::from_reader(io::BufReader::new(File::from(fd))).context("malformed rule set")?;
+                scanner.set_rules(Arc::new(rules));
+                continue;
+            }
+            (Ok(Request::Rules), None) => anyhow::bail!("rule set carried no file descriptor"),
+            (Ok(Request::Scan { path }), Some(fd)) => runtime
+                .block_on(scanner.scan_file(File::from(fd), &path))
+                .map_err(|err| format!("{err:#}")),
+            (Ok(Request::Scan { .. }), None) => Err("request carried no file descriptor".into()),
+            (Err(err), _) => Err(format!("malformed request: {err}")),
+        };
+        send_message(channel, &serde_json::to_vec(&response)?, None)?;
+    }
+    Ok(())
+}
+
+fn seqpacket_pair() -> io::Result<(OwnedFd, OwnedFd)> {
+    let mut fds = [0 as RawFd; 2];
+    // SAFETY: `fds` has room for the two descriptors socketpair returns.
+    let rc = unsafe {
+        libc::socketpair(
+            libc::AF_UNIX,
+            libc::SOCK_SEQPACKET | libc::SOCK_CLOEXEC,
+            0,
+            fds.as_mut_ptr(),
+        )
+    };
+    if rc != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: both descriptors were just created and are owned by nobody else.
+    Ok(unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) })
+}
+
+/// Room for one `SCM_RIGHTS` header and descriptor, suitably aligned.
+type ControlBuffer = [u64; 4];
+
+fn send_message(socket: BorrowedFd<'_>, payload: &[u8], fd: Option<BorrowedFd<'_>>) -> io::Result<()> {
+    let mut iov = libc::iovec {
+        iov_base: payload.as_ptr() as *mut libc::c_void,
+        iov_len: payload.len(),
+    };
+    let mut control: ControlBuffer = [0; 4];
+    // SAFETY: msghdr is plain data; zeroed is its documented initial state.
+    let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    if let Some(fd) = fd {
+        // SAFETY: the control buffer outlives `msg` and is large and aligned
+        // enough for one cmsghdr carrying a single descriptor.
+        unsafe {
+            msg.msg_control = control.as_mut_ptr().cast();
+            msg.msg_controllen = libc::CMSG_SPACE(std::mem::size_of::<RawFd>() as u32) as _;
+            let cmsg = libc::CMSG_FIRSTHDR(&msg);
+            (*cmsg).cmsg_level = libc::SOL_SOCKET;
+            (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+            (*cmsg).cmsg_len = libc::CMSG_LEN(std::mem::size_of::<RawFd>() as u32) as _;
+            std::ptr::write_unaligned(libc::CMSG_DATA(cmsg).cast::<RawFd>(), fd.as_raw_fd());
+        }
+    }
+    loop {
+        // SAFETY: `msg` points at live buffers for the duration of the call.
+        let sent = unsafe { libc::sendmsg(socket.as_raw_fd(), &msg, libc::MSG_NOSIGNAL) };
+        if sent >= 0 {
+            return Ok(());
+        }
+        let err = io::Error::last_os_error();
+        if err.kind() != io::ErrorKind::Interrupted {
+            return Err(err);
+        }
+    }
+}
+
+/// Receive one message and the descriptor attached to it, if any. `None`
+/// means the peer closed its end.
+fn recv_message(socket: BorrowedFd<'_>) -> io::Result<Option<(Vec<u8>, Option<OwnedFd>)>> {
+    let mut buf = vec![0u8; MAX_MESSAGE];
+    let mut iov = libc::iovec {
+        iov_base: buf.as_mut_ptr().cast(),
+        iov_len: buf.len(),
+    };
+    let mut control: ControlBuffer = [0; 4];
+    // SAFETY: msghdr is plain data; zeroed is its documented initial state.
+    let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr().cast();
+    msg.msg_controllen = std::mem::size_of::<ControlBuffer>() as _;
+
+    let received = loop {
+        // SAFETY: `msg` points at live buffers for the duration of the call.
+        let n = unsafe { libc::recvmsg(socket.as_raw_fd(), &mut msg, libc::MSG_CMSG_CLOEXEC) };
+        if n >= 0 {
+            break n as usize;
+        }
+        let err = io::Error::last_os_error();
+        if err.kind() != io::ErrorKind::Interrupted {
+            return Err(err);
+        }
+    };
+
+    let mut fds = vec![];
+    // SAFETY: the kernel filled in the control buffer and `msg_controllen`;
+    // the CMSG_* macros only walk within it.
+    unsafe {
+        let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+        while !cmsg.is_null() {
+            if (*cmsg).cmsg_level == libc::SOL_SOCKET && (*cmsg).cmsg_type == libc::SCM_RIGHTS {
+                let data = libc::CMSG_DATA(cmsg).cast::<RawFd>();
+                let count = ((*cmsg).cmsg_len as usize - libc::CMSG_LEN(0) as usize) / std::mem::size_of::<RawFd>();
+                for i in 0..count {
+                    fds.push(OwnedFd::from_raw_fd(std::ptr::read_unaligned(data.add(i))));
+                }
+            }
+            cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+        }
+    }
+
+    if received == 0 && fds.is_empty() {
+        return Ok(None);
+    }
+    if msg.msg_flags & (libc::MSG_TRUNC | libc::MSG_CTRUNC) != 0 {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "oversized message on worker channel"));
+    }
+    buf.truncate(received);
+    // Extra descriptors are closed on drop; requests carry exactly one.
+    Ok(Some((buf, fds.into_iter().next())))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    fn spawn_worker(scanner: Scanner) -> (OwnedFd, std::thread::JoinHandle<anyhow::Result<()>>) {
+        let (broker, worker) = seqpacket_pair().unwrap();
+        let handle = std::thread::spawn(move || {
+            let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build()?;
+            serve(worker.as_fd(), &scanner, &runtime)
+        });
+        (broker, handle)
+    }
+
+    #[test]
+    fn scans_descriptor_passed_over_channel() {
+        let mut sample = tempfile::NamedTempFile::new().unwrap();
+        sample.write_all(b"#!/bin/sh\necho hello\n").unwrap();
+        let (broker, worker) = spawn_worker(Scanner::new(ScannerConfig::default()).unwrap());
+
+        let request = serde_json::to_vec(&Request::Scan { path: PathBuf::from("/home/user/hello.sh") }).unwrap();
+        let outcome = exchange(&broker, &request, sample.as_file()).unwrap().unwrap();
+        // The worker reports the broker's path; it never saw the real one.
+        assert_eq!(outcome.path, "/home/user/hello.sh");
+
+        send_message(broker.as_fd(), &request, None).unwrap();
+        let (reply, _) = recv_message(broker.as_fd()).unwrap().unwrap();
+        let response: ScanResponse = serde_json::from_slice(&reply).unwrap();
+        assert!(response.unwrap_err().contains("no file descriptor"));
+
+        drop(broker);
+        worker.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn worker_evaluates_against_the_rules_sent_last() {
+        use crate::dispatch::tests::{rule_set, Matches};
+        use av_core::signatures::RuleState;
+
+        let sample = tempfile::tempfile().unwrap();
+        let scanner = Scanner::with_matcher(ScannerConfig::default(), Box::new(Matches(&["new-packer"]))).unwrap();
+        let (broker, worker) = spawn_worker(scanner);
+        let request = serde_json::to_vec(&Request::Scan { path: PathBuf::from("/tmp/sample") }).unwrap();
+        let scan = || exchange(&broker, &request, &sample).unwrap().unwrap();
+
+        assert!(scan().signatures.is_empty(), "no rules loaded yet");
+        let rules = rule_set(&[("vendor/new-packer", RuleState::Experimental)]);
+        send_rules(&broker, &serde_json::to_vec(&*rules).unwrap()).unwrap();
+        let outcome = scan();
+        assert_eq!(outcome.signatures.len(), 1);
+        assert!(outcome.signatures[0].shadow);
+
+        send_rules(&broker, &serde_json::to_vec(&RuleSet::default()).unwrap()).unwrap();
+        assert!(scan().signatures.is_empty());
+
+        drop(broker);
+        worker.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn spawner_starts_workers_and_hands_back_a_pidfd() {
+        use std::os::unix::fs::PermissionsExt;
+
+        // Stands in for the daemon binary: records its arguments and idles.
+        let dir = tempfile::tempdir().unwrap();
+        let exe = dir.path().join("av-daemon");
+        std::fs::write(&exe, "#!/bin/sh\necho \"$@\" > \"$0.args\"\nexec sleep 60\n").unwrap();
+        std::fs::set_permissions(&exe, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let (daemon, spawner) = seqpacket_pair().unwrap();
+        let spawner = std::thread::spawn({
+            let exe = exe.clone();
+            move || spawn_workers(spawner.as_fd(), &exe)
+        });
+
+        let worker = request_worker(&daemon).unwrap();
+        let args = dir.path().join("av-daemon.args");
+        let started = std::time::Instant::now();
+        while std::fs::read_to_string(&args).map_or(true, |args| args.is_empty()) {
+            assert!(started.elapsed() < Duration::from_secs(10), "worker never started");
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(std::fs::read_to_string(&args).unwrap().trim(), WORKER_FLAG);
+
+        // A pidfd turns readable once its process has exited.
+        let pidfd = worker.pidfd.try_clone().unwrap();
+        drop(worker);
+        let mut poll = libc::pollfd { fd: pidfd.as_raw_fd(), events: libc::POLLIN, revents: 0 };
+        // SAFETY: `poll` is a single valid pollfd.
+        assert_eq!(unsafe { libc::poll(&mut poll, 1, 10_000) }, 1, "worker survived being dropped");
+
+        drop(daemon);
+        spawner.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn confined_worker_cannot_open_paths() {
+        let mut sample = tempfile::NamedTempFile::new().unwrap();
+        sample.write_all(b"MZ").unwrap();
+        let passed = File::open(sample.path()).unwrap();
+        let path = sample.path().to_path_buf();
+        // Landlock is per-thread; confine a scratch thread, not the harness.
+        std::thread::spawn(move || {
+            match crate::security::revoke_filesystem() {
+                Ok(_) => {}
+                Err(err) if err.to_string().contains("unavailable") => return,
+                Err(err) => panic!("{err:#}"),
+            }
+            let err = File::open(&path).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+            assert!(std::fs::read_dir("/").is_err());
+            // Descriptors handed over by the broker keep working.
+            let mut data = Vec::new();
+            io::Read::read_to_end(&mut &passed, &mut data).unwrap();
+            assert_eq!(data, b"MZ");
+        })
+        .join()
+        .unwrap();
+    }
+
+    #[test]
+    fn dead_worker_fails_the_exchange() {
+        let (broker, worker) = seqpacket_pair().unwrap();
+        drop(worker);
+        let sample = tempfile::tempfile().unwrap();
+        assert!(exchange(&broker, b"{}", &sample).is_err());
+    }
+}
@@ -0,0 +1,261 @@
+//! Hybrid ML-KEM-768 + X25519 key encapsulation for quarantine data keys.
+//!
+//! A shared secret stays confidential as long as either ML-KEM (FIPS 203)
+//! or X25519 holds, so the artefacts are protected against a future quantum
+//! adversary without betting everything on the newer scheme. The two
+//! secrets are combined as in X-Wing: SHA3-256 over both secrets, the X25519
+//! ciphertext and recipient key, and a domain label. ML-KEM's ciphertext is
+//! left out of the hash, as ML-KEM already binds it into its own secret.
+//!
+//! Only the public key is needed to quarantine. The secret key stays with
+//! whoever is allowed to restore.
+//!
+//! All-zero key material is refused outright: the quarantine once
+//! defaulted to an all-zero key, and such a key protects nothing.
+
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use anyhow::Context;
+use av_core::{base64_bytes, persist};
+use fips203::ml_kem_768;
+use fips203::traits::{Decaps as _, Encaps as _, KeyGen as _, SerDes as _};
+use serde::{Deserialize, Serialize};
+use sha3::{Digest, Sha3_256};
+use zeroize::Zeroizing;
+
+use crate::random_bytes;
+
+const LABEL: &[u8] = b"charmedwoa-av quarantine ML-KEM-768+X25519";
+
+const ZERO_KEY: &str = "refusing to use an all-zero quarantine key";
+
+/// A 32-byte secret agreed through [`encapsulate`].
+pub type SharedSecret = Zeroizing<[u8; 32]>;
+
+/// Key that artefacts are quarantined to.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QuarantinePublicKey {
+    #[serde(with = "base64_bytes")]
+    pub ml_kem: Vec<u8>,
+    #[serde(with = "base64_bytes")]
+    pub x25519: Vec<u8>,
+}
+
+/// Key needed to restore quarantined artefacts. Stored as the ML-KEM seed
+/// and the X25519 scalar; the public half is derived from them.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(try_from = "SecretKeyFields")]
+pub struct QuarantineSecretKey {
+    #[serde(with = "base64_bytes")]
+    ml_kem_seed: Vec<u8>,
+    #[serde(with = "base64_bytes")]
+    x25519: Vec<u8>,
+}
+
+/// A [`QuarantineSecretKey`] as serialized, before its lengths and the
+/// all-zero check are validated.
+#[derive(Deserialize)]
+struct SecretKeyFields {
+    #[serde(with = "base64_bytes")]
+    ml_kem_seed: Vec<u8>,
+    #[serde(with = "base64_bytes")]
+    x25519: Vec<u8>,
+}
+
+impl TryFrom<SecretKeyFields> for QuarantineSecretKey {
+    type Error = anyhow::Error;
+
+    fn try_from(fields: SecretKeyFields) -> anyhow::Result<Self> {
+        let key = Self {
+            ml_kem_seed: fields.ml_kem_seed,
+            x25519: fields.x25519,
+        };
+        key.parts()?;
+        Ok(key)
+    }
+}
+
+/// What the recipient needs, besides the secret key, to recover the secret.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Encapsulation {
+    #[serde(with = "base64_bytes")]
+    pub ml_kem: Vec<u8>,
+    /// Ephemeral X25519 public key.
+    #[serde(with = "base64_bytes")]
+    pub x25519: Vec<u8>,
+}
+
+impl fmt::Debug for QuarantineSecretKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("QuarantineSecretKey").finish_non_exhaustive()
+    }
+}
+
+impl QuarantineSecretKey {
+    pub fn generate() -> anyhow::Result<Self> {
+        Ok(Self {
+            ml_kem_seed: random_bytes::<64>()?.to_vec(),
+            x25519: random_bytes::<32>()?.to_vec(),
+        })
+    }
+
+    /// Read a key written by [`QuarantineSecretKey::save`].
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let bytes = Zeroizing::new(fs::read(path).with_context(|| format!("reading {}", path.display()))?);
+        serde_json::from_slice(&bytes).with_context(|| format!("malformed quarantine key {}", path.display()))
+    }
+
+    /// Write the key to a new owner-only file in the key store's secret
+    /// directory, or wherever a restore will read it from.
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        persist::write_new(path, &Zeroizing::new(serde_json::to_vec_pretty(self)?), 0o600)
+    }
+
+    pub fn public_key(&self) -> QuarantinePublicKey {
+        // Keys are only built by `generate` or validated deserialization.
+        let (ek, _, x25519) = self.parts().expect("validated on construction");
+        QuarantinePublicKey {
+            ml_kem: ek.into_bytes().to_vec(),
+            x25519: x25519_dalek::PublicKey::from(&x25519).as_bytes().to_vec(),
+        }
+    }
+
+    fn parts(&self) -> anyhow::Result<(ml_kem_768::EncapsKey, ml_kem_768::DecapsKey, x25519_dalek::StaticSecret)> {
+        anyhow::ensure!(!is_zero(&self.ml_kem_seed) && !is_zero(&self.x25519), ZERO_KEY);
+        let seed: &[u8; 64] = self.ml_kem_seed.as_slice().try_into().context("ML-KEM seed is not 64 bytes")?;
+        let (d, z) = seed.split_at(32);
+        let (ek, dk) = ml_kem_768::KG::keygen_from_seed(d.try_into()?, z.try_into()?);
+        let scalar: [u8; 32] = self.x25519.as_slice().try_into().context("X25519 key is not 32 bytes")?;
+        Ok((ek, dk, x25519_dalek::StaticSecret::from(scalar)))
+    }
+}
+
+/// Agree on a fresh secret with the holder of `recipient`'s secret key.
+pub fn encapsulate(recipient: &QuarantinePublicKey) -> anyhow::Result<(SharedSecret, Encapsulation)> {
+    anyhow::ensure!(!is_zero(&recipient.ml_kem) && !is_zero(&recipient.x25519), ZERO_KEY);
+    let ek: [u8; ml_kem_768::EK_LEN] =
+        recipient.ml_kem.as_slice().try_into().context("malformed ML-KEM public key")?;
+    let ek = ml_kem_768::EncapsKey::try_from_bytes(ek).map_err(|err| anyhow::anyhow!("ML-KEM public key: {err}"))?;
+    let (ml_kem_secret, ciphertext) = ek.try_encaps().map_err(|err| anyhow::anyhow!("ML-KEM: {err}"))?;
+
+    let recipient_x25519 = x25519_public(&recipient.x25519)?;
+    let ephemeral = x25519_dalek::StaticSecret::from(*random_bytes::<32>()?);
+    let ephemeral_public = x25519_dalek::PublicKey::from(&ephemeral);
+    let x25519_secret = ephemeral.diffie_hellman(&recipient_x25519);
+    anyhow::ensure!(x25519_secret.was_contributory(), "X25519 public key is a low-order point");
+
+    let encapsulation = Encapsulation {
+        ml_kem: ciphertext.into_bytes().to_vec(),
+        x25519: ephemeral_public.as_bytes().to_vec(),
+    };
+    let secret = combine(
+        &ml_kem_secret.into_bytes(),
+        x25519_secret.as_bytes(),
+        ephemeral_public.as_bytes(),
+        recipient_x25519.as_bytes(),
+    );
+    Ok((secret, encapsulation))
+}
+
+/// Recover the secret [`encapsulate`] agreed on for `key`'s public half.
+pub fn decapsulate(key: &QuarantineSecretKey, encapsulation: &Encapsulation) -> anyhow::Result<SharedSecret> {
+    let (_, dk, scalar) = key.parts()?;
+    let ciphertext: [u8; ml_kem_768::CT_LEN] =
+        encapsulation.ml_kem.as_slice().try_into().context("malformed ML-KEM ciphertext")?;
+    let ciphertext =
+        ml_kem_768::CipherText::try_from_bytes(ciphertext).map_err(|err| anyhow::anyhow!("ML-KEM ciphertext: {err}"))?;
+    let ml_kem_secret = dk.try_decaps(&ciphertext).map_err(|err| anyhow::anyhow!("ML-KEM: {err}"))?;
+
+    let ephemeral_public = x25519_public(&encapsulation.x25519)?;
+    let x25519_secret = scalar.diffie_hellman(&ephemeral_public);
+    anyhow::ensure!(x25519_secret.was_contributory(), "X25519 ciphertext is a low-order point");
+    Ok(combine(
+        &ml_kem_secret.into_bytes(),
+        x25519_secret.as_bytes(),
+        ephemeral_public.as_bytes(),
+        x25519_dalek::PublicKey::from(&scalar).as_bytes(),
+    ))
+}
+
+fn is_zero(bytes: &[u8]) -> bool {
+    bytes.iter().all(|&b| b == 0)
+}
+
+fn x25519_public(bytes: &[u8]) -> anyhow::Result<x25519_dalek::PublicKey> {
+    let bytes: [u8; 32] = bytes.try_into().context("X25519 public key is not 32 bytes")?;
+    Ok(x25519_dalek::PublicKey::from(bytes))
+}
+
+fn combine(ml_kem: &[u8; 32], x25519: &[u8; 32], ciphertext: &[u8; 32], recipient: &[u8; 32]) -> SharedSecret {
+    let mut hasher = Sha3_256::new();
+    hasher.update(ml_kem);
+    hasher.update(x25519);
+    hasher.update(ciphertext);
+    hasher.update(recipient);
+    hasher.update(LABEL);
+    Zeroizing::new(hasher.finalize().into())
+}
+
+#[cfg(test)]
+mod tests {
+    use base64::engine::general_purpose::STANDARD as BASE64;
+    use base64::Engine;
+
+    use super::*;
+
+    #[test]
+    fn both_halves_contribute_to_the_secret() {
+        let key = QuarantineSecretKey::generate().unwrap();
+        let public = key.public_key();
+        let (secret, encapsulation) = encapsulate(&public).unwrap();
+        assert_eq!(*decapsulate(&key, &encapsulation).unwrap(), *secret);
+
+        // ML-KEM decapsulates a tampered ciphertext to an unrelated secret
+        // instead of failing.
+        let mut tampered = encapsulation.clone();
+        tampered.ml_kem[0] ^= 1;
+        assert_ne!(*decapsulate(&key, &tampered).unwrap(), *secret);
+        let mut tampered = encapsulation.clone();
+        tampered.x25519[0] ^= 1;
+        assert_ne!(*decapsulate(&key, &tampered).unwrap(), *secret);
+
+        let other = QuarantineSecretKey::generate().unwrap();
+        assert_ne!(*decapsulate(&other, &encapsulation).unwrap(), *secret);
+
+        let low_order = QuarantinePublicKey {
+            x25519: std::iter::once(1).chain([0; 31]).collect(),
+            ..public.clone()
+        };
+        assert!(encapsulate(&low_order).is_err());
+        let zero = QuarantinePublicKey {
+            ml_kem: vec![0; ml_kem_768::EK_LEN],
+            ..public
+        };
+        assert_eq!(encapsulate(&zero).unwrap_err().to_string(), ZERO_KEY);
+        let zero = QuarantineSecretKey {
+            ml_kem_seed: vec![0; 64],
+            x25519: vec![0; 32],
+        };
+        assert_eq!(decapsulate(&zero, &encapsulation).unwrap_err().to_string(), ZERO_KEY);
+
+        for (seed, scalar) in [(vec![0; 64], vec![0; 32]), (vec![7; 16], vec![7; 32])] {
+            let json = serde_json::json!({ "ml_kem_seed": BASE64.encode(seed), "x25519": BASE64.encode(scalar) });
+            assert!(serde_json::from_value::<QuarantineSecretKey>(json).is_err());
+        }
+    }
+
+    #[test]
+    fn saved_key_is_owner_only_and_reloads() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("quarantine.key");
+        let key = QuarantineSecretKey::generate().unwrap();
+        key.save(&path).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        assert!(key.save(&path).is_err());
+        assert_eq!(QuarantineSecretKey::load(&path).unwrap().public_key(), key.public_key());
+    }
+}
@@ -0,0 +1,287 @@
+//! Versioned quarantine key pairs.
+//!
+//! Version `N` is the public key `v<N>.pub`, readable by anyone so the
+//! quarantining process can wrap to it, and the [`QuarantineSecretKey`]
+//! `secret/v<N>.key`, in a directory only its owner (root) can enter. The
+//! highest version is the one new items are wrapped to. Every wrapped data
+//! key names the [`KeyId`] it was wrapped to, so items stay restorable
+//! while older versions are retained. Rotation adds a version, re-wraps all
+//! items to it ([`crate::QuarantineManager::rotate`]) and only then retires
+//! the older versions.
+//!
+//! Keys are stored in files rather than the kernel keyring: keyrings do
+//! not survive a reboot, so a file would have to back them regardless.
+
+use std::fmt;
+use std::fs;
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use anyhow::Context;
+use av_core::persist;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use sha2::{Digest, Sha256};
+
+use crate::kem::{QuarantinePublicKey, QuarantineSecretKey};
+
+pub const KEY_DIR: &str = "/var/lib/av/quarantine-keys";
+
+/// Key version and public key fingerprint, written as `v<version>-<hex>`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct KeyId {
+    pub version: u32,
+    /// First 8 bytes of the SHA-256 of the public key, hex encoded.
+    pub fingerprint: String,
+}
+
+impl KeyId {
+    pub fn new(version: u32, key: &QuarantinePublicKey) -> Self {
+        Self {
+            version,
+            fingerprint: fingerprint(key),
+        }
+    }
+
+    pub fn matches(&self, key: &QuarantinePublicKey) -> bool {
+        self.fingerprint == fingerprint(key)
+    }
+}
+
+impl fmt::Display for KeyId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "v{}-{}", self.version, self.fingerprint)
+    }
+}
+
+impl FromStr for KeyId {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let (version, fingerprint) = s
+            .strip_prefix('v')
+            .and_then(|rest| rest.split_once('-'))
+            .ok_or_else(|| anyhow::anyhow!("malformed quarantine key id `{s}`"))?;
+        Ok(Self {
+            version: version.parse().with_context(|| format!("malformed quarantine key id `{s}`"))?,
+            fingerprint: fingerprint.to_string(),
+        })
+    }
+}
+
+impl Serialize for KeyId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for KeyId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// A public key together with the id recorded on items wrapped to it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RecipientKey {
+    pub id: KeyId,
+    pub key: QuarantinePublicKey,
+}
+
+pub struct KeyStore {
+    dir: PathBuf,
+}
+
+impl KeyStore {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    /// Create the directories and a first key pair unless one exists.
+    /// Returns the id of a newly generated key.
+    pub fn init(&self) -> anyhow::Result<Option<KeyId>> {
+        for (dir, mode) in [(&self.dir, 0o755), (&self.secret_dir(), 0o700)] {
+            if !dir.exists() {
+                fs::DirBuilder::new()
+                    .recursive(true)
+                    .create(dir)
+                    .with_context(|| format!("creating {}", dir.display()))?;
+                // Set explicitly: the umask may be stricter or looser.
+                fs::set_permissions(dir, fs::Permissions::from_mode(mode))?;
+            }
+        }
+        if self.versions()?.is_empty() {
+            return self.generate_next().map(Some);
+        }
+        Ok(None)
+    }
+
+    /// Retained key versions, oldest first.
+    pub fn versions(&self) -> anyhow::Result<Vec<u32>> {
+        check_not_replaceable(&self.dir)?;
+        let mut versions = Vec::new();
+        for entry in fs::read_dir(&self.dir).with_context(|| format!("reading {}", self.dir.display()))? {
+            let name = entry?.file_name();
+            let version = name
+                .to_str()
+                .and_then(|name| name.strip_prefix('v')?.strip_suffix(".pub")?.parse::<u32>().ok());
+            versions.extend(version);
+        }
+        versions.sort_unstable();
+        Ok(versions)
+    }
+
+    /// Key new items are wrapped to. Only reads public keys.
+    pub fn recipient(&self) -> anyhow::Result<RecipientKey> {
+        let version = *self
+            .versions()?
+            .last()
+            .with_context(|| format!("no quarantine keys in {}", self.dir.display()))?;
+        let path = self.public_path(version);
+        check_not_replaceable(&path)?;
+        let bytes = fs::read(&path).with_context(|| format!("reading {}", path.display()))?;
+        let key: QuarantinePublicKey =
+            serde_json::from_slice(&bytes).with_context(|| format!("malformed quarantine key {}", path.display()))?;
+        Ok(RecipientKey {
+            id: KeyId::new(version, &key),
+            key,
+        })
+    }
+
+    /// Secret key of `id`, checked against its fingerprint.
+    pub fn secret_key(&self, id: &KeyId) -> anyhow::Result<QuarantineSecretKey> {
+        check_owner_only(&self.secret_dir())?;
+        let path = self.secret_path(id.version);
+        check_owner_only(&path)?;
+        let key = QuarantineSecretKey::load(&path)?;
+        anyhow::ensure!(id.matches(&key.public_key()), "{} holds a different key than {id}", path.display());
+        Ok(key)
+    }
+
+    /// Generate a key one version above the newest. The public key is
+    /// written last, so a version only exists once both halves do.
+    pub fn generate_next(&self) -> anyhow::Result<KeyId> {
+        let version = self.versions()?.last().map_or(1, |v| v + 1);
+        check_owner_only(&self.secret_dir())?;
+        let key = QuarantineSecretKey::generate()?;
+        key.save(&self.secret_path(version))?;
+        let public = key.public_key();
+        persist::write_new(&self.public_path(version), &serde_json::to_vec_pretty(&public)?, 0o644)?;
+        Ok(KeyId::new(version, &public))
+    }
+
+    /// Delete every version below `version`, secret key first. Returns the
+    /// deleted versions.
+    pub fn retire_before(&self, version: u32) -> anyhow::Result<Vec<u32>> {
+        let retired: Vec<u32> = self.versions()?.into_iter().filter(|&v| v < version).collect();
+        for &old in &retired {
+            for path in [self.secret_path(old), self.public_path(old)] {
+                match fs::remove_file(&path) {
+                    Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+                        return Err(err).with_context(|| format!("removing {}", path.display()));
+                    }
+                    _ => {}
+                }
+            }
+        }
+        Ok(retired)
+    }
+
+    fn secret_dir(&self) -> PathBuf {
+        self.dir.join("secret")
+    }
+
+    fn public_path(&self, version: u32) -> PathBuf {
+        self.dir.join(format!("v{version}.pub"))
+    }
+
+    fn secret_path(&self, version: u32) -> PathBuf {
+        self.secret_dir().join(format!("v{version}.key"))
+    }
+}
+
+/// Refuse public keys, and the directory holding them, that anyone but
+/// root or the current user could replace.
+fn check_not_replaceable(path: &Path) -> anyhow::Result<()> {
+    let metadata = fs::metadata(path).with_context(|| format!("reading {}", path.display()))?;
+    // SAFETY: geteuid has no preconditions and cannot fail.
+    let euid = unsafe { libc::geteuid() };
+    anyhow::ensure!(
+        (metadata.uid() == 0 || metadata.uid() == euid) && metadata.permissions().mode() & 0o022 == 0,
+        "refusing {}: must be owned by root or uid {euid} and writable by its owner only (owner {}, mode {:o})",
+        path.display(),
+        metadata.uid(),
+        metadata.permissions().mode() & 0o777
+    );
+    Ok(())
+}
+
+/// Refuse secret keys that someone besides their owner could read or
+/// replace.
+fn check_owner_only(path: &Path) -> anyhow::Result<()> {
+    let metadata = fs::metadata(path).with_context(|| format!("reading {}", path.display()))?;
+    // SAFETY: geteuid has no preconditions and cannot fail.
+    let euid = unsafe { libc::geteuid() };
+    anyhow::ensure!(
+        metadata.uid() == euid && metadata.permissions().mode() & 0o077 == 0,
+        "refusing {}: must be owned by uid {euid} and inaccessible to anyone else (owner {}, mode {:o})",
+        path.display(),
+        metadata.uid(),
+        metadata.permissions().mode() & 0o777
+    );
+    Ok(())
+}
+
+fn fingerprint(key: &QuarantinePublicKey) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(&key.ml_kem);
+    hasher.update(&key.x25519);
+    hex::encode(&hasher.finalize()[..8])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generates_once_and_versions_keys() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = KeyStore::new(dir.path().join("keys"));
+        let first = store.init().unwrap().unwrap();
+        assert_eq!(first.version, 1);
+        let mode = |path: PathBuf| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode(dir.path().join("keys")), 0o755);
+        assert_eq!(mode(store.secret_dir()), 0o700);
+        assert_eq!(mode(store.public_path(1)), 0o644);
+        assert_eq!(mode(store.secret_path(1)), 0o600);
+        assert_eq!(store.init().unwrap(), None);
+        assert_eq!(store.recipient().unwrap().id, first);
+
+        let second = store.generate_next().unwrap();
+        assert_eq!(second.version, 2);
+        assert_eq!(second.to_string().parse::<KeyId>().unwrap(), second);
+        assert_eq!(store.recipient().unwrap().id, second);
+        assert_ne!(store.secret_key(&first).unwrap().public_key(), store.recipient().unwrap().key);
+        let mismatched = KeyId {
+            version: 1,
+            ..second.clone()
+        };
+        assert!(store.secret_key(&mismatched).is_err());
+
+        assert_eq!(store.retire_before(2).unwrap(), vec![1]);
+        assert!(store.secret_key(&first).is_err());
+        assert!(!store.public_path(1).exists());
+
+        // Wrapping needs the public key only.
+        fs::rename(store.secret_dir(), dir.path().join("elsewhere")).unwrap();
+        assert_eq!(store.recipient().unwrap().id, second);
+        fs::rename(dir.path().join("elsewhere"), store.secret_dir()).unwrap();
+
+        fs::set_permissions(store.secret_path(2), fs::Permissions::from_mode(0o640)).unwrap();
+        let err = store.secret_key(&second).unwrap_err();
+        assert!(err.to_string().contains("inaccessible to anyone else"), "{err}");
+        fs::set_permissions(store.public_path(2), fs::Permissions::from_mode(0o666)).unwrap();
+        let err = store.recipient().unwrap_err();
+        assert!(err.to_string().contains("writable by its owner only"), "{err}");
+    }
+}
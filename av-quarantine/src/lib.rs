@@ -1,47 +1,91 @@
 //! Quarantine manager implementing copy-on-write, integrity-verified
 //! workflows. All operations are opt-in and reversible until a user
 //! explicitly purges artefacts.
+//!
+//! Every artefact is encrypted with its own AES-256-GCM data key. The data
+//! key is wrapped to the quarantine public key through the hybrid KEM in
+//! [`kem`] and stored in the item's record, so quarantining needs only the
+//! public key while restoring needs the secret key. Key pairs are versioned
+//! and rotated through [`keys::KeyStore`].
 
+pub mod kem;
+pub mod keys;
+
+use std::collections::HashMap;
 use std::fs::{self, File, OpenOptions};
-use std::io::{Read, Seek, SeekFrom, Write};
+use std::io::{Read, Write};
 use std::path::{Path, PathBuf};
 
 use anyhow::Context;
-use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM};
-use ring::rand::SystemRandom;
+use av_core::{base64_bytes, persist};
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
 use ring::rand::SecureRandom;
+use ring::rand::SystemRandom;
+use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
+use zeroize::Zeroizing;
+
+use crate::kem::{Encapsulation, QuarantineSecretKey};
+use crate::keys::{KeyId, KeyStore, RecipientKey};
 
 const QUARANTINE_ROOT: &str = "/var/lib/av/quarantine";
 
 #[derive(Debug, Clone)]
 pub struct QuarantineConfig {
     pub root: PathBuf,
-    pub encryption_key: [u8; 32],
+    /// Key new items are wrapped to; quarantining fails without one.
+    pub recipient: Option<RecipientKey>,
 }
 
 impl Default for QuarantineConfig {
     fn default() -> Self {
         Self {
             root: PathBuf::from(QUARANTINE_ROOT),
-            encryption_key: [0; 32],
+            recipient: None,
         }
     }
 }
 
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct QuarantineRecord {
     pub id: String,
     pub original_path: PathBuf,
     pub sha256: String,
     pub size: u64,
     pub timestamp: chrono::DateTime<chrono::Utc>,
+    /// Absent on items stored under the former all-zero shared key.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key: Option<WrappedKey>,
+}
+
+/// An item's data key, encrypted under a secret shared with the holder of
+/// the quarantine secret key.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WrappedKey {
+    /// Quarantine key the data key is wrapped to.
+    pub key_id: KeyId,
+    pub encapsulation: Encapsulation,
+    /// Nonce followed by the AES-256-GCM sealed data key.
+    #[serde(with = "base64_bytes")]
+    pub data_key: Vec<u8>,
 }
 
 pub struct QuarantineManager {
     cfg: QuarantineConfig,
 }
 
+/// Outcome of [`QuarantineManager::rotate`].
+#[derive(Debug, Clone, Serialize)]
+pub struct Rotation {
+    pub key_id: KeyId,
+    pub rewrapped: usize,
+    /// Key versions deleted once nothing was wrapped to them any more.
+    pub retired: Vec<u32>,
+    /// Items stored under the former all-zero key, re-encrypted under a
+    /// fresh data key wrapped to the new version.
+    pub migrated: Vec<String>,
+}
+
 impl QuarantineManager {
     pub fn new(cfg: QuarantineConfig) -> anyhow::Result<Self> {
         fs::create_dir_all(&cfg.root)?;
@@ -52,6 +96,7 @@ impl QuarantineManager {
     /// is left untouched; the caller can remove it only after verifying the
     /// stored artefact.
     pub fn quarantine(&self, path: &Path) -> anyhow::Result<QuarantineRecord> {
+        let recipient = self.cfg.recipient.as_ref().context("no quarantine public key configured")?;
         let mut src = File::open(path)?;
         let mut data = Vec::new();
         src.read_to_end(&mut data)?;
@@ -61,7 +106,9 @@ impl QuarantineManager {
         let id = format!("{}-{}", chrono::Utc::now().timestamp(), sha256);
         let dest_path = self.cfg.root.join(&id);
 
-        let encrypted = self.encrypt(&data)?;
+        let data_key = random_bytes::<32>()?;
+        let key = wrap(recipient, &data_key, &id)?;
+        let encrypted = seal(&data_key, &id, &data)?;
         let mut dest = OpenOptions::new().create_new(true).write(true).open(&dest_path)?;
         dest.write_all(&encrypted)?;
         dest.flush()?;
@@ -72,16 +119,37 @@ impl QuarantineManager {
             sha256,
             size,
             timestamp: chrono::Utc::now(),
+            key: Some(key),
         };
         self.persist_metadata(&record)?;
         Ok(record)
     }
 
-    pub fn restore(&self, record: &QuarantineRecord, destination: &Path) -> anyhow::Result<()> {
+    pub fn restore(
+        &self,
+        record: &QuarantineRecord,
+        secret_key: &QuarantineSecretKey,
+        destination: &Path,
+    ) -> anyhow::Result<()> {
+        let key = record.key.as_ref().with_context(|| {
+            format!(
+                "item {} was stored under the all-zero quarantine key; run `quarantine keys rotate` to migrate it",
+                record.id
+            )
+        })?;
+        anyhow::ensure!(
+            key.key_id.matches(&secret_key.public_key()),
+            "item {} is wrapped to quarantine key {}, not the one given",
+            record.id,
+            key.key_id
+        );
+        let data_key = unwrap(secret_key, key, &record.id)
+            .with_context(|| format!("unwrapping the data key of item {}", record.id))?;
+
         let encrypted_path = self.cfg.root.join(&record.id);
         let mut encrypted = Vec::new();
         File::open(&encrypted_path)?.read_to_end(&mut encrypted)?;
-        let decrypted = self.decrypt(&encrypted)?;
+        let decrypted = open(&data_key, &record.id, &encrypted)?;
 
         anyhow::ensure!(to_hex_hash(&decrypted) == record.sha256, "integrity mismatch");
 
@@ -90,33 +158,159 @@ impl QuarantineManager {
         Ok(())
     }
 
-    fn persist_metadata(&self, record: &QuarantineRecord) -> anyhow::Result<()> {
-        let metadata_path = self.cfg.root.join(format!("{}.json", record.id));
-        let mut file = File::create(metadata_path)?;
-        let json = serde_json::to_vec_pretty(record)?;
-        file.write_all(&json)?;
-        Ok(())
+    /// Records of every quarantined item.
+    pub fn records(&self) -> anyhow::Result<Vec<QuarantineRecord>> {
+        let mut records = Vec::new();
+        for entry in fs::read_dir(&self.cfg.root)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "json") {
+                let bytes = fs::read(&path).with_context(|| format!("reading {}", path.display()))?;
+                let record = serde_json::from_slice(&bytes).with_context(|| format!("malformed record {}", path.display()))?;
+                records.push(record);
+            }
+        }
+        Ok(records)
     }
 
-    fn encrypt(&self, data: &[u8]) -> anyhow::Result<Vec<u8>> {
-        let key = LessSafeKey::new(UnboundKey::new(&AES_256_GCM, &self.cfg.encryption_key)?);
-        let nonce = random_nonce()?;
-        let mut buffer = data.to_vec();
-        key.seal_in_place_append_tag(Nonce::assume_unique_for_key(nonce), Aad::empty(), &mut buffer)?;
-        let mut output = nonce.to_vec();
-        output.extend_from_slice(&buffer);
-        Ok(output)
+    /// Generate a new version in `store`, re-wrap every item's data key to it
+    /// and retire the older versions. The artefacts themselves are not
+    /// re-encrypted, except those stored under the former all-zero key,
+    /// which are migrated to a fresh data key. Older versions are kept if any
+    /// item fails to re-wrap, so an interrupted rotation can simply be run
+    /// again.
+    pub fn rotate(&self, store: &KeyStore) -> anyhow::Result<Rotation> {
+        store.generate_next()?;
+        let recipient = store.recipient()?;
+        let mut secret_keys: HashMap<KeyId, QuarantineSecretKey> = HashMap::new();
+        let mut rewrapped = 0;
+        let mut migrated = Vec::new();
+        for mut record in self.records()? {
+            let Some(key) = &record.key else {
+                self.migrate_legacy(&mut record, &recipient)
+                    .with_context(|| format!("migrating item {} off the all-zero key", record.id))?;
+                migrated.push(record.id);
+                continue;
+            };
+            let staging_path = self.migration_staging_path(&record.id);
+            if staging_path.exists() {
+                // The record of a migration cut short is already written;
+                // only the artefact is left to replace.
+                persist::commit(&staging_path, &self.cfg.root.join(&record.id))?;
+            }
+            if key.key_id == recipient.id {
+                continue;
+            }
+            if !secret_keys.contains_key(&key.key_id) {
+                secret_keys.insert(key.key_id.clone(), store.secret_key(&key.key_id)?);
+            }
+            let data_key = unwrap(&secret_keys[&key.key_id], key, &record.id)
+                .with_context(|| format!("unwrapping the data key of item {}", record.id))?;
+            record.key = Some(wrap(&recipient, &data_key, &record.id)?);
+            self.persist_metadata(&record)?;
+            rewrapped += 1;
+        }
+        Ok(Rotation {
+            retired: store.retire_before(recipient.id.version)?,
+            key_id: recipient.id,
+            rewrapped,
+            migrated,
+        })
     }
 
-    fn decrypt(&self, data: &[u8]) -> anyhow::Result<Vec<u8>> {
-        anyhow::ensure!(data.len() > 12, "ciphertext too short");
-        let (nonce_bytes, cipher) = data.split_at(12);
-        let key = LessSafeKey::new(UnboundKey::new(&AES_256_GCM, &self.cfg.encryption_key)?);
-        let mut buffer = cipher.to_vec();
-        key.open_in_place(Nonce::assume_unique_for_key(<[u8; 12]>::try_from(nonce_bytes)?), Aad::empty(), &mut buffer)?;
-        // ring returns decrypted data in place with trailing tag removed
-        Ok(buffer)
+    /// Re-encrypt an item stored under the all-zero key in the former format
+    /// (`nonce || ciphertext || tag`, no associated data) under a fresh data
+    /// key wrapped to `recipient`. The new artefact is staged next to the old
+    /// one and only replaces it once the record naming its key is written.
+    fn migrate_legacy(&self, record: &mut QuarantineRecord, recipient: &RecipientKey) -> anyhow::Result<()> {
+        let encrypted_path = self.cfg.root.join(&record.id);
+        let encrypted = fs::read(&encrypted_path).with_context(|| format!("reading {}", encrypted_path.display()))?;
+        let decrypted = open_legacy(&encrypted)?;
+        anyhow::ensure!(to_hex_hash(&decrypted) == record.sha256, "integrity mismatch");
+
+        let data_key = random_bytes::<32>()?;
+        let staging_path = self.migration_staging_path(&record.id);
+        let mut staging = File::create(&staging_path)?;
+        staging.write_all(&seal(&data_key, &record.id, &decrypted)?)?;
+        staging.sync_all()?;
+        record.key = Some(wrap(recipient, &data_key, &record.id)?);
+        self.persist_metadata(record)?;
+        persist::commit(&staging_path, &encrypted_path)
     }
+
+    fn migration_staging_path(&self, id: &str) -> PathBuf {
+        self.cfg.root.join(format!("{id}.migrating"))
+    }
+
+    /// Write the record through a temporary file, so re-wrapping never
+    /// leaves a torn record behind.
+    fn persist_metadata(&self, record: &QuarantineRecord) -> anyhow::Result<()> {
+        let metadata_path = self.cfg.root.join(format!("{}.json", record.id));
+        persist::write_atomic(&metadata_path, &serde_json::to_vec_pretty(record)?)
+    }
+}
+
+/// Wrap `data_key` for the holder of `recipient`'s secret key, bound to
+/// the item `id`.
+fn wrap(recipient: &RecipientKey, data_key: &[u8; 32], id: &str) -> anyhow::Result<WrappedKey> {
+    let (secret, encapsulation) = kem::encapsulate(&recipient.key)?;
+    Ok(WrappedKey {
+        key_id: recipient.id.clone(),
+        encapsulation,
+        data_key: seal(&secret, id, data_key)?,
+    })
+}
+
+fn unwrap(secret_key: &QuarantineSecretKey, key: &WrappedKey, id: &str) -> anyhow::Result<Zeroizing<[u8; 32]>> {
+    let secret = kem::decapsulate(secret_key, &key.encapsulation)?;
+    let data_key = Zeroizing::new(open(&secret, id, &key.data_key).context("wrapped data key does not authenticate")?);
+    Ok(Zeroizing::new(data_key.as_slice().try_into().context("data key is not 32 bytes")?))
+}
+
+/// AES-256-GCM with a random nonce, returned as `nonce || ciphertext || tag`.
+/// The item id is authenticated so content cannot be swapped between items.
+fn seal(key: &[u8; 32], id: &str, data: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let key = aes_key(key)?;
+    let nonce = random_bytes::<NONCE_LEN>()?;
+    let mut buffer = data.to_vec();
+    key.seal_in_place_append_tag(Nonce::assume_unique_for_key(*nonce), Aad::from(id.as_bytes()), &mut buffer)
+        .map_err(|_| anyhow::anyhow!("AES-256-GCM encryption failed"))?;
+    let mut output = nonce.to_vec();
+    output.extend_from_slice(&buffer);
+    Ok(output)
+}
+
+fn open(key: &[u8; 32], id: &str, data: &[u8]) -> anyhow::Result<Vec<u8>> {
+    anyhow::ensure!(data.len() > NONCE_LEN, "ciphertext too short");
+    let (nonce_bytes, cipher) = data.split_at(NONCE_LEN);
+    let nonce = Nonce::try_assume_unique_for_key(nonce_bytes).map_err(|_| anyhow::anyhow!("malformed nonce"))?;
+    let mut buffer = cipher.to_vec();
+    let plain_len = aes_key(key)?
+        .open_in_place(nonce, Aad::from(id.as_bytes()), &mut buffer)
+        .map_err(|_| anyhow::anyhow!("ciphertext does not authenticate"))?
+        .len();
+    // ring decrypts in place and leaves the tag behind the plaintext
+    buffer.truncate(plain_len);
+    Ok(buffer)
+}
+
+/// Decrypt an artefact written before data keys were introduced: sealed
+/// under the all-zero key with no associated data.
+fn open_legacy(data: &[u8]) -> anyhow::Result<Vec<u8>> {
+    anyhow::ensure!(data.len() > NONCE_LEN, "ciphertext too short");
+    let (nonce_bytes, cipher) = data.split_at(NONCE_LEN);
+    let nonce = Nonce::try_assume_unique_for_key(nonce_bytes).map_err(|_| anyhow::anyhow!("malformed nonce"))?;
+    let mut buffer = cipher.to_vec();
+    let plain_len = aes_key(&[0; 32])?
+        .open_in_place(nonce, Aad::empty(), &mut buffer)
+        .map_err(|_| anyhow::anyhow!("ciphertext does not authenticate under the all-zero key"))?
+        .len();
+    buffer.truncate(plain_len);
+    Ok(buffer)
+}
+
+fn aes_key(key: &[u8; 32]) -> anyhow::Result<LessSafeKey> {
+    let key = UnboundKey::new(&AES_256_GCM, key).map_err(|_| anyhow::anyhow!("invalid AES-256-GCM key"))?;
+    Ok(LessSafeKey::new(key))
 }
 
 fn to_hex_hash(data: &[u8]) -> String {
@@ -125,9 +319,118 @@ fn to_hex_hash(data: &[u8]) -> String {
     hex::encode(hasher.finalize())
 }
 
-fn random_nonce() -> anyhow::Result<[u8; 12]> {
+pub(crate) fn random_bytes<const N: usize>() -> anyhow::Result<Zeroizing<[u8; N]>> {
     let rng = SystemRandom::new();
-    let mut nonce = [0u8; 12];
-    rng.fill(&mut nonce)?;
-    Ok(nonce)
+    let mut bytes = Zeroizing::new([0u8; N]);
+    rng.fill(bytes.as_mut())
+        .map_err(|_| anyhow::anyhow!("system randomness unavailable"))?;
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn manager(dir: &Path, store: &KeyStore) -> QuarantineManager {
+        QuarantineManager::new(QuarantineConfig {
+            root: dir.join("quarantine"),
+            recipient: Some(store.recipient().unwrap()),
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn quarantines_with_public_key_and_restores_with_secret_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let sample = dir.path().join("sample.bin");
+        fs::write(&sample, b"MZ not really a dropper").unwrap();
+        let store = KeyStore::new(dir.path().join("keys"));
+        let key_id = store.init().unwrap().unwrap();
+        let manager = manager(dir.path(), &store);
+
+        let first = manager.quarantine(&sample).unwrap();
+        assert_eq!(first.key.as_ref().unwrap().key_id, key_id);
+        let stored = fs::read(dir.path().join("quarantine").join(&first.id)).unwrap();
+        assert!(!stored.windows(6).any(|w| w == b"really"));
+
+        let restored = dir.path().join("restored.bin");
+        let secret_key = store.secret_key(&key_id).unwrap();
+        manager.restore(&first, &secret_key, &restored).unwrap();
+        assert_eq!(fs::read(&restored).unwrap(), b"MZ not really a dropper");
+
+        let other_key = QuarantineSecretKey::generate().unwrap();
+        let err = manager.restore(&first, &other_key, &restored).unwrap_err();
+        assert!(err.to_string().contains(&format!("wrapped to quarantine key {key_id}")), "{err}");
+
+        // Data keys are fresh per item and bound to the item id.
+        fs::write(&sample, b"second sample").unwrap();
+        let second = manager.quarantine(&sample).unwrap();
+        assert_ne!(first.key, second.key);
+        let swapped = QuarantineRecord {
+            key: second.key.clone(),
+            ..first.clone()
+        };
+        assert!(manager.restore(&swapped, &secret_key, &restored).is_err());
+
+        let legacy = QuarantineRecord { key: None, ..first };
+        let err = manager.restore(&legacy, &secret_key, &restored).unwrap_err();
+        assert!(err.to_string().contains("all-zero quarantine key"), "{err}");
+        assert!(QuarantineManager::new(QuarantineConfig {
+            root: dir.path().join("quarantine"),
+            recipient: None,
+        })
+        .unwrap()
+        .quarantine(&sample)
+        .is_err());
+    }
+
+    #[test]
+    fn rotation_rewraps_items_and_retires_old_keys() {
+        let dir = tempfile::tempdir().unwrap();
+        let sample = dir.path().join("sample.bin");
+        fs::write(&sample, b"payload").unwrap();
+        let store = KeyStore::new(dir.path().join("keys"));
+        let old = store.init().unwrap().unwrap();
+        let manager = manager(dir.path(), &store);
+        let item = manager.quarantine(&sample).unwrap();
+        // As written before data keys: all-zero key, no associated data.
+        let legacy = QuarantineRecord {
+            id: "0-legacy".into(),
+            key: None,
+            ..item.clone()
+        };
+        let nonce = [7u8; NONCE_LEN];
+        let mut sealed = b"payload".to_vec();
+        aes_key(&[0; 32])
+            .unwrap()
+            .seal_in_place_append_tag(Nonce::assume_unique_for_key(nonce), Aad::empty(), &mut sealed)
+            .unwrap();
+        fs::write(dir.path().join("quarantine/0-legacy"), [nonce.as_slice(), &sealed].concat()).unwrap();
+        manager.persist_metadata(&legacy).unwrap();
+
+        let rotation = manager.rotate(&store).unwrap();
+        assert_eq!(rotation.key_id.version, 2);
+        assert_eq!((rotation.rewrapped, rotation.retired.as_slice()), (1, [1].as_slice()));
+        assert_eq!(rotation.migrated, vec!["0-legacy".to_string()]);
+        assert!(store.secret_key(&old).is_err());
+        assert!(!dir.path().join("quarantine/0-legacy.migrating").exists());
+
+        let record = manager.records().unwrap().into_iter().find(|r| r.id == item.id).unwrap();
+        let key = record.key.as_ref().unwrap();
+        assert_eq!(key.key_id, rotation.key_id);
+        assert_eq!(key.data_key.len(), item.key.as_ref().unwrap().data_key.len());
+        let restored = dir.path().join("restored.bin");
+        manager.restore(&record, &store.secret_key(&key.key_id).unwrap(), &restored).unwrap();
+        assert_eq!(fs::read(&restored).unwrap(), b"payload");
+
+        let migrated = manager.records().unwrap().into_iter().find(|r| r.id == "0-legacy").unwrap();
+        let secret_key = store.secret_key(&migrated.key.as_ref().unwrap().key_id).unwrap();
+        fs::remove_file(&restored).unwrap();
+        manager.restore(&migrated, &secret_key, &restored).unwrap();
+        assert_eq!(fs::read(&restored).unwrap(), b"payload");
+
+        // Nothing is left for a second rotation to migrate.
+        let rotation = manager.rotate(&store).unwrap();
+        assert_eq!((rotation.rewrapped, rotation.migrated.len()), (2, 0));
+    }
 }
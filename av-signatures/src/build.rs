@@ -0,0 +1,156 @@
+//! Packaging locally maintained YARA rules as signed bundles.
+//!
+//! Rules are linted first and a bundle is only built from a clean rule set.
+//! Each rule's metadata comes from its `meta:` block: the required
+//! `description`, `author` and `ab_bucket`, plus optional `date` and
+//! `expires` (`YYYY-MM-DD` or RFC 3339) and `state`. Rule tags become the
+//! metadata tags.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use anyhow::Context;
+use chrono::{DateTime, NaiveDate, Utc};
+use semver::Version;
+
+use av_core::rules::{self, RuleSource, Severity};
+use av_core::signatures::{RuleBundle, RuleMetadata, RuleState};
+
+use crate::crypto::SigningKey;
+use crate::envelope::{Envelope, RULE_BUNDLE_TYPE};
+
+/// Build an unsigned bundle of the rules under `paths`, released at
+/// `issued_at` and valid until `expires_at`.
+pub fn build_bundle(
+    paths: &[PathBuf],
+    version: Version,
+    issued_at: DateTime<Utc>,
+    expires_at: DateTime<Utc>,
+) -> anyhow::Result<RuleBundle> {
+    anyhow::ensure!(expires_at > issued_at, "bundle would expire before it is issued");
+    let errors: Vec<String> = rules::lint(paths)?
+        .into_iter()
+        .filter(|d| d.severity == Severity::Error)
+        .map(|d| d.to_string())
+        .collect();
+    anyhow::ensure!(errors.is_empty(), "rules do not lint cleanly:\n{}", errors.join("\n"));
+
+    let mut bundle = RuleBundle {
+        version,
+        rules: HashMap::new(),
+        checksum: String::new(),
+        issued_at,
+        expires_at,
+    };
+    for file in rules::rule_files(paths)? {
+        let source = std::fs::read_to_string(&file).with_context(|| format!("reading {}", file.display()))?;
+        for rule in rules::parse(&source) {
+            let metadata = metadata(&rule, issued_at).with_context(|| format!("rule `{}` in {}", rule.name, file.display()))?;
+            bundle.rules.insert(rule.name, metadata);
+        }
+    }
+    bundle.checksum = bundle.content_checksum();
+    Ok(bundle)
+}
+
+/// Sign a JSON-encoded bundle as-is, after checking it parses and its
+/// checksum matches its content.
+pub fn sign_bundle(payload: Vec<u8>, keys: &[&SigningKey]) -> anyhow::Result<Envelope> {
+    anyhow::ensure!(!keys.is_empty(), "no signing keys given");
+    let bundle: RuleBundle = serde_json::from_slice(&payload).context("malformed rule bundle")?;
+    bundle.verify(&bundle.content_checksum())?;
+    Envelope::sign(RULE_BUNDLE_TYPE, payload, keys)
+}
+
+fn metadata(rule: &RuleSource, issued_at: DateTime<Utc>) -> anyhow::Result<RuleMetadata> {
+    let meta = |key: &str| rule.meta.get(key).map(String::as_str);
+    let state = match meta("state") {
+        Some(state) => serde_json::from_value(serde_json::Value::from(state))
+            .with_context(|| format!("unknown state `{state}`"))?,
+        None => RuleState::default(),
+    };
+    Ok(RuleMetadata {
+        id: rule.name.clone(),
+        description: meta("description").unwrap_or_default().to_string(),
+        provenance: meta("author").unwrap_or_default().to_string(),
+        ab_bucket: meta("ab_bucket").map(str::to_string),
+        created_at: meta("date").map(parse_date).transpose()?.unwrap_or(issued_at),
+        expires_at: meta("expires").map(parse_date).transpose()?,
+        tags: rule.tags.clone(),
+        state,
+    })
+}
+
+fn parse_date(value: &str) -> anyhow::Result<DateTime<Utc>> {
+    if let Ok(date) = NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        return Ok(date.and_hms_opt(0, 0, 0).expect("midnight exists").and_utc());
+    }
+    Ok(DateTime::parse_from_rfc3339(value)
+        .with_context(|| format!("`{value}` is neither YYYY-MM-DD nor RFC 3339"))?
+        .with_timezone(&Utc))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::SignatureAlgorithm;
+    use crate::policy::SignaturePolicy;
+    use crate::UpdateSource;
+
+    #[test]
+    fn builds_and_signs_local_rules() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("internal.yar"),
+            r#"
+rule InternalDropper : dropper
+{
+    meta:
+        description = "Dropper seen on build hosts"
+        author = "SOC"
+        ab_bucket = "canary"
+        date = "2026-10-01"
+        expires = "2027-04-01T00:00:00Z"
+        state = "experimental"
+    strings:
+        $a = "stage2.bin"
+    condition:
+        $a
+}
+"#,
+        )
+        .unwrap();
+        let issued_at = "2026-10-18T00:00:00Z".parse().unwrap();
+        let expires_at = "2027-01-16T00:00:00Z".parse().unwrap();
+        let bundle = build_bundle(&[dir.path().to_path_buf()], "1.2.0".parse().unwrap(), issued_at, expires_at).unwrap();
+        let rule = &bundle.rules["InternalDropper"];
+        assert_eq!(rule.provenance, "SOC");
+        assert_eq!(rule.ab_bucket.as_deref(), Some("canary"));
+        assert_eq!(rule.created_at, "2026-10-01T00:00:00Z".parse::<DateTime<Utc>>().unwrap());
+        assert_eq!(rule.expires_at, Some("2027-04-01T00:00:00Z".parse().unwrap()));
+        assert_eq!((rule.tags.as_slice(), rule.state), (["dropper".to_string()].as_slice(), RuleState::Experimental));
+        assert_eq!(bundle.checksum, bundle.content_checksum());
+
+        let ed25519 = SigningKey::generate(SignatureAlgorithm::Ed25519).unwrap();
+        let ml_dsa = SigningKey::generate(SignatureAlgorithm::MlDsa65).unwrap();
+        let payload = serde_json::to_vec_pretty(&bundle).unwrap();
+        let envelope = sign_bundle(payload.clone(), &[&ed25519, &ml_dsa]).unwrap();
+        let source: UpdateSource = serde_json::from_value(serde_json::json!({
+            "name": "internal",
+            "url": "https://rules.internal.example/latest.json",
+            "keys": [ed25519.public_key(), ml_dsa.public_key()],
+            "policy": SignaturePolicy::RequireAll,
+        }))
+        .unwrap();
+        let verified = crate::verify_signed(&source, &serde_json::to_vec(&envelope).unwrap()).unwrap();
+        assert_eq!(verified.checksum, bundle.checksum);
+
+        let mut tampered = bundle.clone();
+        tampered.rules.get_mut("InternalDropper").unwrap().state = RuleState::Active;
+        assert!(sign_bundle(serde_json::to_vec(&tampered).unwrap(), &[&ed25519]).is_err());
+        assert!(sign_bundle(payload, &[]).is_err());
+
+        std::fs::write(dir.path().join("bad.yar"), "rule NoMeta { condition: true }").unwrap();
+        assert!(build_bundle(&[dir.path().to_path_buf()], "1.2.1".parse().unwrap(), issued_at, expires_at).is_err());
+    }
+}
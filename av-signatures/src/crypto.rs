@@ -0,0 +1,307 @@
+//! Signature algorithms accepted for rule bundles.
+//!
+//! Ed25519 remains supported for existing sources; ML-DSA (FIPS 204) and
+//! SLH-DSA (FIPS 205) provide post-quantum alternatives. A key is always
+//! bound to one algorithm, so an algorithm identifier taken from an
+//! envelope only selects among configured keys and never changes how a key
+//! is interpreted. Both post-quantum schemes are used with an empty
+//! context string, matching the default of other FIPS 204/205 tooling.
+
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::Context;
+use av_core::{base64_bytes, persist};
+use ed25519_dalek::Signer as _;
+use fips204::traits::{SerDes as _, Signer as _, Verifier as _};
+use fips205::traits::{SerDes as _, Signer as _, Verifier as _};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use sha2::{Digest, Sha256};
+
+const CONTEXT: &[u8] = b"";
+
+// Per-parameter-set glue; the fips204/fips205 types differ only in sizes.
+macro_rules! ml_dsa_verify {
+    ($set:ident, $alg:expr, $key:expr, $msg:expr, $sig:expr) => {{
+        use fips204::$set;
+        let key: [u8; $set::PK_LEN] = $key.as_slice().try_into().map_err(|_| SignatureError::MalformedKey($alg))?;
+        let key = $set::PublicKey::try_from_bytes(key).map_err(|_| SignatureError::MalformedKey($alg))?;
+        let sig: [u8; $set::SIG_LEN] = $sig.try_into().map_err(|_| SignatureError::MalformedSignature($alg))?;
+        key.verify($msg, &sig, CONTEXT)
+    }};
+}
+
+macro_rules! slh_dsa_verify {
+    ($set:ident, $alg:expr, $key:expr, $msg:expr, $sig:expr) => {{
+        use fips205::$set;
+        let key: [u8; $set::PK_LEN] = $key.as_slice().try_into().map_err(|_| SignatureError::MalformedKey($alg))?;
+        let key = $set::PublicKey::try_from_bytes(&key).map_err(|_| SignatureError::MalformedKey($alg))?;
+        let sig: [u8; $set::SIG_LEN] = $sig.try_into().map_err(|_| SignatureError::MalformedSignature($alg))?;
+        key.verify($msg, &sig, CONTEXT)
+    }};
+}
+
+macro_rules! keygen {
+    ($set:path) => {{
+        use $set as set;
+        let (public, secret) = set::try_keygen().map_err(anyhow::Error::msg)?;
+        (secret.into_bytes().to_vec(), public.into_bytes().to_vec())
+    }};
+}
+
+macro_rules! ml_dsa_sign {
+    ($set:ident, $secret:expr, $msg:expr, $malformed:expr) => {{
+        use fips204::$set;
+        let secret: [u8; $set::SK_LEN] = $secret.as_slice().try_into().map_err(|_| $malformed())?;
+        let secret = $set::PrivateKey::try_from_bytes(secret).map_err(|_| $malformed())?;
+        secret.try_sign($msg, CONTEXT).map_err(anyhow::Error::msg)?.to_vec()
+    }};
+}
+
+macro_rules! slh_dsa_sign {
+    ($set:ident, $secret:expr, $msg:expr, $malformed:expr) => {{
+        use fips205::$set;
+        let secret: [u8; $set::SK_LEN] = $secret.as_slice().try_into().map_err(|_| $malformed())?;
+        let secret = $set::PrivateKey::try_from_bytes(&secret).map_err(|_| $malformed())?;
+        secret.try_sign($msg, CONTEXT, true).map_err(anyhow::Error::msg)?.to_vec()
+    }};
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum SignatureAlgorithm {
+    Ed25519,
+    MlDsa44,
+    MlDsa65,
+    MlDsa87,
+    SlhDsaSha2_128s,
+    SlhDsaSha2_128f,
+    SlhDsaShake128s,
+    SlhDsaShake128f,
+}
+
+impl SignatureAlgorithm {
+    pub const ALL: [SignatureAlgorithm; 8] = [
+        Self::Ed25519,
+        Self::MlDsa44,
+        Self::MlDsa65,
+        Self::MlDsa87,
+        Self::SlhDsaSha2_128s,
+        Self::SlhDsaSha2_128f,
+        Self::SlhDsaShake128s,
+        Self::SlhDsaShake128f,
+    ];
+
+    /// Identifier used in envelopes and configuration.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Ed25519 => "ed25519",
+            Self::MlDsa44 => "ml-dsa-44",
+            Self::MlDsa65 => "ml-dsa-65",
+            Self::MlDsa87 => "ml-dsa-87",
+            Self::SlhDsaSha2_128s => "slh-dsa-sha2-128s",
+            Self::SlhDsaSha2_128f => "slh-dsa-sha2-128f",
+            Self::SlhDsaShake128s => "slh-dsa-shake-128s",
+            Self::SlhDsaShake128f => "slh-dsa-shake-128f",
+        }
+    }
+
+    pub fn is_post_quantum(self) -> bool {
+        self != Self::Ed25519
+    }
+}
+
+impl fmt::Display for SignatureAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for SignatureAlgorithm {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|alg| alg.name().eq_ignore_ascii_case(s))
+            .ok_or_else(|| anyhow::anyhow!("unknown signature algorithm `{s}`"))
+    }
+}
+
+impl Serialize for SignatureAlgorithm {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.name())
+    }
+}
+
+impl<'de> Deserialize<'de> for SignatureAlgorithm {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        name.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+#[derive(Clone, Copy, Debug, thiserror::Error, PartialEq, Eq)]
+pub enum SignatureError {
+    #[error("malformed {0} public key")]
+    MalformedKey(SignatureAlgorithm),
+    #[error("malformed {0} signature")]
+    MalformedSignature(SignatureAlgorithm),
+    #[error("{0} signature does not verify")]
+    Invalid(SignatureAlgorithm),
+}
+
+/// A verification key, as listed for an update source.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PublicKey {
+    pub algorithm: SignatureAlgorithm,
+    /// Raw key bytes, base64 in configuration.
+    #[serde(with = "base64_bytes")]
+    pub key: Vec<u8>,
+}
+
+impl PublicKey {
+    /// Stable identifier: hex SHA-256 over the algorithm name and key bytes,
+    /// so the same bytes under two algorithms never share an id.
+    pub fn key_id(&self) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(self.algorithm.name().as_bytes());
+        hasher.update([0]);
+        hasher.update(&self.key);
+        hex::encode(hasher.finalize())
+    }
+
+    pub fn verify(&self, message: &[u8], signature: &[u8]) -> Result<(), SignatureError> {
+        let alg = self.algorithm;
+        let valid = match alg {
+            SignatureAlgorithm::Ed25519 => {
+                let key = ed25519_dalek::VerifyingKey::try_from(self.key.as_slice())
+                    .map_err(|_| SignatureError::MalformedKey(alg))?;
+                let sig = ed25519_dalek::Signature::from_slice(signature)
+                    .map_err(|_| SignatureError::MalformedSignature(alg))?;
+                key.verify_strict(message, &sig).is_ok()
+            }
+            SignatureAlgorithm::MlDsa44 => ml_dsa_verify!(ml_dsa_44, alg, &self.key, message, signature),
+            SignatureAlgorithm::MlDsa65 => ml_dsa_verify!(ml_dsa_65, alg, &self.key, message, signature),
+            SignatureAlgorithm::MlDsa87 => ml_dsa_verify!(ml_dsa_87, alg, &self.key, message, signature),
+            SignatureAlgorithm::SlhDsaSha2_128s => slh_dsa_verify!(slh_dsa_sha2_128s, alg, &self.key, message, signature),
+            SignatureAlgorithm::SlhDsaSha2_128f => slh_dsa_verify!(slh_dsa_sha2_128f, alg, &self.key, message, signature),
+            SignatureAlgorithm::SlhDsaShake128s => slh_dsa_verify!(slh_dsa_shake_128s, alg, &self.key, message, signature),
+            SignatureAlgorithm::SlhDsaShake128f => slh_dsa_verify!(slh_dsa_shake_128f, alg, &self.key, message, signature),
+        };
+        if valid {
+            Ok(())
+        } else {
+            Err(SignatureError::Invalid(alg))
+        }
+    }
+}
+
+/// A private signing key, for building bundles and for tests.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SigningKey {
+    pub algorithm: SignatureAlgorithm,
+    #[serde(with = "base64_bytes")]
+    secret: Vec<u8>,
+    #[serde(with = "base64_bytes")]
+    public: Vec<u8>,
+}
+
+impl fmt::Debug for SigningKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SigningKey").field("algorithm", &self.algorithm).finish_non_exhaustive()
+    }
+}
+
+impl SigningKey {
+    pub fn generate(algorithm: SignatureAlgorithm) -> anyhow::Result<Self> {
+        let (secret, public) = match algorithm {
+            SignatureAlgorithm::Ed25519 => {
+                let mut seed = [0u8; 32];
+                ring::rand::SecureRandom::fill(&ring::rand::SystemRandom::new(), &mut seed)
+                    .map_err(|_| anyhow::anyhow!("system randomness unavailable"))?;
+                let key = ed25519_dalek::SigningKey::from_bytes(&seed);
+                (seed.to_vec(), key.verifying_key().to_bytes().to_vec())
+            }
+            SignatureAlgorithm::MlDsa44 => keygen!(fips204::ml_dsa_44),
+            SignatureAlgorithm::MlDsa65 => keygen!(fips204::ml_dsa_65),
+            SignatureAlgorithm::MlDsa87 => keygen!(fips204::ml_dsa_87),
+            SignatureAlgorithm::SlhDsaSha2_128s => keygen!(fips205::slh_dsa_sha2_128s),
+            SignatureAlgorithm::SlhDsaSha2_128f => keygen!(fips205::slh_dsa_sha2_128f),
+            SignatureAlgorithm::SlhDsaShake128s => keygen!(fips205::slh_dsa_shake_128s),
+            SignatureAlgorithm::SlhDsaShake128f => keygen!(fips205::slh_dsa_shake_128f),
+        };
+        Ok(Self { algorithm, secret, public })
+    }
+
+    /// Read a key written by [`SigningKey::save`].
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let bytes = fs::read(path).with_context(|| format!("reading {}", path.display()))?;
+        serde_json::from_slice(&bytes).with_context(|| format!("malformed signing key {}", path.display()))
+    }
+
+    /// Write the key as JSON for `signatures sign`, readable by the owner
+    /// only. An existing key file is never replaced.
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        persist::write_new(path, &serde_json::to_vec_pretty(self)?, 0o600)
+    }
+
+    pub fn public_key(&self) -> PublicKey {
+        PublicKey {
+            algorithm: self.algorithm,
+            key: self.public.clone(),
+        }
+    }
+
+    pub fn sign(&self, message: &[u8]) -> anyhow::Result<Vec<u8>> {
+        let malformed = || anyhow::anyhow!("malformed {} signing key", self.algorithm);
+        Ok(match self.algorithm {
+            SignatureAlgorithm::Ed25519 => {
+                let seed: [u8; 32] = self.secret.as_slice().try_into().map_err(|_| malformed())?;
+                ed25519_dalek::SigningKey::from_bytes(&seed).sign(message).to_bytes().to_vec()
+            }
+            SignatureAlgorithm::MlDsa44 => ml_dsa_sign!(ml_dsa_44, &self.secret, message, malformed),
+            SignatureAlgorithm::MlDsa65 => ml_dsa_sign!(ml_dsa_65, &self.secret, message, malformed),
+            SignatureAlgorithm::MlDsa87 => ml_dsa_sign!(ml_dsa_87, &self.secret, message, malformed),
+            SignatureAlgorithm::SlhDsaSha2_128s => slh_dsa_sign!(slh_dsa_sha2_128s, &self.secret, message, malformed),
+            SignatureAlgorithm::SlhDsaSha2_128f => slh_dsa_sign!(slh_dsa_sha2_128f, &self.secret, message, malformed),
+            SignatureAlgorithm::SlhDsaShake128s => slh_dsa_sign!(slh_dsa_shake_128s, &self.secret, message, malformed),
+            SignatureAlgorithm::SlhDsaShake128f => slh_dsa_sign!(slh_dsa_shake_128f, &self.secret, message, malformed),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sign_and_verify_each_family() {
+        for alg in [
+            SignatureAlgorithm::Ed25519,
+            SignatureAlgorithm::MlDsa65,
+            SignatureAlgorithm::SlhDsaSha2_128f,
+        ] {
+            let key = SigningKey::generate(alg).unwrap();
+            let sig = key.sign(b"bundle").unwrap();
+            let public = key.public_key();
+            assert_eq!(public.verify(b"bundle", &sig), Ok(()), "{alg}");
+            assert_eq!(public.verify(b"bundl3", &sig), Err(SignatureError::Invalid(alg)), "{alg}");
+            assert_eq!(
+                public.verify(b"bundle", &sig[1..]),
+                Err(SignatureError::MalformedSignature(alg)),
+                "{alg}"
+            );
+        }
+    }
+
+    #[test]
+    fn algorithm_names_round_trip() {
+        for alg in SignatureAlgorithm::ALL {
+            assert_eq!(alg.name().parse::<SignatureAlgorithm>().unwrap(), alg);
+            assert_eq!(serde_json::to_string(&alg).unwrap(), format!("\"{alg}\""));
+        }
+        assert!("rsa-2048".parse::<SignatureAlgorithm>().is_err());
+    }
+}
@@ -0,0 +1,178 @@
+//! Delta rule updates.
+//!
+//! A delta carries the rules added, modified and removed between two bundle
+//! versions and travels in the same signed envelope as a full bundle, under
+//! its own payload type. It only applies to the exact base version it was
+//! built from, and the result must reproduce the target's content checksum;
+//! on any mismatch the updater falls back to downloading the full bundle.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use chrono::{DateTime, Utc};
+use semver::Version;
+use serde::{Deserialize, Serialize};
+
+use av_core::signatures::{RuleBundle, RuleMetadata};
+
+/// Payload type of a JSON-encoded [`RuleDelta`].
+pub const RULE_DELTA_TYPE: &str = "application/vnd.charmedwoa.rule-delta+json";
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RuleDelta {
+    pub base_version: Version,
+    pub version: Version,
+    #[serde(default)]
+    pub added: BTreeMap<String, RuleMetadata>,
+    #[serde(default)]
+    pub modified: BTreeMap<String, RuleMetadata>,
+    #[serde(default)]
+    pub removed: BTreeSet<String>,
+    /// [`RuleBundle::content_checksum`] of the resulting bundle.
+    pub checksum: String,
+    pub issued_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl RuleDelta {
+    /// Delta turning `base` into `target`.
+    pub fn between(base: &RuleBundle, target: &RuleBundle) -> Self {
+        let mut delta = Self {
+            base_version: base.version.clone(),
+            version: target.version.clone(),
+            added: BTreeMap::new(),
+            modified: BTreeMap::new(),
+            removed: BTreeSet::new(),
+            checksum: target.content_checksum(),
+            issued_at: target.issued_at,
+            expires_at: target.expires_at,
+        };
+        for (id, rule) in &target.rules {
+            match base.rules.get(id) {
+                None => {
+                    delta.added.insert(id.clone(), rule.clone());
+                }
+                Some(old) if old != rule => {
+                    delta.modified.insert(id.clone(), rule.clone());
+                }
+                Some(_) => {}
+            }
+        }
+        delta.removed = base
+            .rules
+            .keys()
+            .filter(|id| !target.rules.contains_key(*id))
+            .cloned()
+            .collect();
+        delta
+    }
+
+    pub fn apply(&self, base: &RuleBundle) -> anyhow::Result<RuleBundle> {
+        anyhow::ensure!(
+            base.version == self.base_version,
+            "delta is for base {}, have {}",
+            self.base_version,
+            base.version
+        );
+        anyhow::ensure!(
+            self.version > self.base_version,
+            "delta does not move forward from {}",
+            self.base_version
+        );
+        let mut rules = base.rules.clone();
+        for id in &self.removed {
+            anyhow::ensure!(rules.remove(id).is_some(), "delta removes unknown rule `{id}`");
+        }
+        for (id, rule) in &self.modified {
+            let slot = rules
+                .get_mut(id)
+                .ok_or_else(|| anyhow::anyhow!("delta modifies unknown rule `{id}`"))?;
+            *slot = rule.clone();
+        }
+        for (id, rule) in &self.added {
+            anyhow::ensure!(
+                rules.insert(id.clone(), rule.clone()).is_none(),
+                "delta adds existing rule `{id}`"
+            );
+        }
+        let bundle = RuleBundle {
+            version: self.version.clone(),
+            rules,
+            checksum: self.checksum.clone(),
+            issued_at: self.issued_at,
+            expires_at: self.expires_at,
+        };
+        anyhow::ensure!(
+            bundle.content_checksum() == self.checksum,
+            "bundle rebuilt from delta does not match checksum {}",
+            self.checksum
+        );
+        Ok(bundle)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    pub(crate) fn rule(id: &str, description: &str) -> (String, RuleMetadata) {
+        (
+            id.to_owned(),
+            RuleMetadata {
+                id: id.to_owned(),
+                description: description.to_owned(),
+                provenance: "test".into(),
+                ab_bucket: None,
+                created_at: "2026-10-01T00:00:00Z".parse().unwrap(),
+                expires_at: None,
+                tags: vec![],
+                state: Default::default(),
+            },
+        )
+    }
+
+    pub(crate) fn bundle(version: &str, rules: &[(&str, &str)]) -> RuleBundle {
+        let mut bundle = RuleBundle {
+            version: version.parse().unwrap(),
+            rules: rules.iter().map(|(id, desc)| rule(id, desc)).collect(),
+            checksum: String::new(),
+            issued_at: "2026-10-01T00:00:00Z".parse().unwrap(),
+            expires_at: "2027-01-01T00:00:00Z".parse().unwrap(),
+        };
+        bundle.checksum = bundle.content_checksum();
+        bundle
+    }
+
+    #[test]
+    fn round_trips_between_versions() {
+        let base = bundle("1.0.0", &[("a", "alpha"), ("b", "beta"), ("c", "gamma")]);
+        let target = bundle("1.1.0", &[("a", "alpha"), ("b", "beta v2"), ("d", "delta")]);
+        let delta = RuleDelta::between(&base, &target);
+        assert_eq!(delta.added.keys().collect::<Vec<_>>(), ["d"]);
+        assert_eq!(delta.modified.keys().collect::<Vec<_>>(), ["b"]);
+        assert_eq!(delta.removed.iter().collect::<Vec<_>>(), ["c"]);
+
+        let applied = delta.apply(&base).unwrap();
+        assert_eq!(applied.version, target.version);
+        assert_eq!(applied.rules, target.rules);
+        assert_eq!(applied.checksum, target.checksum);
+    }
+
+    #[test]
+    fn refuses_wrong_base_or_checksum() {
+        let base = bundle("1.0.0", &[("a", "alpha")]);
+        let target = bundle("1.1.0", &[("a", "alpha"), ("b", "beta")]);
+        let delta = RuleDelta::between(&base, &target);
+
+        let other_base = bundle("0.9.0", &[("a", "alpha")]);
+        assert!(delta.apply(&other_base).is_err());
+
+        // Same version label, locally different content.
+        let drifted = bundle("1.0.0", &[("a", "alpha, edited")]);
+        let err = delta.apply(&drifted).unwrap_err();
+        assert!(err.to_string().contains("does not match checksum"), "{err:#}");
+
+        let mut backwards = delta.clone();
+        backwards.version = "0.1.0".parse().unwrap();
+        assert!(backwards.apply(&base).is_err());
+    }
+}
@@ -0,0 +1,96 @@
+//! Detached-payload signing envelope.
+//!
+//! Modelled on DSSE: the bundle travels as opaque base64 bytes and every
+//! signature covers the pre-authentication encoding (PAE) of those exact
+//! bytes plus their type. Nothing is re-serialized before verification, so
+//! map ordering or whitespace in the producer's JSON cannot break a valid
+//! signature, and the payload is only parsed after it has been authenticated.
+
+use serde::{Deserialize, Serialize};
+
+use crate::crypto::{PublicKey, SigningKey};
+use crate::policy::{self, BundleSignature, SignaturePolicy};
+
+/// Payload type of a JSON-encoded [`av_core::signatures::RuleBundle`].
+pub const RULE_BUNDLE_TYPE: &str = "application/vnd.charmedwoa.rule-bundle+json";
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Envelope {
+    pub payload_type: String,
+    #[serde(with = "av_core::base64_bytes")]
+    pub payload: Vec<u8>,
+    pub signatures: Vec<BundleSignature>,
+}
+
+impl Envelope {
+    pub fn sign(payload_type: &str, payload: Vec<u8>, signers: &[&SigningKey]) -> anyhow::Result<Self> {
+        let message = pae(payload_type, &payload);
+        let signatures = signers
+            .iter()
+            .map(|key| {
+                Ok(BundleSignature {
+                    key_id: key.public_key().key_id(),
+                    algorithm: key.algorithm,
+                    signature: key.sign(&message)?,
+                })
+            })
+            .collect::<anyhow::Result<_>>()?;
+        Ok(Self {
+            payload_type: payload_type.to_owned(),
+            payload,
+            signatures,
+        })
+    }
+
+    /// Enforce `policy` and return the authenticated payload.
+    pub fn verify(&self, payload_type: &str, policy: SignaturePolicy, keys: &[PublicKey]) -> anyhow::Result<&[u8]> {
+        anyhow::ensure!(
+            self.payload_type == payload_type,
+            "unexpected payload type `{}`, want `{payload_type}`",
+            self.payload_type
+        );
+        policy::enforce(policy, keys, &pae(&self.payload_type, &self.payload), &self.signatures)?;
+        Ok(&self.payload)
+    }
+}
+
+/// `"DSSEv1" SP LEN(type) SP type SP LEN(payload) SP payload`, lengths in
+/// ASCII decimal.
+pub fn pae(payload_type: &str, payload: &[u8]) -> Vec<u8> {
+    let mut out = format!("DSSEv1 {} {payload_type} {} ", payload_type.len(), payload.len()).into_bytes();
+    out.extend_from_slice(payload);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::SignatureAlgorithm;
+
+    #[test]
+    fn pae_matches_dsse_spec_example() {
+        assert_eq!(
+            pae("http://example.com/HelloWorld", b"hello world"),
+            b"DSSEv1 29 http://example.com/HelloWorld 11 hello world".to_vec()
+        );
+    }
+
+    #[test]
+    fn signature_binds_payload_type_and_bytes() {
+        let key = SigningKey::generate(SignatureAlgorithm::Ed25519).unwrap();
+        let keys = [key.public_key()];
+        let envelope = Envelope::sign("text/plain", b"{\"a\": 1}".to_vec(), &[&key]).unwrap();
+        let policy = SignaturePolicy::RequireAny;
+        assert_eq!(envelope.verify("text/plain", policy, &keys).unwrap(), b"{\"a\": 1}");
+
+        // Same JSON value, different bytes.
+        let mut reformatted = envelope.clone();
+        reformatted.payload = b"{\"a\":1}".to_vec();
+        assert!(reformatted.verify("text/plain", policy, &keys).is_err());
+
+        let mut retyped = envelope.clone();
+        retyped.payload_type = "text/html".into();
+        assert!(retyped.verify("text/html", policy, &keys).is_err());
+        assert!(envelope.verify("text/html", policy, &keys).is_err());
+    }
+}
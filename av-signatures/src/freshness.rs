@@ -0,0 +1,232 @@
+//! Rollback and freeze-attack protection.
+//!
+//! A validly signed bundle is not necessarily a current one: an attacker
+//! on the update path can replay an older release (rollback) or keep
+//! serving the last one forever (freeze). The highest accepted version and
+//! its signed release window are persisted per source; anything older is
+//! refused, and a recorded window that has run out is surfaced as a health
+//! status even when no update attempt is made.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use chrono::{DateTime, Duration, Utc};
+use semver::Version;
+use serde::{Deserialize, Serialize};
+
+use av_core::signatures::RuleBundle;
+
+/// Tolerated clock skew between publisher and client.
+const MAX_CLOCK_SKEW: Duration = Duration::minutes(5);
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum FreshnessError {
+    #[error("rollback refused: offered version {offered} is older than accepted {accepted}")]
+    Rollback { accepted: Version, offered: Version },
+    #[error("replay refused: version {version} issued {offered}, already accepted one issued {accepted}")]
+    Replay {
+        version: Version,
+        accepted: DateTime<Utc>,
+        offered: DateTime<Utc>,
+    },
+    #[error("update metadata expired at {0}")]
+    Expired(DateTime<Utc>),
+    #[error("bundle issued in the future ({0})")]
+    NotYetValid(DateTime<Utc>),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SourceState {
+    pub version: Version,
+    pub issued_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    pub accepted_at: DateTime<Utc>,
+}
+
+/// Persisted per-source high-water marks, keyed by source name.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UpdateState {
+    pub sources: BTreeMap<String, SourceState>,
+}
+
+impl UpdateState {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        match fs::read(path) {
+            Ok(bytes) => Ok(serde_json::from_slice(&bytes)?),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        av_core::persist::write_atomic(path, &serde_json::to_vec_pretty(self)?)
+    }
+
+    /// Whether `bundle` may replace what was last accepted from `source`.
+    ///
+    /// The same version is accepted again only with an equal or newer
+    /// `issued_at`, which is how a publisher extends the window of an
+    /// unchanged rule set.
+    pub fn check(&self, source: &str, bundle: &RuleBundle, now: DateTime<Utc>) -> Result<(), FreshnessError> {
+        if bundle.issued_at > now + MAX_CLOCK_SKEW {
+            return Err(FreshnessError::NotYetValid(bundle.issued_at));
+        }
+        if bundle.expires_at <= now {
+            return Err(FreshnessError::Expired(bundle.expires_at));
+        }
+        let Some(accepted) = self.sources.get(source) else {
+            return Ok(());
+        };
+        if bundle.version < accepted.version {
+            return Err(FreshnessError::Rollback {
+                accepted: accepted.version.clone(),
+                offered: bundle.version.clone(),
+            });
+        }
+        if bundle.version == accepted.version && bundle.issued_at < accepted.issued_at {
+            return Err(FreshnessError::Replay {
+                version: bundle.version.clone(),
+                accepted: accepted.issued_at,
+                offered: bundle.issued_at,
+            });
+        }
+        Ok(())
+    }
+
+    pub fn record(&mut self, source: &str, bundle: &RuleBundle, now: DateTime<Utc>) {
+        self.sources.insert(
+            source.to_owned(),
+            SourceState {
+                version: bundle.version.clone(),
+                issued_at: bundle.issued_at,
+                expires_at: bundle.expires_at,
+                accepted_at: now,
+            },
+        );
+    }
+
+    /// Health of the given sources as of `now`.
+    pub fn health<'a>(&self, sources: impl IntoIterator<Item = &'a str>, now: DateTime<Utc>) -> UpdateHealth {
+        let mut expired = Vec::new();
+        let mut never_updated = Vec::new();
+        for name in sources {
+            match self.sources.get(name) {
+                Some(state) if state.expires_at <= now => expired.push(name.to_owned()),
+                Some(_) => {}
+                None => never_updated.push(name.to_owned()),
+            }
+        }
+        if !expired.is_empty() {
+            UpdateHealth::MetadataExpired(expired)
+        } else if !never_updated.is_empty() {
+            UpdateHealth::NeverUpdated(never_updated)
+        } else {
+            UpdateHealth::Current
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(tag = "status", content = "sources", rename_all = "kebab-case")]
+pub enum UpdateHealth {
+    Current,
+    /// No bundle accepted yet from these sources.
+    NeverUpdated(Vec<String>),
+    /// The last accepted bundle from these sources is past its `expires_at`.
+    MetadataExpired(Vec<String>),
+}
+
+impl fmt::Display for UpdateHealth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Current => f.write_str("signatures current"),
+            Self::NeverUpdated(sources) => write!(f, "signatures never updated ({})", sources.join(", ")),
+            Self::MetadataExpired(sources) => write!(f, "update metadata expired ({})", sources.join(", ")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(s: &str) -> DateTime<Utc> {
+        s.parse().unwrap()
+    }
+
+    fn bundle(version: &str, issued: &str, expires: &str) -> RuleBundle {
+        RuleBundle {
+            version: version.parse().unwrap(),
+            rules: Default::default(),
+            checksum: String::new(),
+            issued_at: at(issued),
+            expires_at: at(expires),
+        }
+    }
+
+    #[test]
+    fn refuses_rollback_and_replay() {
+        let now = at("2026-10-18T12:00:00Z");
+        let mut state = UpdateState::default();
+        let current = bundle("1.4.0", "2026-10-17T00:00:00Z", "2026-10-24T00:00:00Z");
+        state.check("default", &current, now).unwrap();
+        state.record("default", &current, now);
+
+        let older = bundle("1.3.9", "2026-10-18T00:00:00Z", "2026-10-25T00:00:00Z");
+        assert!(matches!(
+            state.check("default", &older, now),
+            Err(FreshnessError::Rollback { .. })
+        ));
+        let replayed = bundle("1.4.0", "2026-10-10T00:00:00Z", "2026-10-24T00:00:00Z");
+        assert!(matches!(
+            state.check("default", &replayed, now),
+            Err(FreshnessError::Replay { .. })
+        ));
+
+        // Re-signing the same version and moving forward are both fine, and
+        // history for one source does not constrain another.
+        let resigned = bundle("1.4.0", "2026-10-18T00:00:00Z", "2026-10-25T00:00:00Z");
+        assert_eq!(state.check("default", &resigned, now), Ok(()));
+        assert_eq!(state.check("default", &current, now), Ok(()));
+        assert_eq!(state.check("mirror", &older, now), Ok(()));
+    }
+
+    #[test]
+    fn rejects_expired_and_future_bundles() {
+        let now = at("2026-10-18T12:00:00Z");
+        let state = UpdateState::default();
+        let expired = bundle("2.0.0", "2026-10-01T00:00:00Z", "2026-10-18T11:00:00Z");
+        assert_eq!(
+            state.check("default", &expired, now),
+            Err(FreshnessError::Expired(expired.expires_at))
+        );
+        let future = bundle("2.0.0", "2026-10-18T13:00:00Z", "2026-10-25T00:00:00Z");
+        assert!(matches!(
+            state.check("default", &future, now),
+            Err(FreshnessError::NotYetValid(_))
+        ));
+    }
+
+    #[test]
+    fn persisted_state_reports_expiry() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.json");
+        let accepted = at("2026-10-18T12:00:00Z");
+
+        let mut state = UpdateState::load(&path).unwrap();
+        assert_eq!(state.health(["default"], accepted), UpdateHealth::NeverUpdated(vec!["default".into()]));
+        state.record(
+            "default",
+            &bundle("1.0.0", "2026-10-18T00:00:00Z", "2026-10-25T00:00:00Z"),
+            accepted,
+        );
+        state.save(&path).unwrap();
+
+        let state = UpdateState::load(&path).unwrap();
+        assert_eq!(state.health(["default"], accepted), UpdateHealth::Current);
+        let later = state.health(["default"], at("2026-10-26T00:00:00Z"));
+        assert_eq!(later.to_string(), "update metadata expired (default)");
+    }
+}
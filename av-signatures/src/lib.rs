@@ -1,26 +1,166 @@
 //! Signed rule bundle management and update verification.
 
+pub mod build;
+pub mod crypto;
+pub mod delta;
+pub mod envelope;
+pub mod freshness;
+pub mod merge;
+pub mod mirrors;
+pub mod pinning;
+pub mod policy;
+pub mod store;
+pub mod tuf;
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs;
 use std::path::{Path, PathBuf};
 
 use anyhow::Context;
-use ed25519_dalek::{Signature, VerifyingKey};
-use reqwest::Client;
+use chrono::{DateTime, Utc};
+use reqwest::header::{ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED};
+use reqwest::{Response, StatusCode};
+use rustls::RootCertStore;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
 
+use av_core::persist::write_atomic;
 use av_core::signatures::RuleBundle;
 
+use crate::crypto::PublicKey;
+use crate::delta::{RuleDelta, RULE_DELTA_TYPE};
+use crate::envelope::{Envelope, RULE_BUNDLE_TYPE};
+use crate::freshness::{UpdateHealth, UpdateState};
+use crate::mirrors::{Endpoint, Mirror, Proxy};
+use crate::pinning::SpkiPin;
+use crate::policy::SignaturePolicy;
+use crate::store::{BundleStore, Installed};
+use crate::tuf::{TrustedMetadata, RULE_BUNDLE_TARGET};
+
+/// Upper bound on root versions followed in one update.
+const MAX_ROOT_ROTATIONS: u64 = 32;
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct UpdateSource {
     pub name: String,
     pub url: url::Url,
-    pub public_key: String,
-    pub pin_sha256: String,
+    /// Keys trusted for this source. Listing keys for several algorithms
+    /// lets the source move between them without a client release.
+    #[serde(default)]
+    pub keys: Vec<PublicKey>,
+    /// How many of `keys` must have signed a bundle.
+    #[serde(default)]
+    pub policy: SignaturePolicy,
+    /// Pinned root metadata of a TUF-style repository at `url`, which must
+    /// then end in `/`. Replaces `keys` and `policy`: signing keys come from
+    /// the root and rotate with it.
+    #[serde(default)]
+    pub root: Option<PathBuf>,
+    /// SPKI pins for the endpoint's leaf or intermediate certificates, one
+    /// or a list including backups. Empty disables pinning.
+    #[serde(default, deserialize_with = "pinning::one_or_many")]
+    pub pin_sha256: Vec<SpkiPin>,
+    /// Directory of signed deltas named `<base version>.json`, tried before
+    /// `url` when a base bundle is cached. Not used for TUF-style sources.
+    #[serde(default)]
+    pub delta_url: Option<url::Url>,
+    /// Namespace the source's rules are merged under; defaults to `name`.
+    /// Sources sharing a namespace compete for the same rule ids.
+    #[serde(default)]
+    pub namespace: Option<String>,
+    /// Higher wins a rule id contested within a namespace.
+    #[serde(default)]
+    pub priority: i32,
+    /// Tried in order when `url` fails or serves a bundle that is rejected.
+    #[serde(default)]
+    pub mirrors: Vec<Mirror>,
+    /// Proxy all of this source's requests go through.
+    #[serde(default)]
+    pub proxy: Option<Proxy>,
+}
+
+impl UpdateSource {
+    pub fn namespace(&self) -> &str {
+        self.namespace.as_deref().unwrap_or(&self.name)
+    }
 }
 
+/// Update settings as shipped in `/etc/charmedwoa-av/signatures.toml`;
+/// missing keys take the [`Default`] values.
 #[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
 pub struct UpdateConfig {
     pub sources: Vec<UpdateSource>,
     pub cache_dir: PathBuf,
+    /// Previous generations kept for rollback besides the active one.
+    pub generations: usize,
+}
+
+impl UpdateConfig {
+    /// Highest accepted version and release window per source.
+    pub fn state_path(&self) -> PathBuf {
+        self.cache_dir.join("state.json")
+    }
+
+    pub fn store(&self) -> BundleStore {
+        BundleStore::new(&self.cache_dir, self.generations)
+    }
+
+    /// Currently trusted role metadata of a TUF-style source.
+    pub fn trusted_metadata_path(&self, source: &str) -> PathBuf {
+        self.cache_dir.join("tuf").join(format!("{source}.json"))
+    }
+
+    /// HTTP validators of the last full bundle fetched per source.
+    pub fn http_cache_path(&self) -> PathBuf {
+        self.cache_dir.join("http-cache.json")
+    }
+}
+
+/// `ETag` and `Last-Modified` of a downloaded bundle, sent back on the next
+/// request so an unchanged bundle is answered with 304 Not Modified.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Validators {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
+
+impl Validators {
+    fn from_response(response: &Response) -> Option<Self> {
+        let header = |name| response.headers().get(name).and_then(|v| v.to_str().ok()).map(str::to_owned);
+        let validators = Self {
+            etag: header(ETAG),
+            last_modified: header(LAST_MODIFIED),
+        };
+        (validators != Self::default()).then_some(validators)
+    }
+}
+
+/// Sources that failed during [`Updater::update`]. The others were still
+/// merged and installed as `installed`; a failed source kept its cached
+/// bundle, if it had one.
+#[derive(Debug, thiserror::Error)]
+#[error("{}", describe_failures(.failures))]
+pub struct SourcesFailed {
+    pub installed: Installed,
+    /// Source name and the error that ended its update.
+    pub failures: Vec<(String, anyhow::Error)>,
+}
+
+fn describe_failures(failures: &[(String, anyhow::Error)]) -> String {
+    let failures: Vec<String> = failures.iter().map(|(name, err)| format!("source `{name}`: {err:#}")).collect();
+    failures.join("; ")
+}
+
+/// Result of fetching one source.
+enum Fetched {
+    /// 304 Not Modified; the cached bundle is still current.
+    NotModified,
+    /// A verified bundle, with the validators its endpoint answered with
+    /// and, for a TUF source, the metadata that vouched for it. That
+    /// metadata is persisted only once the bundle has been accepted.
+    Bundle(RuleBundle, Option<Validators>, Option<Box<TrustedMetadata>>),
 }
 
 impl Default for UpdateConfig {
@@ -28,48 +168,949 @@ impl Default for UpdateConfig {
         Self {
             sources: vec![],
             cache_dir: PathBuf::from("/var/lib/av/signatures"),
+            generations: 3,
         }
     }
 }
 
 pub struct Updater {
-    http: Client,
+    /// URL and mirrors per source name, in the order they are tried.
+    endpoints: HashMap<String, Vec<Endpoint>>,
     cfg: UpdateConfig,
 }
 
 impl Updater {
     pub fn new(cfg: UpdateConfig) -> anyhow::Result<Self> {
-        let http = Client::builder().use_rustls_tls().build()?;
-        Ok(Self { http, cfg })
+        Self::with_roots(cfg, pinning::webpki_roots())
     }
 
-    pub async fn update(&self) -> anyhow::Result<Vec<RuleBundle>> {
+    /// Validate TLS against `roots` instead of the bundled set.
+    pub fn with_roots(cfg: UpdateConfig, roots: RootCertStore) -> anyhow::Result<Self> {
+        let mut endpoints = HashMap::new();
+        for source in &cfg.sources {
+            let source_endpoints =
+                Endpoint::for_source(source, &roots).with_context(|| format!("update source `{}`", source.name))?;
+            endpoints.insert(source.name.clone(), source_endpoints);
+        }
+        Ok(Self { endpoints, cfg })
+    }
+
+    /// Fetch and verify every source, then install the result as a new
+    /// cache generation. Full bundles are requested conditionally; when no
+    /// source has published anything new, nothing is written and
+    /// [`Installed::Unchanged`] is returned.
+    ///
+    /// A source that cannot be fetched or verified keeps its cached bundle
+    /// and does not hold back the others; it is reported afterwards as
+    /// [`SourcesFailed`].
+    pub async fn update(&self) -> anyhow::Result<Installed> {
+        anyhow::ensure!(!self.cfg.sources.is_empty(), "no update sources configured");
+        let mut state = UpdateState::load(&self.cfg.state_path())?;
+        let http_cache_path = self.cfg.http_cache_path();
+        let mut http_cache: BTreeMap<String, Validators> = match fs::read(&http_cache_path) {
+            Ok(bytes) => serde_json::from_slice(&bytes).unwrap_or_else(|err| {
+                warn!(error = %err, "discarding unreadable HTTP cache validators");
+                BTreeMap::new()
+            }),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(err) => return Err(err.into()),
+        };
+        let store = self.cfg.store();
+        let current = store.current()?;
+        let mut changed = false;
         let mut bundles = Vec::new();
+        let mut served_by = BTreeMap::new();
+        let mut trusted_metadata = Vec::new();
+        let mut failures = Vec::new();
         for source in &self.cfg.sources {
-            let bundle = self.fetch_bundle(source).await?;
-            bundles.push(bundle);
+            let cached = store.cached(&source.name)?;
+            let previously = current.as_ref().and_then(|g| g.served_by.get(&source.name)).cloned();
+            let now = Utc::now();
+            let fetched = self
+                .fetch_bundle(source, &http_cache, cached.is_some(), now, |bundle| {
+                    // Also for the release already cached: replaying it must
+                    // not keep an expired bundle looking current.
+                    state
+                        .check(&source.name, bundle, now)
+                        .with_context(|| format!("bundle from source `{}`", source.name))
+                })
+                .await;
+            let (fetched, url) = match fetched {
+                Ok(fetched) => fetched,
+                Err(err) => {
+                    warn!(source = %source.name, error = %format!("{err:#}"), "update source failed");
+                    if let Some(cached) = cached {
+                        served_by.extend(previously.map(|url| (source.name.clone(), url)));
+                        bundles.push((source.name.as_str(), cached));
+                    }
+                    failures.push((source.name.clone(), err));
+                    continue;
+                }
+            };
+            let (bundle, validators) = match fetched {
+                Fetched::NotModified => {
+                    info!(source = %source.name, %url, "bundle not modified");
+                    served_by.extend(previously.map(|url| (source.name.clone(), url)));
+                    bundles.push((source.name.as_str(), cached.context("304 without a cached bundle")?));
+                    continue;
+                }
+                Fetched::Bundle(bundle, validators, trusted) => {
+                    trusted_metadata.extend(trusted.map(|trusted| (source.name.as_str(), trusted)));
+                    (bundle, validators)
+                }
+            };
+            match validators {
+                Some(validators) => http_cache.insert(url.to_string(), validators),
+                None => http_cache.remove(url.as_str()),
+            };
+            if let Some(cached) = cached.filter(|cached| same_release(cached, &bundle)) {
+                served_by.extend(previously.map(|url| (source.name.clone(), url)));
+                bundles.push((source.name.as_str(), cached));
+                continue;
+            }
+            info!(source = %source.name, version = %bundle.version, %url, "fetched new bundle");
+            state.record(&source.name, &bundle, now);
+            served_by.insert(source.name.clone(), url.to_string());
+            bundles.push((source.name.as_str(), bundle));
+            changed = true;
+        }
+        // A source added to or removed from the configuration, or failing
+        // with nothing cached, changes the rule set even when every bundle
+        // in it is unchanged.
+        let included: BTreeSet<&str> = bundles.iter().map(|(name, _)| *name).collect();
+        changed |= !current
+            .as_ref()
+            .is_some_and(|g| g.bundles.keys().map(String::as_str).eq(included.iter().copied()));
+        let installed = if changed && !bundles.is_empty() {
+            self.install(state, bundles, served_by)?
+        } else {
+            Installed::Unchanged
+        };
+        // Only now that the bundles behind them are installed: a validator
+        // saved for a bundle that failed to install would turn every later
+        // poll into a 304 for it, and TUF metadata saved for a bundle that
+        // was rejected would make the repository look newer than what is
+        // actually installed.
+        for (name, trusted) in trusted_metadata {
+            write_atomic(&self.cfg.trusted_metadata_path(name), &serde_json::to_vec_pretty(&trusted)?)?;
+        }
+        let endpoints: BTreeSet<String> = self.endpoints.values().flatten().map(|e| e.url.to_string()).collect();
+        http_cache.retain(|url, _| endpoints.contains(url));
+        write_atomic(&http_cache_path, &serde_json::to_vec_pretty(&http_cache)?)?;
+        if !failures.is_empty() {
+            return Err(SourcesFailed { installed, failures }.into());
+        }
+        Ok(installed)
+    }
+
+    /// Install a signed bundle carried over by hand, e.g. onto an air-gapped
+    /// machine. It goes through the same signature and rollback checks as a
+    /// download; the other sources keep their cached bundles.
+    pub fn import(&self, source: &str, body: &[u8]) -> anyhow::Result<Installed> {
+        let source = self.source(source)?;
+        ensure_offline(source)?;
+        let imported = verify_signed(source, body).with_context(|| format!("bundle for source `{}`", source.name))?;
+        let mut state = UpdateState::load(&self.cfg.state_path())?;
+        let now = Utc::now();
+        state
+            .check(&source.name, &imported, now)
+            .with_context(|| format!("bundle for source `{}`", source.name))?;
+        let store = self.cfg.store();
+        if store.cached(&source.name)?.is_some_and(|cached| same_release(&cached, &imported)) {
+            return Ok(Installed::Unchanged);
+        }
+        state.record(&source.name, &imported, now);
+        let mut bundles = Vec::new();
+        for other in &self.cfg.sources {
+            if other.name == source.name {
+                bundles.push((other.name.as_str(), imported.clone()));
+            } else if let Some(cached) = store.cached(&other.name)? {
+                bundles.push((other.name.as_str(), cached));
+            }
+        }
+        let mut served_by = store.current()?.map(|g| g.served_by).unwrap_or_default();
+        served_by.remove(&source.name);
+        self.install(state, bundles, served_by)
+    }
+
+    /// Download a source's full signed bundle for [`Updater::import`]
+    /// elsewhere. The bytes are returned exactly as signed, after the
+    /// checks an update would apply; nothing is installed.
+    pub async fn export(&self, source: &str) -> anyhow::Result<bytes::Bytes> {
+        let source = self.source(source)?;
+        ensure_offline(source)?;
+        let state = UpdateState::load(&self.cfg.state_path())?;
+        let endpoints = &self.endpoints[&source.name];
+        let mut failures = Vec::new();
+        for endpoint in endpoints {
+            let export = async {
+                let body = self.get(endpoint, endpoint.url.clone(), None).await?.error_for_status()?.bytes().await?;
+                let bundle =
+                    verify_signed(source, &body).with_context(|| format!("bundle from source `{}`", source.name))?;
+                state
+                    .check(&source.name, &bundle, Utc::now())
+                    .with_context(|| format!("bundle from source `{}`", source.name))?;
+                anyhow::Ok(body)
+            };
+            match export.await {
+                Ok(body) => return Ok(body),
+                Err(err) => endpoint_failed(source, endpoint, err, &mut failures),
+            }
         }
-        Ok(bundles)
+        Err(exhausted(source, failures))
     }
 
-    async fn fetch_bundle(&self, source: &UpdateSource) -> anyhow::Result<RuleBundle> {
-        let response = self.http.get(source.url.clone()).send().await?;
+    /// Install `bundles` as a new generation, then persist `state`, which
+    /// must already record the newly accepted ones. Bundles carried over
+    /// from the cache are not recorded again, so a rolled-back generation
+    /// never lowers a high-water mark.
+    fn install(
+        &self,
+        state: UpdateState,
+        bundles: Vec<(&str, RuleBundle)>,
+        served_by: BTreeMap<String, String>,
+    ) -> anyhow::Result<Installed> {
+        let mut sources = Vec::new();
+        for (name, bundle) in &bundles {
+            sources.push((self.source(name)?, bundle));
+        }
+        let merged = merge::merge(&sources)?;
+        for shadowed in &merged.shadowed {
+            warn!(rule = %shadowed.id, kept = %shadowed.kept, dropped = %shadowed.dropped, "rule id published by several sources");
+        }
+        let staged: Vec<_> = bundles.iter().map(|(name, bundle)| (*name, bundle)).collect();
+        let installed = self.cfg.store().install(&staged, &merged, served_by, Utc::now())?;
+        state.save(&self.cfg.state_path())?;
+        Ok(installed)
+    }
+
+    /// Freshness of the last accepted bundles, without contacting sources.
+    pub fn health(&self) -> anyhow::Result<UpdateHealth> {
+        let state = UpdateState::load(&self.cfg.state_path())?;
+        Ok(state.health(self.cfg.sources.iter().map(|s| s.name.as_str()), Utc::now()))
+    }
+
+    fn source(&self, name: &str) -> anyhow::Result<&UpdateSource> {
+        self.cfg
+            .sources
+            .iter()
+            .find(|s| s.name == name)
+            .with_context(|| format!("no update source named `{name}`"))
+    }
+
+    /// Fetch a source's newest bundle from the first endpoint that serves
+    /// one `accept` agrees to, and return it with that endpoint's URL. With
+    /// `conditional` set, each endpoint is sent the validators it last
+    /// answered with and may reply [`Fetched::NotModified`]. TUF metadata
+    /// expiry is judged at `now`.
+    async fn fetch_bundle(
+        &self,
+        source: &UpdateSource,
+        http_cache: &BTreeMap<String, Validators>,
+        conditional: bool,
+        now: DateTime<Utc>,
+        accept: impl Fn(&RuleBundle) -> anyhow::Result<()>,
+    ) -> anyhow::Result<(Fetched, &url::Url)> {
+        let endpoints = &self.endpoints[&source.name];
+        let mut failures = Vec::new();
+        for endpoint in endpoints {
+            let validators = http_cache.get(endpoint.url.as_str()).filter(|_| conditional);
+            let fetched = match self.fetch_from(source, endpoint, validators, now).await {
+                Ok(Fetched::Bundle(bundle, validators, trusted)) => {
+                    accept(&bundle).map(|()| Fetched::Bundle(bundle, validators, trusted))
+                }
+                other => other,
+            };
+            match fetched {
+                Ok(fetched) => return Ok((fetched, &endpoint.url)),
+                Err(err) => endpoint_failed(source, endpoint, err, &mut failures),
+            }
+        }
+        Err(exhausted(source, failures))
+    }
+
+    /// Fetch a source's newest bundle from one endpoint. The full bundle is
+    /// requested with `validators`, if given.
+    async fn fetch_from(
+        &self,
+        source: &UpdateSource,
+        endpoint: &Endpoint,
+        validators: Option<&Validators>,
+        now: DateTime<Utc>,
+    ) -> anyhow::Result<Fetched> {
+        if let Some(root) = &source.root {
+            return self
+                .fetch_tuf(source, endpoint, root, now)
+                .await
+                .map(|(bundle, trusted)| Fetched::Bundle(bundle, None, Some(Box::new(trusted))))
+                .with_context(|| format!("repository of source `{}`", source.name));
+        }
+        match self.fetch_delta(source, endpoint).await {
+            Ok(Some(bundle)) => return Ok(Fetched::Bundle(bundle, None, None)),
+            Ok(None) => {}
+            Err(err) => warn!(source = %source.name, error = %format!("{err:#}"), "delta update failed, fetching full bundle"),
+        }
+        let response = self.get(endpoint, endpoint.url.clone(), validators).await?;
+        if validators.is_some() && response.status() == StatusCode::NOT_MODIFIED {
+            return Ok(Fetched::NotModified);
+        }
+        let response = response
+            .error_for_status()
+            .with_context(|| format!("fetching {}", endpoint.url))?;
+        let validators = Validators::from_response(&response);
         let body = response.bytes().await?;
-        let signed: SignedBundle = serde_json::from_slice(&body)?;
-        let bundle = signed.bundle;
-        let sig = Signature::from_bytes(&signed.signature);
-        let key_bytes = base64::decode(&source.public_key)?;
-        let key = VerifyingKey::try_from(&key_bytes[..]).context("invalid ed25519 key")?;
-        key.verify_strict(serde_json::to_string(&bundle)?.as_bytes(), &sig)
-            .context("signature verification failed")?;
-        bundle.verify(&signed.bundle_checksum)?;
-        Ok(bundle)
-    }
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-struct SignedBundle {
-    bundle_checksum: String,
-    bundle: RuleBundle,
-    signature: [u8; 64],
+        let bundle = verify_signed(source, &body).with_context(|| format!("bundle from source `{}`", source.name))?;
+        Ok(Fetched::Bundle(bundle, validators, None))
+    }
+
+    /// Apply a signed delta to the cached bundle, if the source publishes
+    /// one for the cached version.
+    async fn fetch_delta(&self, source: &UpdateSource, endpoint: &Endpoint) -> anyhow::Result<Option<RuleBundle>> {
+        let Some(delta_url) = &endpoint.delta_url else {
+            return Ok(None);
+        };
+        let Some(base) = self.cfg.store().cached(&source.name)? else {
+            return Ok(None);
+        };
+        let url = delta_url.join(&format!("{}.json", base.version))?;
+        let response = self.get(endpoint, url.clone(), None).await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let body = response.error_for_status().with_context(|| format!("fetching {url}"))?.bytes().await?;
+        let delta: RuleDelta = verify_payload(source, &body, RULE_DELTA_TYPE)?;
+        let bundle = delta.apply(&base)?;
+        info!(source = %source.name, from = %delta.base_version, to = %delta.version, "applied delta update");
+        Ok(Some(bundle))
+    }
+
+    /// Walk the root chain, then timestamp, snapshot and targets, and fetch
+    /// the bundle they vouch for. The updated metadata is returned rather
+    /// than saved; the caller persists it once the bundle is accepted.
+    async fn fetch_tuf(
+        &self,
+        source: &UpdateSource,
+        endpoint: &Endpoint,
+        pinned_root: &Path,
+        now: DateTime<Utc>,
+    ) -> anyhow::Result<(RuleBundle, TrustedMetadata)> {
+        let state_path = self.cfg.trusted_metadata_path(&source.name);
+        let mut trusted = match fs::read(&state_path) {
+            Ok(bytes) => serde_json::from_slice(&bytes).context("corrupt trusted metadata")?,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                let pinned = fs::read(pinned_root).with_context(|| format!("reading {}", pinned_root.display()))?;
+                TrustedMetadata::from_pinned_root(&pinned)?
+            }
+            Err(err) => return Err(err.into()),
+        };
+
+        for _ in 0..MAX_ROOT_ROTATIONS {
+            let name = format!("{}.root.json", trusted.root.version + 1);
+            match self.fetch_file(endpoint, &name).await? {
+                Some(bytes) => trusted.update_root(&bytes)?,
+                None => break,
+            }
+        }
+        trusted.update_timestamp(&self.require_file(endpoint, "timestamp.json").await?, now)?;
+        trusted.update_snapshot(&self.require_file(endpoint, "snapshot.json").await?, now)?;
+        trusted.update_targets(&self.require_file(endpoint, "targets.json").await?, now)?;
+        let bundle = self.require_file(endpoint, RULE_BUNDLE_TARGET).await?;
+        trusted.verify_target(RULE_BUNDLE_TARGET, &bundle)?;
+        let bundle = serde_json::from_slice(&bundle).context("malformed rule bundle target")?;
+        Ok((bundle, trusted))
+    }
+
+    /// Fetch a file relative to the endpoint URL; `None` if it does not
+    /// exist.
+    async fn fetch_file(&self, endpoint: &Endpoint, name: &str) -> anyhow::Result<Option<bytes::Bytes>> {
+        let url = endpoint.url.join(name)?;
+        let response = self.get(endpoint, url.clone(), None).await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let response = response.error_for_status().with_context(|| format!("fetching {url}"))?;
+        Ok(Some(response.bytes().await?))
+    }
+
+    /// Send a GET, conditional on `validators` if given, turning a failed
+    /// handshake into [`pinning::PinMismatch`] when the pin check was the
+    /// cause.
+    async fn get(&self, endpoint: &Endpoint, url: url::Url, validators: Option<&Validators>) -> anyhow::Result<Response> {
+        let mut request = endpoint.http.get(url);
+        if let Some(validators) = validators {
+            if let Some(etag) = &validators.etag {
+                request = request.header(IF_NONE_MATCH, etag);
+            }
+            if let Some(last_modified) = &validators.last_modified {
+                request = request.header(IF_MODIFIED_SINCE, last_modified);
+            }
+        }
+        match request.send().await {
+            Ok(response) => Ok(response),
+            Err(err) => match endpoint.verifier.as_ref().and_then(|v| v.take_mismatch()) {
+                Some(mismatch) => Err(mismatch.into()),
+                None => Err(err.into()),
+            },
+        }
+    }
+
+    async fn require_file(&self, endpoint: &Endpoint, name: &str) -> anyhow::Result<bytes::Bytes> {
+        self.fetch_file(endpoint, name)
+            .await?
+            .with_context(|| format!("{name} missing from repository"))
+    }
+}
+
+/// Note an endpoint that failed; the next one in line is tried.
+fn endpoint_failed(source: &UpdateSource, endpoint: &Endpoint, err: anyhow::Error, failures: &mut Vec<anyhow::Error>) {
+    if !source.mirrors.is_empty() {
+        warn!(source = %source.name, url = %endpoint.url, error = %format!("{err:#}"), "update endpoint failed");
+    }
+    failures.push(err);
+}
+
+/// The error once every endpoint of `source` has failed: the only one for
+/// a source without mirrors, else the last, noting the others.
+fn exhausted(source: &UpdateSource, mut failures: Vec<anyhow::Error>) -> anyhow::Error {
+    let count = failures.len();
+    let last = failures.pop().expect("every source has its own URL as an endpoint");
+    if count == 1 {
+        return last;
+    }
+    last.context(format!("all {count} endpoints of source `{}` failed", source.name))
+}
+
+/// Whether `fetched` is the release already cached: same content and the
+/// same signed release window.
+fn same_release(cached: &RuleBundle, fetched: &RuleBundle) -> bool {
+    cached.version == fetched.version
+        && cached.checksum == fetched.checksum
+        && cached.issued_at == fetched.issued_at
+        && cached.expires_at == fetched.expires_at
+}
+
+/// Only envelope-signed bundles carry their own proof; a TUF-style target
+/// is meaningless without the role metadata that vouches for it.
+fn ensure_offline(source: &UpdateSource) -> anyhow::Result<()> {
+    anyhow::ensure!(
+        source.root.is_none(),
+        "source `{}` uses repository metadata, which cannot be verified offline",
+        source.name
+    );
+    Ok(())
+}
+
+/// Authenticate an envelope against the source's keys and policy, then
+/// parse the bundle from the exact bytes that were signed.
+pub fn verify_signed(source: &UpdateSource, body: &[u8]) -> anyhow::Result<RuleBundle> {
+    verify_payload(source, body, RULE_BUNDLE_TYPE)
+}
+
+fn verify_payload<T: DeserializeOwned>(source: &UpdateSource, body: &[u8], payload_type: &str) -> anyhow::Result<T> {
+    let envelope: Envelope = serde_json::from_slice(body).context("malformed signed envelope")?;
+    let payload = envelope.verify(payload_type, source.policy, &source.keys)?;
+    serde_json::from_slice(payload).with_context(|| format!("malformed {payload_type} payload"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::{SignatureAlgorithm, SigningKey};
+    use crate::freshness::FreshnessError;
+
+    fn source(keys: Vec<PublicKey>, policy: SignaturePolicy) -> UpdateSource {
+        UpdateSource {
+            name: "test".into(),
+            url: "https://updates.example/latest.json".parse().unwrap(),
+            keys,
+            policy,
+            root: None,
+            pin_sha256: Vec::new(),
+            delta_url: None,
+            namespace: None,
+            priority: 0,
+            mirrors: Vec::new(),
+            proxy: None,
+        }
+    }
+
+    fn envelope(signers: &[&SigningKey]) -> Vec<u8> {
+        let bundle = RuleBundle {
+            version: semver::Version::new(1, 2, 0),
+            rules: Default::default(),
+            checksum: "abc".into(),
+            issued_at: "2026-10-01T00:00:00Z".parse().unwrap(),
+            expires_at: "2036-10-01T00:00:00Z".parse().unwrap(),
+        };
+        let payload = serde_json::to_vec(&bundle).unwrap();
+        serde_json::to_vec(&Envelope::sign(RULE_BUNDLE_TYPE, payload, signers).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn hybrid_source_requires_both_signatures() {
+        let ed25519 = SigningKey::generate(SignatureAlgorithm::Ed25519).unwrap();
+        let ml_dsa = SigningKey::generate(SignatureAlgorithm::MlDsa65).unwrap();
+        let src = source(vec![ed25519.public_key(), ml_dsa.public_key()], SignaturePolicy::RequireAll);
+
+        let bundle = verify_signed(&src, &envelope(&[&ed25519, &ml_dsa])).unwrap();
+        assert_eq!(bundle.version, semver::Version::new(1, 2, 0));
+
+        let err = verify_signed(&src, &envelope(&[&ml_dsa])).unwrap_err();
+        assert!(err.to_string().contains("1 of 2 required keys verified"), "{err:#}");
+        assert!(err.to_string().contains("ed25519 key"), "{err:#}");
+    }
+
+    #[test]
+    fn rejects_signature_from_untrusted_key() {
+        let ed25519 = SigningKey::generate(SignatureAlgorithm::Ed25519).unwrap();
+        let slh_dsa = SigningKey::generate(SignatureAlgorithm::SlhDsaSha2_128f).unwrap();
+        let src = source(vec![ed25519.public_key()], SignaturePolicy::default());
+
+        assert!(verify_signed(&src, &envelope(&[&ed25519])).is_ok());
+        let err = verify_signed(&src, &envelope(&[&slh_dsa])).unwrap_err();
+        assert!(err.to_string().contains("slh-dsa-sha2-128f key"), "{err:#}");
+        assert!(err.to_string().contains("not trusted"), "{err:#}");
+    }
+
+    const FIXTURE_KEYS: &str = include_str!("../testdata/keys.json");
+    const FIXTURES: [(&str, &str); 4] = [
+        ("ed25519", include_str!("../testdata/bundle-ed25519.json")),
+        ("ml-dsa-65", include_str!("../testdata/bundle-ml-dsa-65.json")),
+        ("slh-dsa-sha2-128s", include_str!("../testdata/bundle-slh-dsa-sha2-128s.json")),
+        ("hybrid", include_str!("../testdata/bundle-hybrid.json")),
+    ];
+
+    #[test]
+    fn fixture_corpus_verifies_stably() {
+        let keys: Vec<PublicKey> = serde_json::from_str(FIXTURE_KEYS).unwrap();
+        let any = source(keys.clone(), SignaturePolicy::RequireAny);
+        let hybrid = source(keys[..2].to_vec(), SignaturePolicy::RequireAll);
+
+        // HashMap iteration order varies between runs and processes; the
+        // signed bytes do not.
+        for _ in 0..8 {
+            for (name, fixture) in FIXTURES {
+                let bundle = verify_signed(&any, fixture.as_bytes()).unwrap_or_else(|err| panic!("{name}: {err:#}"));
+                assert_eq!(bundle.version, semver::Version::new(2026, 10, 1), "{name}");
+                assert_eq!(bundle.rules.len(), 5, "{name}");
+            }
+            verify_signed(&hybrid, FIXTURES[3].1.as_bytes()).unwrap();
+        }
+        assert!(verify_signed(&hybrid, FIXTURES[0].1.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn tampered_fixture_payload_is_rejected() {
+        let keys: Vec<PublicKey> = serde_json::from_str(FIXTURE_KEYS).unwrap();
+        let src = source(keys, SignaturePolicy::RequireAny);
+        let mut envelope: Envelope = serde_json::from_str(FIXTURES[1].1).unwrap();
+        let pos = envelope.payload.windows(4).position(|w| w == b"2026").unwrap();
+        envelope.payload[pos + 3] = b'7';
+        let err = verify_signed(&src, &serde_json::to_vec(&envelope).unwrap()).unwrap_err();
+        assert!(err.to_string().contains("does not verify"), "{err:#}");
+    }
+
+    async fn fetch(updater: &Updater, source: &UpdateSource) -> anyhow::Result<RuleBundle> {
+        match updater.fetch_bundle(source, &BTreeMap::new(), false, Utc::now(), |_| Ok(())).await?.0 {
+            Fetched::Bundle(bundle, ..) => Ok(bundle),
+            Fetched::NotModified => anyhow::bail!("unconditional request answered with 304"),
+        }
+    }
+
+    #[tokio::test]
+    async fn pinned_source_reports_pin_mismatch() {
+        let pki = pinning::tests::TestPki::generate();
+        let port = pki.serve(FIXTURES[0].1.as_bytes()).await;
+        let keys: Vec<PublicKey> = serde_json::from_str(FIXTURE_KEYS).unwrap();
+        let mut src = source(keys, SignaturePolicy::RequireAny);
+        src.url = format!("https://localhost:{port}/latest.json").parse().unwrap();
+
+        let leaf = SpkiPin::of_certificate(&pki.leaf).unwrap();
+        src.pin_sha256 = vec![SpkiPin([1; 32]), leaf];
+        let cfg = UpdateConfig {
+            sources: vec![src.clone()],
+            ..Default::default()
+        };
+        let updater = Updater::with_roots(cfg, pki.roots()).unwrap();
+        assert_eq!(fetch(&updater, &src).await.unwrap().rules.len(), 5);
+
+        src.pin_sha256 = vec![SpkiPin([1; 32])];
+        let cfg = UpdateConfig {
+            sources: vec![src.clone()],
+            ..Default::default()
+        };
+        let updater = Updater::with_roots(cfg, pki.roots()).unwrap();
+        let err = fetch(&updater, &src).await.unwrap_err();
+        let mismatch = err.downcast_ref::<pinning::PinMismatch>().expect("distinct pin error");
+        assert_eq!(mismatch.presented[0], leaf);
+    }
+
+    #[test]
+    fn pin_accepts_single_value_or_list() {
+        let one: UpdateSource = serde_json::from_str(
+            r#"{"name": "a", "url": "https://u.example/", "pin_sha256": "sha256/47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU="}"#,
+        )
+        .unwrap();
+        assert_eq!(one.pin_sha256.len(), 1);
+        let many: UpdateSource = serde_json::from_str(
+            r#"{"name": "a", "url": "https://u.example/", "pin_sha256": [
+                "sha256/47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU=",
+                "sha256/AQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQE="]}"#,
+        )
+        .unwrap();
+        assert_eq!(many.pin_sha256.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn applies_delta_to_cached_base_and_falls_back() {
+        use crate::delta::tests::bundle;
+
+        let key = SigningKey::generate(SignatureAlgorithm::Ed25519).unwrap();
+        let seal = |payload_type: &str, payload: Vec<u8>| {
+            let envelope = Envelope::sign(payload_type, payload, &[&key]).unwrap();
+            serde_json::to_vec(&envelope).unwrap()
+        };
+        let base = bundle("1.0.0", &[("a", "alpha"), ("b", "beta")]);
+        let target = bundle("1.1.0", &[("a", "alpha"), ("c", "gamma")]);
+        let mut bad_delta = RuleDelta::between(&base, &target);
+        bad_delta.checksum = "sha256:00".into();
+
+        let pki = pinning::tests::TestPki::generate();
+        let port = pki
+            .serve_files(vec![
+                ("/latest.json".into(), seal(RULE_BUNDLE_TYPE, serde_json::to_vec(&target).unwrap())),
+                (
+                    "/deltas/1.0.0.json".into(),
+                    seal(RULE_DELTA_TYPE, serde_json::to_vec(&RuleDelta::between(&base, &target)).unwrap()),
+                ),
+                ("/bad/1.0.0.json".into(), seal(RULE_DELTA_TYPE, serde_json::to_vec(&bad_delta).unwrap())),
+            ])
+            .await;
+        let dir = tempfile::tempdir().unwrap();
+        let mut src = source(vec![key.public_key()], SignaturePolicy::RequireAny);
+        src.url = format!("https://localhost:{port}/latest.json").parse().unwrap();
+        src.delta_url = Some(format!("https://localhost:{port}/deltas/").parse().unwrap());
+        src.pin_sha256 = vec![SpkiPin::of_certificate(&pki.leaf).unwrap()];
+        let updater = |src: &UpdateSource| {
+            let cfg = UpdateConfig {
+                sources: vec![src.clone()],
+                cache_dir: dir.path().to_owned(),
+                ..Default::default()
+            };
+            Updater::with_roots(cfg, pki.roots()).unwrap()
+        };
+
+        // Nothing cached: full download.
+        assert_eq!(fetch(&updater(&src), &src).await.unwrap().version, target.version);
+
+        // Base cached and the full bundle unavailable: only the delta can
+        // produce the target.
+        BundleStore::new(dir.path(), 3)
+            .install(&[("test", &base)], &Default::default(), BTreeMap::new(), Utc::now()).unwrap();
+        let full_url = std::mem::replace(&mut src.url, format!("https://localhost:{port}/gone.json").parse().unwrap());
+        let via_delta = fetch(&updater(&src), &src).await.unwrap();
+        assert_eq!(via_delta.rules, target.rules);
+        assert_eq!(via_delta.checksum, target.checksum);
+
+        // A delta that does not reproduce the checksum falls back to full.
+        src.url = full_url;
+        src.delta_url = Some(format!("https://localhost:{port}/bad/").parse().unwrap());
+        assert_eq!(fetch(&updater(&src), &src).await.unwrap().rules, target.rules);
+    }
+
+    #[tokio::test]
+    async fn tuf_metadata_is_not_persisted_for_a_rejected_bundle() {
+        let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/tuf");
+        let files = ["2.root.json", "timestamp.json", "snapshot.json", "targets.json", RULE_BUNDLE_TARGET]
+            .into_iter()
+            .map(|name| (format!("/repo/{name}"), fs::read(format!("{dir}/{name}")).unwrap()))
+            .collect();
+        let pki = pinning::tests::TestPki::generate();
+        let port = pki.serve_files(files).await;
+        let mut src = source(vec![], SignaturePolicy::RequireAny);
+        src.url = format!("https://localhost:{port}/repo/").parse().unwrap();
+        src.root = Some(format!("{dir}/1.root.json").into());
+        src.pin_sha256 = vec![SpkiPin::of_certificate(&pki.leaf).unwrap()];
+        let cache = tempfile::tempdir().unwrap();
+        let cfg = UpdateConfig {
+            sources: vec![src.clone()],
+            cache_dir: cache.path().to_owned(),
+            ..Default::default()
+        };
+        let updater = Updater::with_roots(cfg.clone(), pki.roots()).unwrap();
+        let now = "2026-10-18T12:00:00Z".parse().unwrap();
+
+        let rejected = updater
+            .fetch_bundle(&src, &BTreeMap::new(), false, now, |_| anyhow::bail!("rolled back"))
+            .await;
+        assert!(rejected.is_err());
+        assert!(!cfg.trusted_metadata_path("test").exists());
+
+        let (fetched, _) = updater.fetch_bundle(&src, &BTreeMap::new(), false, now, |_| Ok(())).await.unwrap();
+        let Fetched::Bundle(bundle, None, Some(trusted)) = fetched else {
+            panic!("TUF source returned no metadata");
+        };
+        assert_eq!(bundle.version, semver::Version::new(2026, 10, 1));
+        assert_eq!(trusted.root.version, 2);
+        assert!(!cfg.trusted_metadata_path("test").exists());
+    }
+
+    #[tokio::test]
+    async fn exported_bundle_imports_offline() {
+        use crate::delta::tests::bundle;
+
+        let key = SigningKey::generate(SignatureAlgorithm::Ed25519).unwrap();
+        let seal = |bundle: &RuleBundle| {
+            let envelope = Envelope::sign(RULE_BUNDLE_TYPE, serde_json::to_vec(bundle).unwrap(), &[&key]).unwrap();
+            serde_json::to_vec(&envelope).unwrap()
+        };
+        let (old, new) = (bundle("1.0.0", &[("a", "alpha")]), bundle("1.1.0", &[("b", "beta")]));
+
+        let pki = pinning::tests::TestPki::generate();
+        let port = pki.serve_files(vec![("*".into(), seal(&new))]).await;
+        let mut src = source(vec![key.public_key()], SignaturePolicy::RequireAny);
+        src.url = format!("https://localhost:{port}/latest.json").parse().unwrap();
+        src.pin_sha256 = vec![SpkiPin::of_certificate(&pki.leaf).unwrap()];
+        let mut other = source(vec![key.public_key()], SignaturePolicy::RequireAny);
+        other.name = "other".into();
+        let mut tuf = source(vec![], SignaturePolicy::RequireAny);
+        tuf.name = "tuf".into();
+        tuf.root = Some("/nonexistent/root.json".into());
+
+        let connected = tempfile::tempdir().unwrap();
+        let cfg = UpdateConfig {
+            sources: vec![src.clone()],
+            cache_dir: connected.path().to_owned(),
+            ..Default::default()
+        };
+        let exported = Updater::with_roots(cfg, pki.roots()).unwrap().export("test").await.unwrap();
+        assert_eq!(exported.as_ref(), seal(&new).as_slice());
+
+        let air_gapped = tempfile::tempdir().unwrap();
+        let cfg = UpdateConfig {
+            sources: vec![src, other, tuf],
+            cache_dir: air_gapped.path().to_owned(),
+            ..Default::default()
+        };
+        let updater = Updater::new(cfg.clone()).unwrap();
+        updater.import("other", &seal(&old)).unwrap();
+        assert!(matches!(updater.import("test", &exported).unwrap(), Installed::Activated(_)));
+        let store = cfg.store();
+        assert_eq!(store.cached("test").unwrap().unwrap().version, new.version);
+        assert_eq!(store.cached("other").unwrap().unwrap().version, old.version);
+        let rules = store.rules().unwrap().unwrap().rules;
+        assert_eq!(rules.keys().collect::<Vec<_>>(), ["other/a", "test/b"]);
+        assert_eq!(rules["test/b"].provenance, "test@1.1.0");
+
+        let err = updater.import("test", &seal(&old)).unwrap_err();
+        assert!(
+            matches!(err.downcast_ref(), Some(FreshnessError::Rollback { .. })),
+            "{err:#}"
+        );
+        let stranger = SigningKey::generate(SignatureAlgorithm::Ed25519).unwrap();
+        let forged = Envelope::sign(RULE_BUNDLE_TYPE, serde_json::to_vec(&new).unwrap(), &[&stranger]).unwrap();
+        assert!(updater.import("other", &serde_json::to_vec(&forged).unwrap()).is_err());
+        assert!(updater.import("tuf", &exported).is_err());
+        assert!(updater.import("missing", &exported).is_err());
+    }
+
+    #[tokio::test]
+    async fn conditional_requests_skip_unchanged_bundles() {
+        use crate::delta::tests::bundle;
+
+        let key = SigningKey::generate(SignatureAlgorithm::Ed25519).unwrap();
+        let sealed = serde_json::to_vec(
+            &Envelope::sign(RULE_BUNDLE_TYPE, serde_json::to_vec(&bundle("1.0.0", &[("a", "alpha")])).unwrap(), &[&key])
+                .unwrap(),
+        )
+        .unwrap();
+        let pki = pinning::tests::TestPki::generate();
+        let port = pki.serve_files(vec![("*".into(), sealed)]).await;
+        let mut src = source(vec![key.public_key()], SignaturePolicy::RequireAny);
+        src.url = format!("https://localhost:{port}/latest.json").parse().unwrap();
+        src.pin_sha256 = vec![SpkiPin::of_certificate(&pki.leaf).unwrap()];
+        let dir = tempfile::tempdir().unwrap();
+        let cfg = UpdateConfig {
+            sources: vec![src.clone()],
+            cache_dir: dir.path().to_owned(),
+            ..Default::default()
+        };
+        let updater = Updater::with_roots(cfg.clone(), pki.roots()).unwrap();
+
+        assert!(matches!(updater.update().await.unwrap(), Installed::Activated(ref g) if g.id == 1));
+        let validators: BTreeMap<String, Validators> =
+            serde_json::from_slice(&fs::read(cfg.http_cache_path()).unwrap()).unwrap();
+        assert!(validators[src.url.as_str()].etag.is_some());
+
+        // The stand-in answers a matching `If-None-Match` with 304.
+        assert_eq!(updater.update().await.unwrap(), Installed::Unchanged);
+        assert_eq!(pki.not_modified.load(std::sync::atomic::Ordering::SeqCst), 1);
+        // Without validators the same bundle is downloaded but still not
+        // installed as a new generation.
+        fs::remove_file(cfg.http_cache_path()).unwrap();
+        assert_eq!(updater.update().await.unwrap(), Installed::Unchanged);
+        assert_eq!(cfg.store().generations().unwrap().len(), 1);
+
+        let empty = Updater::new(UpdateConfig::default()).unwrap();
+        assert!(empty.update().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn falls_back_to_mirrors_in_order_through_proxy() {
+        use crate::delta::tests::bundle;
+        use crate::mirrors::tests::{connect_proxy, pem};
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+
+        let key = SigningKey::generate(SignatureAlgorithm::Ed25519).unwrap();
+        let seal = |bundle: &RuleBundle| {
+            let envelope = Envelope::sign(RULE_BUNDLE_TYPE, serde_json::to_vec(bundle).unwrap(), &[&key]).unwrap();
+            serde_json::to_vec(&envelope).unwrap()
+        };
+        let (old, new) = (bundle("1.0.0", &[("a", "alpha")]), bundle("1.1.0", &[("b", "beta")]));
+
+        // The vendor endpoint is down, the first mirror is stale and the
+        // second, run by another CA, is current.
+        let (vendor, internal) = (pinning::tests::TestPki::generate(), pinning::tests::TestPki::generate());
+        let stale = vendor.serve_files(vec![("/av/latest.json".into(), seal(&old))]).await;
+        let current = internal.serve_files(vec![("/av/latest.json".into(), seal(&new))]).await;
+        let tunnels = Arc::new(AtomicUsize::new(0));
+        let proxy = connect_proxy(tunnels.clone()).await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let ca_bundle = dir.path().join("corp-ca.pem");
+        fs::write(&ca_bundle, pem(&vendor.root) + &pem(&internal.root)).unwrap();
+        let mut src = source(vec![key.public_key()], SignaturePolicy::RequireAny);
+        src.url = format!("https://localhost:{stale}/gone.json").parse().unwrap();
+        src.pin_sha256 = vec![SpkiPin::of_certificate(&vendor.leaf).unwrap()];
+        src.mirrors = vec![
+            Mirror {
+                url: format!("https://localhost:{stale}/av/latest.json").parse().unwrap(),
+                pin_sha256: vec![SpkiPin::of_certificate(&vendor.leaf).unwrap()],
+                delta_url: None,
+            },
+            Mirror {
+                url: format!("https://localhost:{current}/av/latest.json").parse().unwrap(),
+                pin_sha256: vec![SpkiPin::of_certificate(&internal.intermediate).unwrap()],
+                delta_url: None,
+            },
+        ];
+        src.proxy = Some(Proxy {
+            url: format!("http://127.0.0.1:{proxy}").parse().unwrap(),
+            ca_bundle: Some(ca_bundle),
+        });
+        let cfg = UpdateConfig {
+            sources: vec![src.clone()],
+            cache_dir: dir.path().join("cache"),
+            ..Default::default()
+        };
+        let mut state = UpdateState::default();
+        state.record("test", &new, Utc::now());
+        state.save(&cfg.state_path()).unwrap();
+
+        // Only the bundled roots and the proxy's CA bundle are trusted.
+        let updater = Updater::new(cfg.clone()).unwrap();
+        let Installed::Activated(generation) = updater.update().await.unwrap() else {
+            panic!("mirror bundle not installed");
+        };
+        assert_eq!(generation.served_by["test"], src.mirrors[1].url.as_str());
+        assert_eq!(cfg.store().cached("test").unwrap().unwrap().version, new.version);
+        assert_eq!(tunnels.load(Ordering::SeqCst), 3);
+
+        // A second mirror pinned to the wrong key is a pin failure, reported
+        // once every endpoint has been tried.
+        src.mirrors[1].pin_sha256 = vec![SpkiPin([1; 32])];
+        let cfg = UpdateConfig {
+            sources: vec![src],
+            ..cfg
+        };
+        let err = Updater::new(cfg).unwrap().update().await.unwrap_err();
+        let failed = err.downcast_ref::<SourcesFailed>().expect("per-source failure report");
+        assert_eq!(failed.installed, Installed::Unchanged);
+        let (name, err) = &failed.failures[0];
+        assert_eq!(name, "test");
+        assert!(err.to_string().contains("all 3 endpoints of source `test` failed"), "{err:#}");
+        assert!(err.downcast_ref::<pinning::PinMismatch>().is_some(), "{err:#}");
+    }
+
+    #[tokio::test]
+    async fn failing_source_does_not_hold_back_the_others() {
+        use crate::delta::tests::bundle;
+
+        let key = SigningKey::generate(SignatureAlgorithm::Ed25519).unwrap();
+        let sealed = serde_json::to_vec(
+            &Envelope::sign(RULE_BUNDLE_TYPE, serde_json::to_vec(&bundle("1.0.0", &[("a", "alpha")])).unwrap(), &[&key])
+                .unwrap(),
+        )
+        .unwrap();
+        let pki = pinning::tests::TestPki::generate();
+        let port = pki.serve_files(vec![("/latest.json".into(), sealed)]).await;
+        let mut src = source(vec![key.public_key()], SignaturePolicy::RequireAny);
+        src.url = format!("https://localhost:{port}/latest.json").parse().unwrap();
+        src.pin_sha256 = vec![SpkiPin::of_certificate(&pki.leaf).unwrap()];
+        let mut broken = src.clone();
+        broken.name = "broken".into();
+        broken.url = format!("https://localhost:{port}/missing.json").parse().unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let cfg = UpdateConfig {
+            sources: vec![broken, src],
+            cache_dir: dir.path().to_owned(),
+            ..Default::default()
+        };
+        let updater = Updater::with_roots(cfg.clone(), pki.roots()).unwrap();
+
+        let err = updater.update().await.unwrap_err();
+        let failed = err.downcast_ref::<SourcesFailed>().expect("per-source failure report");
+        assert!(matches!(failed.installed, Installed::Activated(ref g) if g.id == 1), "{failed:?}");
+        assert_eq!(failed.failures.len(), 1);
+        assert_eq!(failed.failures[0].0, "broken");
+        assert!(err.to_string().contains("source `broken`: fetching"), "{err:#}");
+        let rules = cfg.store().rules().unwrap().unwrap().rules;
+        assert_eq!(rules.keys().collect::<Vec<_>>(), ["test/a"]);
+
+        // Still failing with nothing cached: no new generation either.
+        let err = updater.update().await.unwrap_err();
+        assert_eq!(err.downcast_ref::<SourcesFailed>().unwrap().installed, Installed::Unchanged);
+        assert_eq!(cfg.store().generations().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn replayed_cached_release_is_checked_for_expiry() {
+        use crate::delta::tests::bundle;
+
+        let key = SigningKey::generate(SignatureAlgorithm::Ed25519).unwrap();
+        let mut release = bundle("1.0.0", &[("a", "alpha")]);
+        release.issued_at = Utc::now() - chrono::Duration::hours(1);
+        release.expires_at = Utc::now() + chrono::Duration::seconds(1);
+        let sealed =
+            serde_json::to_vec(&Envelope::sign(RULE_BUNDLE_TYPE, serde_json::to_vec(&release).unwrap(), &[&key]).unwrap())
+                .unwrap();
+        let pki = pinning::tests::TestPki::generate();
+        let port = pki.serve_files(vec![("/latest.json".into(), sealed.clone())]).await;
+        let mut src = source(vec![key.public_key()], SignaturePolicy::RequireAny);
+        src.url = format!("https://localhost:{port}/latest.json").parse().unwrap();
+        src.pin_sha256 = vec![SpkiPin::of_certificate(&pki.leaf).unwrap()];
+        let dir = tempfile::tempdir().unwrap();
+        let cfg = UpdateConfig {
+            sources: vec![src],
+            cache_dir: dir.path().to_owned(),
+            ..Default::default()
+        };
+        let updater = Updater::with_roots(cfg.clone(), pki.roots()).unwrap();
+        assert!(matches!(updater.import("test", &sealed).unwrap(), Installed::Activated(ref g) if g.id == 1));
+
+        tokio::time::sleep(std::time::Duration::from_millis(1500)).await;
+        // The mirror keeps serving the cached release after it expired.
+        let err = updater.update().await.unwrap_err();
+        let failed = err.downcast_ref::<SourcesFailed>().expect("per-source failure report");
+        assert_eq!(failed.installed, Installed::Unchanged);
+        assert_eq!(failed.failures[0].0, "test");
+        assert!(
+            matches!(failed.failures[0].1.downcast_ref::<FreshnessError>(), Some(FreshnessError::Expired(_))),
+            "{err:#}"
+        );
+        let err = updater.import("test", &sealed).unwrap_err();
+        assert!(matches!(err.downcast_ref::<FreshnessError>(), Some(FreshnessError::Expired(_))), "{err:#}");
+        assert_eq!(cfg.store().generations().unwrap().len(), 1);
+    }
 }
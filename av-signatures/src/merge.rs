@@ -0,0 +1,135 @@
+//! Merging bundles from several update sources into one active rule set.
+//!
+//! Each source publishes into a namespace, by default its own name, and its
+//! rules are keyed `<namespace>/<rule id>`. Sources only collide when they
+//! share a namespace, e.g. a primary feed and a mirror or a local override
+//! feed; the source with the higher `priority` then wins each contested id,
+//! with ties going to the source listed first.
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use av_core::signatures::{RuleBundle, RuleMetadata};
+
+use crate::UpdateSource;
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct MergedRules {
+    /// Active rules keyed by namespaced id. `provenance` names the source
+    /// and bundle version each rule came from.
+    pub rules: BTreeMap<String, RuleMetadata>,
+    /// Rules dropped in favour of a higher-priority source.
+    #[serde(default)]
+    pub shadowed: Vec<Shadowed>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Shadowed {
+    pub id: String,
+    pub kept: String,
+    pub dropped: String,
+}
+
+pub fn merge(bundles: &[(&UpdateSource, &RuleBundle)]) -> anyhow::Result<MergedRules> {
+    let mut ordered = bundles.to_vec();
+    // Stable, so equal priorities keep configuration order.
+    ordered.sort_by_key(|(source, _)| std::cmp::Reverse(source.priority));
+
+    let mut merged = MergedRules::default();
+    let mut owners: BTreeMap<String, &str> = BTreeMap::new();
+    for (source, bundle) in ordered {
+        let namespace = source.namespace();
+        anyhow::ensure!(
+            !namespace.is_empty() && !namespace.contains('/'),
+            "namespace `{namespace}` of source `{}` must be non-empty and contain no `/`",
+            source.name
+        );
+        let mut ids: Vec<&String> = bundle.rules.keys().collect();
+        ids.sort();
+        for id in ids {
+            let qualified = format!("{namespace}/{id}");
+            if let Some(owner) = owners.get(&qualified) {
+                merged.shadowed.push(Shadowed {
+                    id: qualified,
+                    kept: owner.to_string(),
+                    dropped: source.name.clone(),
+                });
+                continue;
+            }
+            let mut rule = bundle.rules[id].clone();
+            rule.id = qualified.clone();
+            rule.provenance = format!("{}@{}", source.name, bundle.version);
+            owners.insert(qualified.clone(), &source.name);
+            merged.rules.insert(qualified, rule);
+        }
+    }
+    Ok(merged)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::delta::tests::bundle;
+
+    fn source(name: &str, namespace: Option<&str>, priority: i32) -> UpdateSource {
+        serde_json::from_value(serde_json::json!({
+            "name": name,
+            "url": "https://updates.example/latest.json",
+            "namespace": namespace,
+            "priority": priority,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn namespaces_rules_per_source() {
+        let (vendor, lab) = (source("vendor", None, 0), source("lab", None, 0));
+        let a = bundle("1.0.0", &[("eicar", "vendor eicar")]);
+        let b = bundle("2.0.0", &[("eicar", "lab eicar"), ("canary", "lab canary")]);
+        let merged = merge(&[(&vendor, &a), (&lab, &b)]).unwrap();
+
+        assert_eq!(
+            merged.rules.keys().collect::<Vec<_>>(),
+            ["lab/canary", "lab/eicar", "vendor/eicar"]
+        );
+        assert!(merged.shadowed.is_empty());
+        let rule = &merged.rules["vendor/eicar"];
+        assert_eq!((rule.id.as_str(), rule.provenance.as_str()), ("vendor/eicar", "vendor@1.0.0"));
+    }
+
+    #[test]
+    fn shared_namespace_resolved_by_priority() {
+        let primary = source("primary", Some("charmedwoa"), 0);
+        let overrides = source("overrides", Some("charmedwoa"), 10);
+        let mirror = source("mirror", Some("charmedwoa"), 0);
+        let a = bundle("1.0.0", &[("eicar", "primary eicar"), ("pe", "primary pe")]);
+        let b = bundle("1.0.1", &[("eicar", "tuned eicar")]);
+        let c = bundle("0.9.0", &[("pe", "mirror pe"), ("elf", "mirror elf")]);
+        let merged = merge(&[(&primary, &a), (&overrides, &b), (&mirror, &c)]).unwrap();
+
+        assert_eq!(merged.rules["charmedwoa/eicar"].description, "tuned eicar");
+        assert_eq!(merged.rules["charmedwoa/eicar"].provenance, "overrides@1.0.1");
+        // Equal priority: the source listed first keeps the id.
+        assert_eq!(merged.rules["charmedwoa/pe"].provenance, "primary@1.0.0");
+        assert_eq!(merged.rules["charmedwoa/elf"].provenance, "mirror@0.9.0");
+        assert_eq!(
+            merged.shadowed,
+            [
+                Shadowed {
+                    id: "charmedwoa/eicar".into(),
+                    kept: "overrides".into(),
+                    dropped: "primary".into()
+                },
+                Shadowed {
+                    id: "charmedwoa/pe".into(),
+                    kept: "primary".into(),
+                    dropped: "mirror".into()
+                },
+            ]
+        );
+
+        let bad = source("bad", Some("a/b"), 0);
+        assert!(merge(&[(&bad, &a)]).is_err());
+    }
+}
@@ -0,0 +1,190 @@
+//! Where a source's bundles are downloaded from.
+//!
+//! A source is fetched from its own `url` first and then from each of its
+//! `mirrors` in order, until one serves a bundle that verifies and passes
+//! the rollback checks. Every mirror carries its own SPKI pins: an internal
+//! mirror presents a certificate from the site's CA, not the vendor's.
+//!
+//! All requests for a source can be sent through a `proxy`. Its optional
+//! `ca_bundle` is trusted in addition to the bundled roots, which covers
+//! both HTTPS proxies and proxies that re-sign the TLS connections they
+//! relay. Pins are still checked against what the proxy presents, so a
+//! pinned mirror behind a re-signing proxy needs the proxy's key pinned.
+
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use anyhow::Context;
+use reqwest::Client;
+use rustls::{Certificate, ClientConfig, RootCertStore};
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::pinning::{self, PinnedVerifier, SpkiPin};
+use crate::UpdateSource;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Mirror {
+    /// Same layout as the source's `url`: the bundle itself, or the
+    /// repository root of a TUF-style source.
+    pub url: Url,
+    #[serde(default, deserialize_with = "pinning::one_or_many")]
+    pub pin_sha256: Vec<SpkiPin>,
+    #[serde(default)]
+    pub delta_url: Option<Url>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Proxy {
+    /// `http://` or `https://`, with `user:password@` if the proxy requires
+    /// basic authentication.
+    pub url: Url,
+    /// PEM certificates trusted for connections made through the proxy.
+    #[serde(default)]
+    pub ca_bundle: Option<PathBuf>,
+}
+
+/// The source URL or one of its mirrors, with a client configured for it.
+pub(crate) struct Endpoint {
+    pub url: Url,
+    pub delta_url: Option<Url>,
+    pub http: Client,
+    /// Set when the endpoint is pinned, to recover the pin mismatch behind
+    /// a failed handshake.
+    pub verifier: Option<Arc<PinnedVerifier>>,
+}
+
+impl Endpoint {
+    /// The source's endpoints in the order they are tried, validating TLS
+    /// against `roots` plus the proxy's CA bundle.
+    pub(crate) fn for_source(source: &UpdateSource, roots: &RootCertStore) -> anyhow::Result<Vec<Self>> {
+        let mut roots = roots.clone();
+        if let Some(path) = source.proxy.as_ref().and_then(|p| p.ca_bundle.as_ref()) {
+            add_pem_bundle(&mut roots, &fs::read(path).with_context(|| format!("reading {}", path.display()))?)
+                .with_context(|| format!("CA bundle {}", path.display()))?;
+        }
+        let primary = (&source.url, &source.delta_url, &source.pin_sha256);
+        let mirrors = source.mirrors.iter().map(|m| (&m.url, &m.delta_url, &m.pin_sha256));
+        std::iter::once(primary)
+            .chain(mirrors)
+            .map(|(url, delta_url, pins)| {
+                let verifier = (!pins.is_empty()).then(|| PinnedVerifier::new(roots.clone(), pins.clone()));
+                let tls = match &verifier {
+                    Some(verifier) => verifier.client_config(),
+                    None => ClientConfig::builder()
+                        .with_safe_defaults()
+                        .with_root_certificates(roots.clone())
+                        .with_no_client_auth(),
+                };
+                let mut http = Client::builder().use_preconfigured_tls(tls);
+                if let Some(proxy) = &source.proxy {
+                    http = http.proxy(reqwest::Proxy::all(proxy.url.as_str())?);
+                }
+                Ok(Self {
+                    url: url.clone(),
+                    delta_url: delta_url.clone(),
+                    http: http.build()?,
+                    verifier,
+                })
+            })
+            .collect()
+    }
+}
+
+fn add_pem_bundle(roots: &mut RootCertStore, pem: &[u8]) -> anyhow::Result<()> {
+    let mut added = 0;
+    for block in x509_parser::pem::Pem::iter_from_buffer(pem) {
+        let block = block?;
+        if block.label == "CERTIFICATE" {
+            roots.add(&Certificate(block.contents))?;
+            added += 1;
+        }
+    }
+    anyhow::ensure!(added > 0, "no certificates found");
+    Ok(())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use base64::engine::general_purpose::STANDARD as BASE64;
+    use base64::Engine;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+
+    use crate::pinning::tests::TestPki;
+
+    pub(crate) fn pem(der: &[u8]) -> String {
+        let encoded = BASE64.encode(der);
+        let lines: Vec<&str> = encoded
+            .as_bytes()
+            .chunks(64)
+            .map(|line| std::str::from_utf8(line).unwrap())
+            .collect();
+        format!("-----BEGIN CERTIFICATE-----\n{}\n-----END CERTIFICATE-----\n", lines.join("\n"))
+    }
+
+    /// A forward proxy that tunnels `CONNECT` requests to local ports,
+    /// counting each tunnel in `tunnels`. Returns its port.
+    pub(crate) async fn connect_proxy(tunnels: Arc<AtomicUsize>) -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            loop {
+                let Ok((mut client, _)) = listener.accept().await else { return };
+                let tunnels = tunnels.clone();
+                tokio::spawn(async move {
+                    let mut head = Vec::new();
+                    let mut byte = [0u8; 1];
+                    while !head.ends_with(b"\r\n\r\n") {
+                        if client.read(&mut byte).await.unwrap_or(0) == 0 {
+                            return;
+                        }
+                        head.push(byte[0]);
+                    }
+                    let head = String::from_utf8_lossy(&head);
+                    let target_port = head
+                        .strip_prefix("CONNECT ")
+                        .and_then(|rest| rest.split(' ').next())
+                        .and_then(|target| target.rsplit_once(':'))
+                        .and_then(|(_, port)| port.parse::<u16>().ok());
+                    let Some(target_port) = target_port else { return };
+                    let Ok(mut upstream) = TcpStream::connect(("127.0.0.1", target_port)).await else { return };
+                    tunnels.fetch_add(1, Ordering::SeqCst);
+                    let _ = client.write_all(b"HTTP/1.1 200 Connection established\r\n\r\n").await;
+                    let _ = tokio::io::copy_bidirectional(&mut client, &mut upstream).await;
+                });
+            }
+        });
+        port
+    }
+
+    #[test]
+    fn parses_mirrors_and_proxy() {
+        let source: UpdateSource = serde_json::from_value(serde_json::json!({
+            "name": "default",
+            "url": "https://updates.example/latest.json",
+            "mirrors": [{
+                "url": "https://mirror.corp.example/av/latest.json",
+                "pin_sha256": "sha256/47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU=",
+            }],
+            "proxy": { "url": "http://proxy.corp.example:3128" },
+        }))
+        .unwrap();
+        assert_eq!(source.mirrors[0].pin_sha256.len(), 1);
+        assert_eq!(source.proxy.as_ref().unwrap().ca_bundle, None);
+        assert_eq!(Endpoint::for_source(&source, &RootCertStore::empty()).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn rejects_ca_bundle_without_certificates() {
+        let mut roots = RootCertStore::empty();
+        assert!(add_pem_bundle(&mut roots, b"not a certificate").is_err());
+        add_pem_bundle(&mut roots, pem(&TestPki::generate().root).as_bytes()).unwrap();
+        assert_eq!(roots.len(), 1);
+    }
+}
@@ -0,0 +1,347 @@
+//! TLS public-key pinning for update endpoints.
+//!
+//! Pins are SHA-256 digests of a certificate's DER `SubjectPublicKeyInfo`,
+//! written `sha256/<base64>` as in HPKP. The normal WebPKI chain validation
+//! runs first; the connection is then accepted only if the leaf or one of
+//! the intermediates the server sent matches a pin. Listing a backup pin
+//! (for a key not yet in service, or a second CA) keeps updates working
+//! across a planned certificate change.
+
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use rustls::client::{ServerCertVerified, ServerCertVerifier, WebPkiVerifier};
+use rustls::{Certificate, CertificateError, ClientConfig, OwnedTrustAnchor, RootCertStore, ServerName};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use sha2::{Digest, Sha256};
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SpkiPin(pub [u8; 32]);
+
+impl SpkiPin {
+    /// Pin of the public key in a DER certificate.
+    pub fn of_certificate(der: &[u8]) -> anyhow::Result<Self> {
+        let (_, cert) = x509_parser::parse_x509_certificate(der)?;
+        Ok(Self(Sha256::digest(cert.public_key().raw).into()))
+    }
+}
+
+impl fmt::Display for SpkiPin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "sha256/{}", BASE64.encode(self.0))
+    }
+}
+
+impl fmt::Debug for SpkiPin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl FromStr for SpkiPin {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let encoded = s
+            .strip_prefix("sha256/")
+            .ok_or_else(|| anyhow::anyhow!("SPKI pin `{s}` must start with `sha256/`"))?;
+        let digest = BASE64.decode(encoded)?;
+        let digest = digest
+            .try_into()
+            .map_err(|_| anyhow::anyhow!("SPKI pin `{s}` is not a SHA-256 digest"))?;
+        Ok(Self(digest))
+    }
+}
+
+impl Serialize for SpkiPin {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for SpkiPin {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// Accept a single pin or a list, so a backup pin can be added to an
+/// existing configuration without restructuring it.
+pub(crate) fn one_or_many<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<SpkiPin>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(SpkiPin),
+        Many(Vec<SpkiPin>),
+    }
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(pin) => vec![pin],
+        OneOrMany::Many(pins) => pins,
+    })
+}
+
+/// The server's chain validated but none of its keys is pinned.
+#[derive(Clone, Debug, PartialEq, Eq, thiserror::Error)]
+#[error("TLS public key pin mismatch for {host}: server presented {presented:?}")]
+pub struct PinMismatch {
+    pub host: String,
+    pub presented: Vec<SpkiPin>,
+}
+
+/// WebPKI verification plus an SPKI pin check.
+///
+/// rustls reports verifier errors to the caller only as an opaque alert, so
+/// the last mismatch is also kept here for [`PinnedVerifier::take_mismatch`].
+pub struct PinnedVerifier {
+    inner: WebPkiVerifier,
+    pins: Vec<SpkiPin>,
+    mismatch: Mutex<Option<PinMismatch>>,
+}
+
+impl PinnedVerifier {
+    pub fn new(roots: RootCertStore, pins: Vec<SpkiPin>) -> Arc<Self> {
+        Arc::new(Self {
+            inner: WebPkiVerifier::new(roots, None),
+            pins,
+            mismatch: Mutex::new(None),
+        })
+    }
+
+    /// Client configuration that verifies with `self`.
+    pub fn client_config(self: &Arc<Self>) -> ClientConfig {
+        ClientConfig::builder()
+            .with_safe_defaults()
+            .with_custom_certificate_verifier(self.clone())
+            .with_no_client_auth()
+    }
+
+    pub fn take_mismatch(&self) -> Option<PinMismatch> {
+        self.mismatch.lock().unwrap_or_else(|e| e.into_inner()).take()
+    }
+}
+
+impl ServerCertVerifier for PinnedVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        server_name: &ServerName,
+        scts: &mut dyn Iterator<Item = &[u8]>,
+        ocsp_response: &[u8],
+        now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let verified =
+            self.inner
+                .verify_server_cert(end_entity, intermediates, server_name, scts, ocsp_response, now)?;
+        let presented = std::iter::once(end_entity)
+            .chain(intermediates)
+            .map(|cert| SpkiPin::of_certificate(&cert.0))
+            .collect::<anyhow::Result<Vec<_>>>()
+            .map_err(|_| rustls::Error::InvalidCertificate(CertificateError::BadEncoding))?;
+        if presented.iter().any(|pin| self.pins.contains(pin)) {
+            return Ok(verified);
+        }
+        let host = match server_name {
+            ServerName::DnsName(name) => name.as_ref().to_owned(),
+            ServerName::IpAddress(addr) => addr.to_string(),
+            _ => "unknown host".to_owned(),
+        };
+        let mismatch = PinMismatch { host, presented };
+        *self.mismatch.lock().unwrap_or_else(|e| e.into_inner()) = Some(mismatch.clone());
+        Err(rustls::Error::InvalidCertificate(CertificateError::Other(Arc::new(mismatch))))
+    }
+}
+
+/// The Mozilla root set bundled with the client.
+pub fn webpki_roots() -> RootCertStore {
+    let mut roots = RootCertStore::empty();
+    roots.add_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.iter().map(|ta| {
+        OwnedTrustAnchor::from_subject_spki_name_constraints(ta.subject, ta.spki, ta.name_constraints)
+    }));
+    roots
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use rcgen::{BasicConstraints, Certificate as RcgenCert, CertificateParams, IsCa};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// Root CA, intermediate and `localhost` leaf.
+    pub(crate) struct TestPki {
+        pub root: Vec<u8>,
+        pub intermediate: Vec<u8>,
+        pub leaf: Vec<u8>,
+        leaf_key: Vec<u8>,
+        /// 304 responses sent by the servers started from this PKI.
+        pub not_modified: Arc<AtomicUsize>,
+    }
+
+    impl TestPki {
+        pub(crate) fn generate() -> Self {
+            let ca = |name: &str| {
+                let mut params = CertificateParams::new(vec![]);
+                params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+                params.distinguished_name.push(rcgen::DnType::CommonName, name);
+                RcgenCert::from_params(params).unwrap()
+            };
+            let root = ca("test root");
+            let intermediate = ca("test intermediate");
+            let leaf = RcgenCert::from_params(CertificateParams::new(vec!["localhost".into()])).unwrap();
+            Self {
+                root: root.serialize_der().unwrap(),
+                intermediate: intermediate.serialize_der_with_signer(&root).unwrap(),
+                leaf: leaf.serialize_der_with_signer(&intermediate).unwrap(),
+                leaf_key: leaf.serialize_private_key_der(),
+                not_modified: Arc::default(),
+            }
+        }
+
+        pub(crate) fn roots(&self) -> RootCertStore {
+            let mut roots = RootCertStore::empty();
+            roots.add(&Certificate(self.root.clone())).unwrap();
+            roots
+        }
+
+        /// Serve `body` at every path over HTTPS on a local port until the
+        /// runtime stops.
+        pub(crate) async fn serve(&self, body: &'static [u8]) -> u16 {
+            self.serve_files(vec![("*".into(), body.to_vec())]).await
+        }
+
+        /// Serve `(path, body)` pairs, `*` matching any path; others get 404.
+        /// Bodies carry an `ETag`, and a matching `If-None-Match` gets 304.
+        pub(crate) async fn serve_files(&self, files: Vec<(String, Vec<u8>)>) -> u16 {
+            let files = Arc::new(files);
+            let not_modified = self.not_modified.clone();
+            let config = rustls::ServerConfig::builder()
+                .with_safe_defaults()
+                .with_no_client_auth()
+                .with_single_cert(
+                    vec![Certificate(self.leaf.clone()), Certificate(self.intermediate.clone())],
+                    rustls::PrivateKey(self.leaf_key.clone()),
+                )
+                .unwrap();
+            let acceptor = tokio_rustls::TlsAcceptor::from(Arc::new(config));
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let port = listener.local_addr().unwrap().port();
+            tokio::spawn(async move {
+                loop {
+                    let Ok((tcp, _)) = listener.accept().await else { return };
+                    let acceptor = acceptor.clone();
+                    let files = files.clone();
+                    let not_modified = not_modified.clone();
+                    tokio::spawn(async move {
+                        let Ok(mut tls) = acceptor.accept(tcp).await else { return };
+                        let mut request = [0u8; 4096];
+                        let n = tls.read(&mut request).await.unwrap_or(0);
+                        let request = String::from_utf8_lossy(&request[..n]);
+                        let path = request.split(' ').nth(1).unwrap_or("");
+                        let found = files.iter().find(|(p, _)| p == "*" || p == path);
+                        let etag = found.map(|(_, body)| format!("\"{}\"", hex::encode(&Sha256::digest(body)[..8])));
+                        let if_none_match = request
+                            .lines()
+                            .filter_map(|line| line.split_once(':'))
+                            .find(|(name, _)| name.eq_ignore_ascii_case("if-none-match"))
+                            .map(|(_, value)| value.trim());
+                        let (status, body) = match found {
+                            Some(_) if if_none_match.is_some() && if_none_match == etag.as_deref() => {
+                                not_modified.fetch_add(1, Ordering::SeqCst);
+                                ("304 Not Modified", &b""[..])
+                            }
+                            Some((_, body)) => ("200 OK", body.as_slice()),
+                            None => ("404 Not Found", &b""[..]),
+                        };
+                        let etag = etag.map(|etag| format!("etag: {etag}\r\n")).unwrap_or_default();
+                        let head = format!(
+                            "HTTP/1.1 {status}\r\n{etag}content-length: {}\r\nconnection: close\r\n\r\n",
+                            body.len()
+                        );
+                        let _ = tls.write_all(head.as_bytes()).await;
+                        let _ = tls.write_all(body).await;
+                        let _ = tls.shutdown().await;
+                    });
+                }
+            });
+            port
+        }
+    }
+
+    async fn fetch(pki: &TestPki, pins: Vec<SpkiPin>, port: u16) -> (reqwest::Result<String>, Arc<PinnedVerifier>) {
+        let verifier = PinnedVerifier::new(pki.roots(), pins);
+        let client = reqwest::Client::builder()
+            .use_preconfigured_tls(verifier.client_config())
+            .build()
+            .unwrap();
+        let result = async {
+            client
+                .get(format!("https://localhost:{port}/"))
+                .send()
+                .await?
+                .text()
+                .await
+        }
+        .await;
+        (result, verifier)
+    }
+
+    #[tokio::test]
+    async fn accepts_leaf_intermediate_and_backup_pins() {
+        let pki = TestPki::generate();
+        let port = pki.serve(b"ok").await;
+        let leaf = SpkiPin::of_certificate(&pki.leaf).unwrap();
+        let intermediate = SpkiPin::of_certificate(&pki.intermediate).unwrap();
+        let unused = SpkiPin([7; 32]);
+
+        for pins in [vec![leaf], vec![intermediate], vec![unused, leaf]] {
+            let (result, verifier) = fetch(&pki, pins, port).await;
+            assert_eq!(result.unwrap(), "ok");
+            assert_eq!(verifier.take_mismatch(), None);
+        }
+    }
+
+    #[tokio::test]
+    async fn reports_mismatch_distinctly() {
+        let pki = TestPki::generate();
+        let port = pki.serve(b"ok").await;
+        // The root is trusted but not presented by the server, so pinning it
+        // does not count.
+        let root = SpkiPin::of_certificate(&pki.root).unwrap();
+
+        let (result, verifier) = fetch(&pki, vec![root], port).await;
+        assert!(result.is_err());
+        let mismatch = verifier.take_mismatch().unwrap();
+        assert_eq!(mismatch.host, "localhost");
+        assert_eq!(
+            mismatch.presented,
+            vec![
+                SpkiPin::of_certificate(&pki.leaf).unwrap(),
+                SpkiPin::of_certificate(&pki.intermediate).unwrap()
+            ]
+        );
+
+        // An untrusted chain fails validation before pins are consulted.
+        let other = TestPki::generate();
+        let leaf = SpkiPin::of_certificate(&pki.leaf).unwrap();
+        let (result, verifier) = fetch(&other, vec![leaf], port).await;
+        assert!(result.is_err());
+        assert_eq!(verifier.take_mismatch(), None);
+    }
+
+    #[test]
+    fn pin_syntax() {
+        let pin: SpkiPin = "sha256/47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU=".parse().unwrap();
+        assert_eq!(pin, SpkiPin(Sha256::digest(b"").into()));
+        assert_eq!(pin.to_string(), "sha256/47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU=");
+        assert!("47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU=".parse::<SpkiPin>().is_err());
+        assert!("sha256/AAAA".parse::<SpkiPin>().is_err());
+    }
+}
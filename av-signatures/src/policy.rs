@@ -0,0 +1,275 @@
+//! Multi-signature acceptance policies.
+//!
+//! An envelope may carry several signatures, each naming the key that made
+//! it. A source lists its trusted keys and a [`SignaturePolicy`] saying how
+//! many of them must have signed, so a hybrid Ed25519 + ML-DSA source can
+//! insist on both while a single-key source keeps working unchanged.
+
+use std::collections::BTreeSet;
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::crypto::{PublicKey, SignatureAlgorithm, SignatureError};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SignaturePolicy {
+    /// Every listed key must have signed.
+    RequireAll,
+    /// Any one listed key is enough.
+    #[default]
+    RequireAny,
+    /// At least this many distinct listed keys must have signed.
+    Threshold(usize),
+}
+
+impl SignaturePolicy {
+    /// Number of verified keys needed out of `keys` configured ones.
+    pub fn required(self, keys: usize) -> anyhow::Result<usize> {
+        anyhow::ensure!(keys > 0, "no signing keys configured");
+        match self {
+            Self::RequireAll => Ok(keys),
+            Self::RequireAny => Ok(1),
+            Self::Threshold(k) => {
+                anyhow::ensure!(k > 0 && k <= keys, "threshold {k} outside 1..={keys} configured keys");
+                Ok(k)
+            }
+        }
+    }
+}
+
+impl fmt::Display for SignaturePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::RequireAll => f.write_str("require-all"),
+            Self::RequireAny => f.write_str("require-any"),
+            Self::Threshold(k) => write!(f, "{k}-of-n"),
+        }
+    }
+}
+
+/// One signature in an envelope.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BundleSignature {
+    /// [`PublicKey::key_id`] of the signing key.
+    pub key_id: String,
+    pub algorithm: SignatureAlgorithm,
+    #[serde(with = "av_core::base64_bytes")]
+    pub signature: Vec<u8>,
+}
+
+/// Why a signature, or a required key, did not count towards the policy.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Rejection {
+    /// The envelope has no signature from this configured key.
+    Missing,
+    /// The signature names a key the source does not trust.
+    UnknownKey,
+    /// The signature claims a different algorithm than the key it names.
+    AlgorithmMismatch { claimed: SignatureAlgorithm },
+    Failed(SignatureError),
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Missing => f.write_str("no signature"),
+            Self::UnknownKey => f.write_str("key not trusted by this source"),
+            Self::AlgorithmMismatch { claimed } => write!(f, "signature claims {claimed}"),
+            Self::Failed(err) => err.fmt(f),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SignatureReport {
+    pub key_id: String,
+    pub algorithm: SignatureAlgorithm,
+    pub rejection: Rejection,
+}
+
+#[derive(Debug)]
+pub struct PolicyError {
+    pub policy: SignaturePolicy,
+    pub verified: usize,
+    pub required: usize,
+    pub rejections: Vec<SignatureReport>,
+}
+
+impl fmt::Display for PolicyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "signature policy {} not met: {} of {} required keys verified",
+            self.policy, self.verified, self.required
+        )?;
+        for report in &self.rejections {
+            write!(f, "; {} key {}: {}", report.algorithm, short_id(&report.key_id), report.rejection)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for PolicyError {}
+
+fn short_id(key_id: &str) -> &str {
+    key_id.get(..12).unwrap_or(key_id)
+}
+
+/// Check `signatures` over `message` against the trusted `keys`.
+///
+/// Returns the ids of the keys that verified. Several signatures from one
+/// key count once; signatures from unknown keys are ignored unless the
+/// policy fails, in which case they are reported alongside the rest.
+pub fn enforce(
+    policy: SignaturePolicy,
+    keys: &[PublicKey],
+    message: &[u8],
+    signatures: &[BundleSignature],
+) -> anyhow::Result<Vec<String>> {
+    let required = policy.required(keys.len())?;
+    let ids: Vec<String> = keys.iter().map(PublicKey::key_id).collect();
+    let mut verified = BTreeSet::new();
+    let mut rejections = Vec::new();
+
+    for sig in signatures {
+        let Some(index) = ids.iter().position(|id| *id == sig.key_id) else {
+            rejections.push(SignatureReport {
+                key_id: sig.key_id.clone(),
+                algorithm: sig.algorithm,
+                rejection: Rejection::UnknownKey,
+            });
+            continue;
+        };
+        let key = &keys[index];
+        let outcome = if sig.algorithm != key.algorithm {
+            Err(Rejection::AlgorithmMismatch { claimed: sig.algorithm })
+        } else {
+            key.verify(message, &sig.signature).map_err(Rejection::Failed)
+        };
+        match outcome {
+            Ok(()) => {
+                verified.insert(index);
+            }
+            Err(rejection) => rejections.push(SignatureReport {
+                key_id: sig.key_id.clone(),
+                algorithm: key.algorithm,
+                rejection,
+            }),
+        }
+    }
+
+    if verified.len() >= required {
+        return Ok(verified.into_iter().map(|index| ids[index].clone()).collect());
+    }
+
+    // A key with a rejected signature is already reported; flag the rest.
+    for (index, key) in keys.iter().enumerate() {
+        let reported = rejections.iter().any(|report| report.key_id == ids[index]);
+        if !verified.contains(&index) && !reported {
+            rejections.push(SignatureReport {
+                key_id: ids[index].clone(),
+                algorithm: key.algorithm,
+                rejection: Rejection::Missing,
+            });
+        }
+    }
+    Err(PolicyError {
+        policy,
+        verified: verified.len(),
+        required,
+        rejections,
+    }
+    .into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::SigningKey;
+
+    fn sign(key: &SigningKey, message: &[u8]) -> BundleSignature {
+        BundleSignature {
+            key_id: key.public_key().key_id(),
+            algorithm: key.algorithm,
+            signature: key.sign(message).unwrap(),
+        }
+    }
+
+    fn policy_error(result: anyhow::Result<Vec<String>>) -> PolicyError {
+        result.unwrap_err().downcast().unwrap()
+    }
+
+    #[test]
+    fn hybrid_require_all_reports_each_failure() {
+        let ed25519 = SigningKey::generate(SignatureAlgorithm::Ed25519).unwrap();
+        let ml_dsa = SigningKey::generate(SignatureAlgorithm::MlDsa65).unwrap();
+        let keys = [ed25519.public_key(), ml_dsa.public_key()];
+        let both = [sign(&ed25519, b"bundle"), sign(&ml_dsa, b"bundle")];
+
+        let verified = enforce(SignaturePolicy::RequireAll, &keys, b"bundle", &both).unwrap();
+        assert_eq!(verified.len(), 2);
+
+        let mut tampered = both.clone();
+        tampered[1].signature = ml_dsa.sign(b"other").unwrap();
+        let err = policy_error(enforce(SignaturePolicy::RequireAll, &keys, b"bundle", &tampered));
+        assert_eq!((err.verified, err.required), (1, 2));
+        assert_eq!(
+            err.rejections,
+            vec![SignatureReport {
+                key_id: keys[1].key_id(),
+                algorithm: SignatureAlgorithm::MlDsa65,
+                rejection: Rejection::Failed(SignatureError::Invalid(SignatureAlgorithm::MlDsa65)),
+            }]
+        );
+        assert!(err.to_string().contains("ml-dsa-65 signature does not verify"), "{err}");
+
+        let err = policy_error(enforce(SignaturePolicy::RequireAll, &keys, b"bundle", &both[..1]));
+        assert_eq!(err.rejections[0].rejection, Rejection::Missing);
+        assert!(enforce(SignaturePolicy::RequireAny, &keys, b"bundle", &both[..1]).is_ok());
+    }
+
+    #[test]
+    fn threshold_counts_distinct_trusted_keys() {
+        let signers: Vec<_> = (0..3)
+            .map(|_| SigningKey::generate(SignatureAlgorithm::Ed25519).unwrap())
+            .collect();
+        let keys: Vec<_> = signers.iter().map(SigningKey::public_key).collect();
+        let stranger = SigningKey::generate(SignatureAlgorithm::Ed25519).unwrap();
+        let policy = SignaturePolicy::Threshold(2);
+
+        // The same key twice plus an untrusted key is still one of two.
+        let sigs = [sign(&signers[0], b"m"), sign(&signers[0], b"m"), sign(&stranger, b"m")];
+        let err = policy_error(enforce(policy, &keys, b"m", &sigs));
+        assert_eq!(err.verified, 1);
+        assert!(err.rejections.iter().any(|r| r.rejection == Rejection::UnknownKey));
+
+        let sigs = [sign(&signers[2], b"m"), sign(&signers[0], b"m")];
+        assert_eq!(enforce(policy, &keys, b"m", &sigs).unwrap().len(), 2);
+
+        assert!(enforce(SignaturePolicy::Threshold(4), &keys, b"m", &sigs).is_err());
+        assert!(enforce(SignaturePolicy::Threshold(0), &keys, b"m", &sigs).is_err());
+    }
+
+    #[test]
+    fn claimed_algorithm_must_match_key() {
+        let key = SigningKey::generate(SignatureAlgorithm::Ed25519).unwrap();
+        let mut sig = sign(&key, b"m");
+        sig.algorithm = SignatureAlgorithm::MlDsa44;
+        let err = policy_error(enforce(SignaturePolicy::RequireAny, &[key.public_key()], b"m", &[sig]));
+        assert_eq!(
+            err.rejections[0].rejection,
+            Rejection::AlgorithmMismatch {
+                claimed: SignatureAlgorithm::MlDsa44
+            }
+        );
+    }
+
+    #[test]
+    fn policy_serde_forms() {
+        assert_eq!(serde_json::to_string(&SignaturePolicy::RequireAll).unwrap(), "\"require-all\"");
+        let parsed: SignaturePolicy = serde_json::from_str(r#"{"threshold": 2}"#).unwrap();
+        assert_eq!(parsed, SignaturePolicy::Threshold(2));
+    }
+}
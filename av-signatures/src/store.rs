@@ -0,0 +1,317 @@
+//! On-disk bundle cache with atomic activation and rollback.
+//!
+//! Every successful update is written as a new generation:
+//!
+//! ```text
+//! <cache_dir>/generations/000007/manifest.json
+//! <cache_dir>/generations/000007/<source>.json
+//! <cache_dir>/generations/000007/rules.json
+//! <cache_dir>/current -> generations/000007
+//! ```
+//!
+//! A generation is staged under a dot-name, fsynced, renamed into place and
+//! only then activated by atomically replacing the `current` symlink, so the
+//! scanner never sees a partial rule set. Older generations are kept for
+//! rollback. Rolling back places a hold on the rejected rule set so the next
+//! update does not immediately reinstall it; any newer release lifts it.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::os::unix::fs::symlink;
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use semver::Version;
+use serde::{Deserialize, Serialize};
+
+use av_core::persist;
+use av_core::signatures::RuleBundle;
+
+use crate::merge::MergedRules;
+
+const GENERATIONS: &str = "generations";
+const CURRENT: &str = "current";
+const MANIFEST: &str = "manifest.json";
+const RULES: &str = "rules.json";
+const HOLD: &str = "hold.json";
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Generation {
+    pub id: u64,
+    pub created_at: DateTime<Utc>,
+    /// Bundle version per source name.
+    pub bundles: BTreeMap<String, Version>,
+    /// URL, the source's own or a mirror's, each downloaded bundle was
+    /// fetched from. Imported bundles have no entry.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub served_by: BTreeMap<String, String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Installed {
+    Activated(Generation),
+    /// The rule set matches one rolled back from; nothing was written.
+    Held,
+    /// Nothing new was published; nothing was written.
+    Unchanged,
+}
+
+pub struct BundleStore {
+    root: PathBuf,
+    keep: usize,
+}
+
+impl BundleStore {
+    /// `keep` is the number of generations retained besides `current`.
+    pub fn new(root: impl Into<PathBuf>, keep: usize) -> Self {
+        Self {
+            root: root.into(),
+            keep,
+        }
+    }
+
+    /// Path of a source's bundle in the active generation.
+    pub fn bundle_path(&self, source: &str) -> PathBuf {
+        self.root.join(CURRENT).join(format!("{source}.json"))
+    }
+
+    /// Path of the merged rule set in the active generation.
+    pub fn rules_path(&self) -> PathBuf {
+        self.root.join(CURRENT).join(RULES)
+    }
+
+    /// The merged rule set of the active generation, if any.
+    pub fn rules(&self) -> anyhow::Result<Option<MergedRules>> {
+        read_json(&self.rules_path())
+    }
+
+    /// A source's bundle in the active generation, if any.
+    pub fn cached(&self, source: &str) -> anyhow::Result<Option<RuleBundle>> {
+        read_json(&self.bundle_path(source))
+    }
+
+    /// Install per-source `bundles` along with the `rules` merged from them,
+    /// recording where each came from.
+    pub fn install(
+        &self,
+        bundles: &[(&str, &RuleBundle)],
+        rules: &MergedRules,
+        served_by: BTreeMap<String, String>,
+        now: DateTime<Utc>,
+    ) -> anyhow::Result<Installed> {
+        for (source, _) in bundles {
+            anyhow::ensure!(is_plain_name(source), "source name `{source}` is not usable as a file name");
+        }
+        let versions: BTreeMap<String, Version> = bundles
+            .iter()
+            .map(|(source, bundle)| (source.to_string(), bundle.version.clone()))
+            .collect();
+        let hold = self.root.join(HOLD);
+        if let Some(held) = read_json::<Generation>(&hold)? {
+            if held.bundles == versions {
+                return Ok(Installed::Held);
+            }
+        }
+
+        let generations = self.root.join(GENERATIONS);
+        fs::create_dir_all(&generations)?;
+        let id = self.generations()?.last().map_or(1, |g| g.id + 1);
+        let generation = Generation {
+            id,
+            created_at: now,
+            bundles: versions,
+            served_by,
+        };
+
+        let staging = generations.join(format!(".staging-{id:06}"));
+        if staging.exists() {
+            fs::remove_dir_all(&staging)?;
+        }
+        fs::create_dir(&staging)?;
+        for (source, bundle) in bundles {
+            persist::write_atomic(&staging.join(format!("{source}.json")), &serde_json::to_vec_pretty(bundle)?)?;
+        }
+        persist::write_atomic(&staging.join(RULES), &serde_json::to_vec_pretty(rules)?)?;
+        persist::write_atomic(&staging.join(MANIFEST), &serde_json::to_vec_pretty(&generation)?)?;
+        persist::commit(&staging, &generations.join(dir_name(id)))?;
+
+        self.activate(id)?;
+        match fs::remove_file(&hold) {
+            Err(err) if err.kind() != ErrorKind::NotFound => return Err(err.into()),
+            _ => {}
+        }
+        self.prune()?;
+        Ok(Installed::Activated(generation))
+    }
+
+    /// Re-activate generation `to`, or the one before the current one.
+    pub fn rollback(&self, to: Option<u64>) -> anyhow::Result<Generation> {
+        let current = self.current()?.context("no active signature generation")?;
+        let generations = self.generations()?;
+        let target = match to {
+            Some(id) => generations.into_iter().find(|g| g.id == id),
+            None => generations.into_iter().rev().find(|g| g.id < current.id),
+        };
+        let target = match (target, to) {
+            (Some(target), _) => target,
+            (None, Some(id)) => anyhow::bail!("no signature generation {id}"),
+            (None, None) => anyhow::bail!("no generation older than {} to roll back to", current.id),
+        };
+        anyhow::ensure!(target.id != current.id, "generation {} is already active", target.id);
+        persist::write_atomic(&self.root.join(HOLD), &serde_json::to_vec_pretty(&current)?)?;
+        self.activate(target.id)?;
+        Ok(target)
+    }
+
+    pub fn current(&self) -> anyhow::Result<Option<Generation>> {
+        read_json(&self.root.join(CURRENT).join(MANIFEST))
+    }
+
+    /// Installed generations, oldest first.
+    pub fn generations(&self) -> anyhow::Result<Vec<Generation>> {
+        let entries = match fs::read_dir(self.root.join(GENERATIONS)) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err.into()),
+        };
+        let mut generations = Vec::new();
+        for entry in entries {
+            let entry = entry?;
+            if entry.file_name().to_string_lossy().starts_with('.') {
+                continue;
+            }
+            if let Some(generation) = read_json::<Generation>(&entry.path().join(MANIFEST))? {
+                generations.push(generation);
+            }
+        }
+        generations.sort_by_key(|g| g.id);
+        Ok(generations)
+    }
+
+    /// Point `current` at generation `id` via symlink-and-rename.
+    fn activate(&self, id: u64) -> anyhow::Result<()> {
+        let link = self.root.join(CURRENT);
+        let tmp = self.root.join(".current.tmp");
+        match fs::remove_file(&tmp) {
+            Err(err) if err.kind() != ErrorKind::NotFound => return Err(err.into()),
+            _ => {}
+        }
+        symlink(Path::new(GENERATIONS).join(dir_name(id)), &tmp)?;
+        persist::commit(&tmp, &link).context("activating signature generation")
+    }
+
+    /// Drop all but the newest `keep` generations besides the active one.
+    fn prune(&self) -> anyhow::Result<()> {
+        let current = self.current()?.map(|g| g.id);
+        let mut others: Vec<u64> = self
+            .generations()?
+            .into_iter()
+            .map(|g| g.id)
+            .filter(|id| Some(*id) != current)
+            .collect();
+        let excess = others.len().saturating_sub(self.keep);
+        for id in others.drain(..excess) {
+            fs::remove_dir_all(self.root.join(GENERATIONS).join(dir_name(id)))?;
+        }
+        Ok(())
+    }
+}
+
+fn dir_name(id: u64) -> String {
+    format!("{id:06}")
+}
+
+fn is_plain_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('.')
+        && !name.contains(['/', '\0'])
+        && ![MANIFEST, RULES].contains(&format!("{name}.json").as_str())
+}
+
+fn read_json<T: serde::de::DeserializeOwned>(path: &Path) -> anyhow::Result<Option<T>> {
+    match fs::read(path) {
+        Ok(bytes) => Ok(Some(
+            serde_json::from_slice(&bytes).with_context(|| format!("parsing {}", path.display()))?,
+        )),
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bundle(version: &str) -> RuleBundle {
+        RuleBundle {
+            version: version.parse().unwrap(),
+            rules: Default::default(),
+            checksum: String::new(),
+            issued_at: "2026-10-01T00:00:00Z".parse().unwrap(),
+            expires_at: "2027-01-01T00:00:00Z".parse().unwrap(),
+        }
+    }
+
+    fn installed_version(store: &BundleStore) -> Version {
+        let bytes = fs::read(store.bundle_path("default")).unwrap();
+        serde_json::from_slice::<RuleBundle>(&bytes).unwrap().version
+    }
+
+    #[test]
+    fn installs_generations_and_prunes() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = BundleStore::new(dir.path(), 2);
+        for minor in 0..5 {
+            let b = bundle(&format!("1.{minor}.0"));
+            let installed = store.install(&[("default", &b)], &MergedRules::default(), BTreeMap::new(), Utc::now()).unwrap();
+            assert!(matches!(installed, Installed::Activated(ref g) if g.id == minor + 1));
+        }
+        assert_eq!(installed_version(&store), "1.4.0".parse().unwrap());
+        let ids: Vec<u64> = store.generations().unwrap().iter().map(|g| g.id).collect();
+        assert_eq!(ids, [3, 4, 5]);
+        assert_eq!(
+            fs::read_link(dir.path().join(CURRENT)).unwrap(),
+            Path::new("generations/000005")
+        );
+    }
+
+    #[test]
+    fn rollback_holds_the_rejected_rule_set() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = BundleStore::new(dir.path(), 3);
+        let (good, bad, fixed) = (bundle("1.0.0"), bundle("1.1.0"), bundle("1.2.0"));
+        store.install(&[("default", &good)], &MergedRules::default(), BTreeMap::new(), Utc::now()).unwrap();
+        store.install(&[("default", &bad)], &MergedRules::default(), BTreeMap::new(), Utc::now()).unwrap();
+
+        assert_eq!(store.rollback(None).unwrap().id, 1);
+        assert_eq!(installed_version(&store), good.version);
+        assert_eq!(store.current().unwrap().unwrap().id, 1);
+        assert!(store.rollback(None).is_err());
+
+        // Polling again offers the same bad bundle: keep the rollback.
+        assert_eq!(store.install(&[("default", &bad)], &MergedRules::default(), BTreeMap::new(), Utc::now()).unwrap(), Installed::Held);
+        assert_eq!(installed_version(&store), good.version);
+
+        let installed = store.install(&[("default", &fixed)], &MergedRules::default(), BTreeMap::new(), Utc::now()).unwrap();
+        assert!(matches!(installed, Installed::Activated(ref g) if g.id == 3));
+        assert_eq!(installed_version(&store), fixed.version);
+
+        // Explicit targets, including rolling forward again.
+        assert_eq!(store.rollback(Some(2)).unwrap().id, 2);
+        assert!(store.rollback(Some(2)).is_err());
+        assert!(store.rollback(Some(9)).is_err());
+    }
+
+    #[test]
+    fn rejects_unsafe_source_names() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = BundleStore::new(dir.path(), 1);
+        for name in ["../etc", "rules", "manifest"] {
+            assert!(store.install(&[(name, &bundle("1.0.0"))], &MergedRules::default(), BTreeMap::new(), Utc::now()).is_err());
+        }
+        assert_eq!(store.current().unwrap(), None);
+    }
+}
@@ -0,0 +1,385 @@
+//! TUF-style role separation and key rotation.
+//!
+//! Instead of one long-lived signing key per source, trust is split across
+//! four roles, each with its own keys and signature threshold:
+//!
+//! * `root` lists every role's keys and thresholds. Its keys stay offline
+//!   and only sign new root versions.
+//! * `targets` lists the hash and length of each downloadable file.
+//! * `snapshot` pins the current `targets` version and hash.
+//! * `timestamp` pins the current `snapshot`; it is re-signed often and
+//!   expires quickly, which bounds how long a freeze can go unnoticed.
+//!
+//! The client starts from a pinned root and follows `N.root.json` one
+//! version at a time; each new root must be signed by a threshold of both
+//! the previous root's keys and its own, so a compromised non-root key is
+//! rotated out by publishing the next root. Every document is an
+//! [`Envelope`] whose payload type names its role.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::crypto::PublicKey;
+use crate::envelope::Envelope;
+use crate::policy::SignaturePolicy;
+
+/// Target name of the rule bundle in a TUF repository.
+pub const RULE_BUNDLE_TARGET: &str = "rules.json";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Root,
+    Targets,
+    Snapshot,
+    Timestamp,
+}
+
+impl Role {
+    pub fn payload_type(self) -> &'static str {
+        match self {
+            Self::Root => "application/vnd.charmedwoa.tuf.root+json",
+            Self::Targets => "application/vnd.charmedwoa.tuf.targets+json",
+            Self::Snapshot => "application/vnd.charmedwoa.tuf.snapshot+json",
+            Self::Timestamp => "application/vnd.charmedwoa.tuf.timestamp+json",
+        }
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Root => "root",
+            Self::Targets => "targets",
+            Self::Snapshot => "snapshot",
+            Self::Timestamp => "timestamp",
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RoleKeys {
+    pub key_ids: Vec<String>,
+    pub threshold: usize,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Root {
+    pub version: u64,
+    pub expires: DateTime<Utc>,
+    /// Keys by [`PublicKey::key_id`].
+    pub keys: BTreeMap<String, PublicKey>,
+    pub roles: BTreeMap<Role, RoleKeys>,
+}
+
+impl Root {
+    fn validate(&self) -> anyhow::Result<()> {
+        for (id, key) in &self.keys {
+            anyhow::ensure!(*id == key.key_id(), "root lists key {id} under the wrong id");
+        }
+        for role in [Role::Root, Role::Targets, Role::Snapshot, Role::Timestamp] {
+            let (keys, threshold) = self.role_keys(role)?;
+            SignaturePolicy::Threshold(threshold)
+                .required(keys.len())
+                .with_context(|| format!("{role} role"))?;
+        }
+        Ok(())
+    }
+
+    fn role_keys(&self, role: Role) -> anyhow::Result<(Vec<PublicKey>, usize)> {
+        let entry = self.roles.get(&role).with_context(|| format!("root does not define the {role} role"))?;
+        let keys = entry
+            .key_ids
+            .iter()
+            .map(|id| {
+                self.keys
+                    .get(id)
+                    .cloned()
+                    .with_context(|| format!("{role} role names unknown key {id}"))
+            })
+            .collect::<anyhow::Result<_>>()?;
+        Ok((keys, entry.threshold))
+    }
+
+    /// Check `envelope` carries a threshold of `role` signatures and parse it.
+    fn verify<T: DeserializeOwned>(&self, role: Role, envelope: &Envelope) -> anyhow::Result<T> {
+        let (keys, threshold) = self.role_keys(role)?;
+        let payload = envelope.verify(role.payload_type(), SignaturePolicy::Threshold(threshold), &keys)?;
+        serde_json::from_slice(payload).with_context(|| format!("malformed {role} metadata"))
+    }
+}
+
+/// Version, length and hash of another metadata file or target.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MetaInfo {
+    pub version: u64,
+    pub length: u64,
+    pub sha256: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Timestamp {
+    pub version: u64,
+    pub expires: DateTime<Utc>,
+    pub snapshot: MetaInfo,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub version: u64,
+    pub expires: DateTime<Utc>,
+    pub targets: MetaInfo,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TargetInfo {
+    pub length: u64,
+    pub sha256: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Targets {
+    pub version: u64,
+    pub expires: DateTime<Utc>,
+    pub targets: BTreeMap<String, TargetInfo>,
+}
+
+/// Metadata the client currently trusts. Persisted between updates so the
+/// version checks also hold across restarts.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TrustedMetadata {
+    pub root: Root,
+    pub timestamp: Option<Timestamp>,
+    pub snapshot: Option<Snapshot>,
+    pub targets: Option<Targets>,
+}
+
+impl TrustedMetadata {
+    /// Start from a pinned root shipped with the client. It must be signed
+    /// by its own root threshold; expiry is checked once the chain has been
+    /// followed, since a newer root may have been published since.
+    pub fn from_pinned_root(bytes: &[u8]) -> anyhow::Result<Self> {
+        let envelope = parse_envelope(Role::Root, bytes)?;
+        let unverified: Root = serde_json::from_slice(&envelope.payload).context("malformed root metadata")?;
+        unverified.validate()?;
+        let root = unverified.verify(Role::Root, &envelope).context("pinned root")?;
+        Ok(Self {
+            root,
+            timestamp: None,
+            snapshot: None,
+            targets: None,
+        })
+    }
+
+    /// Rotate to the next root version.
+    pub fn update_root(&mut self, bytes: &[u8]) -> anyhow::Result<()> {
+        let envelope = parse_envelope(Role::Root, bytes)?;
+        let next: Root = self
+            .root
+            .verify(Role::Root, &envelope)
+            .with_context(|| format!("root v{} signing keys", self.root.version))?;
+        next.validate()?;
+        next.verify::<Root>(Role::Root, &envelope)
+            .with_context(|| format!("root v{} signing keys", next.version))?;
+        anyhow::ensure!(
+            next.version == self.root.version + 1,
+            "root rotation from v{} to v{} skips or repeats a version",
+            self.root.version,
+            next.version
+        );
+        // A rotated timestamp or snapshot key may have been the reason for
+        // the rotation; forget what it signed (TUF 5.3.11).
+        let rotated = [Role::Timestamp, Role::Snapshot]
+            .iter()
+            .any(|role| self.root.roles.get(role) != next.roles.get(role));
+        if rotated {
+            self.timestamp = None;
+            self.snapshot = None;
+        }
+        self.root = next;
+        Ok(())
+    }
+
+    pub fn update_timestamp(&mut self, bytes: &[u8], now: DateTime<Utc>) -> anyhow::Result<()> {
+        check_not_expired(Role::Root, self.root.expires, now)?;
+        let envelope = parse_envelope(Role::Timestamp, bytes)?;
+        let timestamp: Timestamp = self.root.verify(Role::Timestamp, &envelope)?;
+        if let Some(trusted) = &self.timestamp {
+            check_not_older(Role::Timestamp, trusted.version, timestamp.version)?;
+            check_not_older(Role::Snapshot, trusted.snapshot.version, timestamp.snapshot.version)?;
+        }
+        check_not_expired(Role::Timestamp, timestamp.expires, now)?;
+        self.timestamp = Some(timestamp);
+        Ok(())
+    }
+
+    pub fn update_snapshot(&mut self, bytes: &[u8], now: DateTime<Utc>) -> anyhow::Result<()> {
+        let expected = &self.timestamp.as_ref().context("timestamp not loaded")?.snapshot;
+        check_meta(Role::Snapshot, expected, bytes)?;
+        let envelope = parse_envelope(Role::Snapshot, bytes)?;
+        let snapshot: Snapshot = self.root.verify(Role::Snapshot, &envelope)?;
+        anyhow::ensure!(
+            snapshot.version == expected.version,
+            "snapshot v{} does not match timestamp's v{}",
+            snapshot.version,
+            expected.version
+        );
+        if let Some(trusted) = &self.snapshot {
+            check_not_older(Role::Targets, trusted.targets.version, snapshot.targets.version)?;
+        }
+        check_not_expired(Role::Snapshot, snapshot.expires, now)?;
+        self.snapshot = Some(snapshot);
+        Ok(())
+    }
+
+    pub fn update_targets(&mut self, bytes: &[u8], now: DateTime<Utc>) -> anyhow::Result<()> {
+        let expected = &self.snapshot.as_ref().context("snapshot not loaded")?.targets;
+        check_meta(Role::Targets, expected, bytes)?;
+        let envelope = parse_envelope(Role::Targets, bytes)?;
+        let targets: Targets = self.root.verify(Role::Targets, &envelope)?;
+        anyhow::ensure!(
+            targets.version == expected.version,
+            "targets v{} does not match snapshot's v{}",
+            targets.version,
+            expected.version
+        );
+        check_not_expired(Role::Targets, targets.expires, now)?;
+        self.targets = Some(targets);
+        Ok(())
+    }
+
+    /// Check downloaded target bytes against the trusted targets metadata.
+    pub fn verify_target(&self, name: &str, bytes: &[u8]) -> anyhow::Result<()> {
+        let targets = self.targets.as_ref().context("targets not loaded")?;
+        let info = targets.targets.get(name).with_context(|| format!("no target named `{name}`"))?;
+        anyhow::ensure!(
+            bytes.len() as u64 == info.length && sha256_hex(bytes) == info.sha256,
+            "target `{name}` does not match its signed length and hash"
+        );
+        Ok(())
+    }
+}
+
+fn parse_envelope(role: Role, bytes: &[u8]) -> anyhow::Result<Envelope> {
+    serde_json::from_slice(bytes).with_context(|| format!("malformed {role} envelope"))
+}
+
+fn check_meta(role: Role, expected: &MetaInfo, bytes: &[u8]) -> anyhow::Result<()> {
+    anyhow::ensure!(
+        bytes.len() as u64 == expected.length && sha256_hex(bytes) == expected.sha256,
+        "{role} metadata does not match the signed length and hash"
+    );
+    Ok(())
+}
+
+fn check_not_older(role: Role, trusted: u64, offered: u64) -> anyhow::Result<()> {
+    anyhow::ensure!(
+        offered >= trusted,
+        "{role} rollback refused: offered v{offered}, trusted v{trusted}"
+    );
+    Ok(())
+}
+
+fn check_not_expired(role: Role, expires: DateTime<Utc>, now: DateTime<Utc>) -> anyhow::Result<()> {
+    anyhow::ensure!(expires > now, "{role} metadata expired at {expires}");
+    Ok(())
+}
+
+pub fn sha256_hex(bytes: &[u8]) -> String {
+    hex::encode(Sha256::digest(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: &str = "2026-10-18T12:00:00Z";
+
+    fn fixture(name: &str) -> Vec<u8> {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/tuf/");
+        std::fs::read(format!("{path}{name}")).unwrap()
+    }
+
+    fn now() -> DateTime<Utc> {
+        NOW.parse().unwrap()
+    }
+
+    fn load_repository(trusted: &mut TrustedMetadata, now: DateTime<Utc>) -> anyhow::Result<()> {
+        trusted.update_timestamp(&fixture("timestamp.json"), now)?;
+        trusted.update_snapshot(&fixture("snapshot.json"), now)?;
+        trusted.update_targets(&fixture("targets.json"), now)
+    }
+
+    #[test]
+    fn follows_root_rotation_to_verified_target() {
+        let mut trusted = TrustedMetadata::from_pinned_root(&fixture("1.root.json")).unwrap();
+        trusted.update_root(&fixture("2.root.json")).unwrap();
+        assert_eq!(trusted.root.version, 2);
+        load_repository(&mut trusted, now()).unwrap();
+
+        let bundle = fixture(RULE_BUNDLE_TARGET);
+        trusted.verify_target(RULE_BUNDLE_TARGET, &bundle).unwrap();
+        let mut tampered = bundle.clone();
+        tampered[10] ^= 1;
+        assert!(trusted.verify_target(RULE_BUNDLE_TARGET, &tampered).is_err());
+    }
+
+    #[test]
+    fn rotated_out_targets_key_is_refused() {
+        // Version 1 of the root still trusts the old targets key.
+        let v1 = TrustedMetadata::from_pinned_root(&fixture("1.root.json")).unwrap();
+        let (old_keys, _) = v1.root.role_keys(Role::Targets).unwrap();
+        let envelope = parse_envelope(Role::Targets, &fixture("targets.revoked-key.json")).unwrap();
+        assert_eq!(envelope.signatures[0].key_id, old_keys[0].key_id());
+        v1.root.verify::<Targets>(Role::Targets, &envelope).unwrap();
+
+        let mut v2 = v1.clone();
+        v2.update_root(&fixture("2.root.json")).unwrap();
+        let err = v2.root.verify::<Targets>(Role::Targets, &envelope).unwrap_err();
+        assert!(err.to_string().contains("not trusted"), "{err:#}");
+    }
+
+    #[test]
+    fn new_root_must_be_signed_by_previous_root() {
+        let mut trusted = TrustedMetadata::from_pinned_root(&fixture("1.root.json")).unwrap();
+        let err = trusted.update_root(&fixture("2.root.unchained.json")).unwrap_err();
+        assert!(format!("{err:#}").contains("root v1 signing keys"), "{err:#}");
+
+        // Nor may a root be replayed or skipped.
+        trusted.update_root(&fixture("2.root.json")).unwrap();
+        assert!(trusted.update_root(&fixture("2.root.json")).is_err());
+        assert_eq!(trusted.root.version, 2);
+    }
+
+    #[test]
+    fn pinned_root_is_not_trusted_for_other_roles() {
+        // The offline root key signs nothing but roots.
+        let trusted = TrustedMetadata::from_pinned_root(&fixture("1.root.json")).unwrap();
+        let root_envelope = parse_envelope(Role::Root, &fixture("1.root.json")).unwrap();
+        let mut as_targets = root_envelope.clone();
+        as_targets.payload_type = Role::Targets.payload_type().into();
+        assert!(trusted.root.verify::<Targets>(Role::Targets, &as_targets).is_err());
+    }
+
+    #[test]
+    fn expired_and_replayed_metadata_is_refused() {
+        let mut trusted = TrustedMetadata::from_pinned_root(&fixture("1.root.json")).unwrap();
+        trusted.update_root(&fixture("2.root.json")).unwrap();
+
+        let late: DateTime<Utc> = "2026-11-01T00:00:00Z".parse().unwrap();
+        let err = load_repository(&mut trusted, late).unwrap_err();
+        assert!(err.to_string().contains("timestamp metadata expired"), "{err:#}");
+
+        load_repository(&mut trusted, now()).unwrap();
+        let mut newer = trusted.clone();
+        newer.timestamp.as_mut().unwrap().version += 1;
+        let err = newer.update_timestamp(&fixture("timestamp.json"), now()).unwrap_err();
+        assert!(err.to_string().contains("timestamp rollback refused"), "{err:#}");
+    }
+}
@@ -16,6 +16,7 @@ mkdir -p "$DEB_ROOT/DEBIAN"
 mkdir -p "$DEB_ROOT/usr/lib/$PACKAGE_NAME"
 mkdir -p "$DEB_ROOT/etc/$PACKAGE_NAME"
 mkdir -p "$DEB_ROOT/var/lib/av/quarantine"
+mkdir -p "$DEB_ROOT/var/lib/av/state"
 mkdir -p "$DEB_ROOT/var/log/$PACKAGE_NAME"
 mkdir -p "$DEB_ROOT/usr/lib/systemd/system"
 mkdir -p "$DEB_ROOT/usr/share/apparmor"
@@ -36,7 +37,9 @@ cp target/$TARGET/release/av-cli "$DEB_ROOT/usr/lib/$PACKAGE_NAME/"
 cp systemd/av-daemon.service "$DEB_ROOT/usr/lib/systemd/system/"
 cp policies/apparmor/av-daemon.apparmor "$DEB_ROOT/usr/share/apparmor/"
 cp policies/seccomp/av-daemon.json "$DEB_ROOT/etc/$PACKAGE_NAME/"
+cp policies/seccomp/av-scan-worker.json "$DEB_ROOT/etc/$PACKAGE_NAME/"
 cp config/daemon.toml "$DEB_ROOT/etc/$PACKAGE_NAME/"
+cp config/signatures.toml "$DEB_ROOT/etc/$PACKAGE_NAME/"
 
 cat > "$DEB_ROOT/DEBIAN/postinst" <<'POSTINST'
 #!/bin/sh
@@ -22,9 +22,9 @@ sudo systemctl stop av-daemon.service || true
 sudo systemctl disable av-daemon.service || true
 
 if ! $PRESERVE_QUARANTINE; then
-  read -p "Purge quarantine directory? [y/N] " confirm
+  read -p "Purge quarantine directory and its keys? [y/N] " confirm
   if [[ "$confirm" =~ ^[Yy]$ ]]; then
-    sudo rm -rf /var/lib/av/quarantine
+    sudo rm -rf /var/lib/av/quarantine /var/lib/av/quarantine-keys
   fi
 fi
 